@@ -0,0 +1,79 @@
+//! Small-message encode and decode throughput against rmpv, the usual
+//! point of comparison: `cargo bench --bench small_messages`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use stuff::{decode_from_slice, encode_to, msgpack, Value};
+
+/// A typical telemetry record: short keys, small integers, short strings.
+fn message(i: i64) -> Value {
+    msgpack!({
+        "ts": 1_700_000_000 + i,
+        "host": "web-01",
+        "cpu": 0.53,
+        "mem": 812_345,
+        "ok": true,
+        "tags": ["prod", "eu-west", i],
+        "code": -3
+    })
+}
+
+fn to_rmpv(v: &Value) -> rmpv::Value {
+    match v {
+        Value::Null => rmpv::Value::Nil,
+        Value::Boolean(b) => rmpv::Value::Boolean(*b),
+        Value::Integer(i) => rmpv::Value::from(*i as i64),
+        Value::Float(f) => rmpv::Value::F64(*f),
+        Value::Array(a) => rmpv::Value::Array(a.iter().map(to_rmpv).collect()),
+        Value::Map(m) => rmpv::Value::Map(m.iter().map(|(k, v)| (to_rmpv(k), to_rmpv(v))).collect()),
+        v => rmpv::Value::from(v.as_str().unwrap_or_default())
+    }
+}
+
+fn encode(c: &mut Criterion) {
+    let values: Vec<Value> = (0..100).map(message).collect();
+    let theirs: Vec<rmpv::Value> = values.iter().map(to_rmpv).collect();
+    let mut buf = Vec::with_capacity(1 << 16);
+    c.bench_function("encode/stuff", |b| {
+        b.iter(|| {
+            buf.clear();
+            for v in &values {
+                let _ = encode_to(&mut buf, black_box(v));
+            }
+        })
+    });
+    c.bench_function("encode/rmpv", |b| {
+        b.iter(|| {
+            buf.clear();
+            for v in &theirs {
+                let _ = rmpv::encode::write_value(&mut buf, black_box(v));
+            }
+        })
+    });
+}
+
+fn decode(c: &mut Criterion) {
+    let mut buf = Vec::new();
+    for i in 0..100 {
+        let _ = encode_to(&mut buf, &message(i));
+    }
+    c.bench_function("decode/stuff", |b| {
+        b.iter(|| {
+            let mut rest = black_box(&buf[..]);
+            while let Ok((v, len)) = decode_from_slice(rest) {
+                black_box(v);
+                rest = &rest[len..];
+            }
+        })
+    });
+    c.bench_function("decode/rmpv", |b| {
+        b.iter(|| {
+            let mut rest = black_box(&buf[..]);
+            while let Ok(v) = rmpv::decode::read_value(&mut rest) {
+                black_box(v);
+            }
+        })
+    });
+}
+
+criterion_group!(benches, encode, decode);
+criterion_main!(benches);
@@ -0,0 +1,105 @@
+use std::ops::Range;
+
+use crate::validate::{header_kind, read_header, skip_len, Header};
+use crate::{decode_from_slice, Document, Error, Result, Value};
+
+/// Byte offsets of every element of an encoded top-level array, found in
+/// a single scan so any element can then be reached directly. Elements are
+/// only checked for structure while indexing; strings and everything else
+/// are left alone until an element is actually decoded.
+#[derive(Debug, Clone)]
+pub struct ArrayIndex<'a> {
+    buf: &'a [u8],
+    /// Start of each element, followed by the end of the last one.
+    offsets: Vec<usize>
+}
+
+impl<'a> ArrayIndex<'a> {
+    /// Scans the array at the front of `buf`. Fails if it isn't an array or
+    /// is truncated or malformed; bytes after the array are ignored.
+    pub fn build(buf: &'a [u8]) -> Result<ArrayIndex<'a>> {
+        let (len, mut pos) = match read_header(buf)? {
+            (Header::Array(len), n) => (len, n),
+            (header, _) => return Err(Error::TypeMismatch { expected: "array", found: header_kind(header, buf) })
+        };
+        // Every element takes at least a byte, which bounds what a lying
+        // header can make us reserve.
+        let mut offsets = Vec::with_capacity(len.min(buf.len() - pos) + 1);
+        for _ in 0..len {
+            offsets.push(pos);
+            pos += skip_len(&buf[pos..])?;
+        }
+        offsets.push(pos);
+        Ok(ArrayIndex { buf, offsets })
+    }
+
+    pub fn len(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The encoded length of the whole array, header included.
+    pub fn encoded_len(&self) -> usize {
+        self.offsets[self.offsets.len() - 1]
+    }
+
+    /// Where element `index` lies in the buffer.
+    pub fn range(&self, index: usize) -> Option<Range<usize>> {
+        Some(*self.offsets.get(index)?..*self.offsets.get(index + 1)?)
+    }
+
+    /// The encoded bytes of element `index`.
+    pub fn encoded(&self, index: usize) -> Option<&'a [u8]> {
+        self.range(index).map(|range| &self.buf[range])
+    }
+
+    /// A lazy view of element `index`.
+    pub fn get(&self, index: usize) -> Option<Document<'a>> {
+        self.encoded(index).map(Document::new)
+    }
+
+    /// Decodes element `index` alone.
+    pub fn decode(&self, index: usize) -> Option<Result<Value>> {
+        self.encoded(index).map(|buf| decode_from_slice(buf).map(|(v, _)| v))
+    }
+
+    /// The encoded bytes of each element in order.
+    pub fn iter(&self) -> impl Iterator<Item = &'a [u8]> + '_ {
+        self.offsets.windows(2).map(move |w| &self.buf[w[0]..w[1]])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{enc, ok};
+
+    #[test]
+    fn indexes_every_element() {
+        let items: Vec<Value> = (0..1000).map(|i| msgpack!({"id": i, "name": format!("item{}", i)})).collect();
+        let buf = enc(&Value::Array(items.clone()));
+        let index = ok(ArrayIndex::build(&buf));
+        assert_eq!(index.len(), 1000);
+        assert_eq!(index.encoded_len(), buf.len());
+        assert_eq!(index.decode(737).and_then(Result::ok), Some(items[737].clone()));
+        assert_eq!(index.get(999).and_then(|d| d.get("name")).and_then(|d| d.as_str()), Some("item999"));
+        assert!(index.get(1000).is_none());
+        assert_eq!(index.iter().count(), 1000);
+        assert_eq!(index.iter().nth(5), index.encoded(5));
+        assert_eq!(index.range(0).map(|r| r.start), Some(3));
+    }
+
+    #[test]
+    fn rejects_non_arrays_and_truncation() {
+        let buf = enc(&msgpack!({"a": 1}));
+        assert!(matches!(ArrayIndex::build(&buf), Err(Error::TypeMismatch { expected: "array", found: "map" })));
+        assert!(matches!(ArrayIndex::build(&[0x07]), Err(Error::TypeMismatch { found: "integer", .. })));
+        // Claims 2^32 - 1 elements but holds one.
+        let buf = [0xdd, 0xff, 0xff, 0xff, 0xff, 0x01];
+        assert!(matches!(ArrayIndex::build(&buf), Err(Error::UnexpectedEof { .. })));
+        assert!(ArrayIndex::build(&[0x90]).map(|i| i.is_empty()).unwrap_or_default());
+    }
+}
@@ -0,0 +1,102 @@
+//! Serde helpers writing byte containers as msgpack bin rather than as
+//! arrays of integers, for use with `#[serde(with = "stuff::bin")]`. Unlike
+//! serde_bytes, this also covers fixed-size arrays such as `[u8; 32]`.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use serde::Serializer;
+
+pub fn serialize<T: AsRef<[u8]> + ?Sized, S: Serializer>(bytes: &T, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_bytes(bytes.as_ref())
+}
+
+/// Accepts bin, str or an array of integers; a fixed-size array also
+/// requires the length to match.
+pub fn deserialize<'de, T: TryFrom<Vec<u8>>, D: Deserializer<'de>>(d: D) -> Result<T, D::Error> {
+    d.deserialize_byte_buf(BinVisitor(PhantomData))
+}
+
+struct BinVisitor<T>(PhantomData<T>);
+
+impl<T: TryFrom<Vec<u8>>> BinVisitor<T> {
+    fn convert<E: de::Error>(b: Vec<u8>) -> Result<T, E> {
+        let len = b.len();
+        T::try_from(b).map_err(|_| E::invalid_length(len, &"a byte array of the expected length"))
+    }
+}
+
+impl<'de, T: TryFrom<Vec<u8>>> Visitor<'de> for BinVisitor<T> {
+    type Value = T;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "bytes")
+    }
+
+    fn visit_bytes<E: de::Error>(self, b: &[u8]) -> Result<T, E> {
+        BinVisitor::convert(b.to_vec())
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, b: Vec<u8>) -> Result<T, E> {
+        BinVisitor::convert(b)
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<T, E> {
+        BinVisitor::convert(s.as_bytes().to_vec())
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<T, A::Error> {
+        let mut b = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+        while let Some(byte) = seq.next_element()? {
+            b.push(byte);
+        }
+        BinVisitor::convert(b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use serde_bytes::ByteBuf;
+
+    use crate::test_util::{ok, ser};
+    use crate::{from_slice, from_value, to_value, Error, Value};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Blobs {
+        #[serde(with = "serde_bytes")]
+        body: Vec<u8>,
+        buf: ByteBuf,
+        #[serde(with = "crate::bin")]
+        digest: [u8; 4],
+        plain: Vec<u8>
+    }
+
+    #[test]
+    fn writes_and_reads_bin() {
+        let blobs = Blobs {
+            body: vec![1, 2],
+            buf: ByteBuf::from(vec![3]),
+            digest: [4, 5, 6, 7],
+            plain: vec![8]
+        };
+        let buf = ser(&blobs);
+        assert_eq!(&buf[..11], &[0x94, 0xc4, 0x02, 1, 2, 0xc4, 0x01, 3, 0xc4, 0x04, 4]);
+        assert_eq!(from_slice::<Blobs>(&buf).ok().as_ref(), Some(&blobs));
+        let v = ok(to_value(&blobs));
+        assert_eq!(v, msgpack!([(vec![1u8, 2]), (vec![3u8]), (vec![4u8, 5, 6, 7]), [8]]));
+        assert_eq!(from_value::<Blobs>(v).ok(), Some(blobs));
+    }
+
+    #[test]
+    fn plain_byte_vectors_accept_bin() {
+        let bin = Value::Bytes(vec![1, 2, 3]);
+        assert_eq!(from_value::<Vec<u8>>(bin.clone()).ok(), Some(vec![1, 2, 3]));
+        assert_eq!(from_value::<(u8, u8, u8)>(bin.clone()).ok(), Some((1, 2, 3)));
+        assert_eq!(from_slice::<Vec<u8>>(&[0xc4, 0x02, 9, 8]).ok(), Some(vec![9, 8]));
+        assert_eq!(from_slice::<[u8; 2]>(&[0xc4, 0x02, 9, 8]).ok(), Some([9, 8]));
+        assert!(matches!(from_slice::<[u8; 3]>(&[0xc4, 0x02, 9, 8]), Err(Error::Custom(_))));
+    }
+}
@@ -0,0 +1,67 @@
+use bytes::{Buf, BufMut};
+
+use crate::{decode_buf_with, encode_with, DecodeOptions, EncodeOptions, Result, Value};
+
+/// Encodes `value` straight into `buf`, e.g. a `BytesMut` headed for a
+/// socket, without an intermediate `Vec`. A buffer that can't grow fails
+/// with `Error::Io` once full, having taken part of the value.
+pub fn put_value<B: BufMut + ?Sized>(buf: &mut B, value: &Value) -> Result<()> {
+    put_value_with(buf, value, &EncodeOptions::default())
+}
+
+pub fn put_value_with<B: BufMut + ?Sized>(buf: &mut B, value: &Value, opts: &EncodeOptions) -> Result<()> {
+    encode_with(&mut buf.writer(), value, opts)
+}
+
+/// Decodes one value from the front of `buf`, parsing directly out of its
+/// chunks. Only the bytes of the value are consumed, so further values can
+/// be read from the same `Bytes` or chain of buffers.
+pub fn get_value<B: Buf + ?Sized>(buf: &mut B) -> Result<Value> {
+    get_value_with(buf, &DecodeOptions::default())
+}
+
+pub fn get_value_with<B: Buf + ?Sized>(buf: &mut B, opts: &DecodeOptions) -> Result<Value> {
+    decode_buf_with(&mut buf.reader(), opts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::enc;
+    use crate::Error;
+    use bytes::{Bytes, BytesMut};
+
+    #[test]
+    fn round_trips_through_bytes() {
+        let v = msgpack!({"id": 7, "tags": ["a", "b"], "blob": Value::Bytes(vec![1; 300])});
+        let mut out = BytesMut::new();
+        assert!(put_value(&mut out, &v).is_ok());
+        assert!(put_value(&mut out, &msgpack!(42)).is_ok());
+        let mut frozen: Bytes = out.freeze();
+        assert_eq!(get_value(&mut frozen).ok(), Some(v));
+        assert_eq!(get_value(&mut frozen).ok(), Some(msgpack!(42)));
+        assert!(!frozen.has_remaining());
+    }
+
+    #[test]
+    fn reads_across_chunks() {
+        let v = msgpack!(["split across", "two chunks", 123456]);
+        let buf = enc(&v);
+        let (a, b) = buf.split_at(7);
+        let mut chain = Bytes::copy_from_slice(a).chain(Bytes::copy_from_slice(b));
+        assert_eq!(get_value(&mut chain).ok(), Some(v));
+        let mut short = Bytes::copy_from_slice(&buf[..buf.len() - 1]);
+        assert!(matches!(get_value(&mut short).map_err(Error::into_root), Err(Error::UnexpectedEof { .. })));
+    }
+
+    #[test]
+    fn fixed_buffers_fail_when_full() {
+        let mut storage = [0u8; 4];
+        let mut slice = &mut storage[..];
+        assert!(matches!(put_value(&mut slice, &msgpack!("too long to fit")), Err(Error::Io(_))));
+        let mut storage = [0u8; 4];
+        let mut slice = &mut storage[..];
+        assert!(put_value(&mut slice, &msgpack!([1, 2, 3])).is_ok());
+        assert_eq!(storage, [0x93, 0x01, 0x02, 0x03]);
+    }
+}
@@ -0,0 +1,90 @@
+use crate::{Map, Value};
+
+/// Builds a `Value::Map` one entry at a time, for documents whose keys
+/// aren't known until runtime. Start one with `Value::map()`.
+#[derive(Debug, Default)]
+pub struct MapBuilder {
+    map: Map
+}
+
+impl MapBuilder {
+    pub fn new() -> MapBuilder {
+        MapBuilder::default()
+    }
+
+    /// Adds an entry, replacing any earlier entry with the same key.
+    pub fn entry<K: Into<Value>, V: Into<Value>>(mut self, key: K, value: V) -> MapBuilder {
+        self.map.insert(key.into(), value.into());
+        self
+    }
+
+    pub fn build(self) -> Value {
+        Value::Map(self.map)
+    }
+}
+
+/// Builds a `Value::Array` one element at a time. Start one with
+/// `Value::array()`.
+#[derive(Debug, Default)]
+pub struct ArrayBuilder {
+    items: Vec<Value>
+}
+
+impl ArrayBuilder {
+    pub fn new() -> ArrayBuilder {
+        ArrayBuilder::default()
+    }
+
+    pub fn push<V: Into<Value>>(mut self, value: V) -> ArrayBuilder {
+        self.items.push(value.into());
+        self
+    }
+
+    pub fn build(self) -> Value {
+        Value::Array(self.items)
+    }
+}
+
+/// Nested builders can be passed as values without calling `build`.
+impl From<MapBuilder> for Value {
+    fn from(b: MapBuilder) -> Value {
+        b.build()
+    }
+}
+
+impl From<ArrayBuilder> for Value {
+    fn from(b: ArrayBuilder) -> Value {
+        b.build()
+    }
+}
+
+impl Value {
+    pub fn map() -> MapBuilder {
+        MapBuilder::new()
+    }
+
+    pub fn array() -> ArrayBuilder {
+        ArrayBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_documents() {
+        let keys = ["x", "y"];
+        let mut coords = Value::map();
+        for (i, k) in keys.iter().enumerate() {
+            coords = coords.entry(*k, i as u32);
+        }
+        let v = Value::map()
+            .entry("a", 1)
+            .entry("b", [1, 2])
+            .entry("coords", coords)
+            .entry("list", Value::array().push("s").push(Value::Null))
+            .build();
+        assert_eq!(v, msgpack!({"a": 1, "b": [1, 2], "coords": {"x": 0, "y": 1}, "list": ["s", null]}));
+    }
+}
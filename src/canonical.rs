@@ -0,0 +1,81 @@
+use crate::Value;
+
+impl Value {
+    /// Rewrites this value into a canonical form: every NaN gets the bit
+    /// pattern of `f64::NAN`, `-0.0` becomes `0.0` and fixed-width integers
+    /// become plain integers, recursively through arrays, maps and keys.
+    /// Keys that become equal are collapsed to a single entry, and map
+    /// entries are put in key order, as `Ord` for `Value` sorts them.
+    ///
+    /// Under `preserve_order` and `btree_map` equal documents then encode
+    /// identically. The default hashed map keeps no order, so encode with
+    /// `EncodeOptions::sort_keys` there.
+    pub fn canonicalize(&mut self) {
+        match self {
+            Value::Float(f) if f.is_nan() => *f = f64::NAN,
+            Value::Float(f) if *f == 0.0 => *f = 0.0,
+            Value::FixedInteger(i, _) => *self = Value::Integer(*i),
+            Value::Array(a) => a.iter_mut().for_each(Value::canonicalize),
+            Value::Map(m) => {
+                let mut entries: Vec<(Value, Value)> = std::mem::take(m).into_iter().map(|(mut k, mut v)| {
+                    k.canonicalize();
+                    v.canonicalize();
+                    (k, v)
+                }).collect();
+                entries.sort_by(|(k, _), (k2, _)| k.cmp(k2));
+                *m = entries.into_iter().collect();
+            },
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::enc_with;
+    use crate::{EncodeOptions, IntFormat, Value};
+
+    fn canonical_bytes(mut v: Value) -> Vec<u8> {
+        v.canonicalize();
+        enc_with(&v, &EncodeOptions { sort_keys: true, ..Default::default() })
+    }
+
+    #[test]
+    fn normalizes_recursively() {
+        let mut v = Value::Array(vec![
+            Value::Float(-0.0),
+            Value::Float(f64::from_bits(0x7ff8_0000_0000_0001)),
+            Value::FixedInteger(7, IntFormat::U32),
+            msgpack!({"k": [-0.0]})
+        ]);
+        v.canonicalize();
+        if let Value::Array(a) = &v {
+            assert_eq!(a[0].to_string(), "0.0");
+            assert_eq!(a[1], Value::Float(f64::NAN));
+            assert!(matches!(a[2], Value::Integer(7)));
+            assert_eq!(a[3]["k"][0], Value::Float(0.0));
+        }
+    }
+
+    #[test]
+    fn equal_documents_encode_identically() {
+        let mut a = Value::map();
+        let mut b = Value::map();
+        for i in 0..40 {
+            a = a.entry(i, Value::map().entry("x", -0.0).entry("y", i));
+            b = b.entry(39 - i, Value::map().entry("y", Value::FixedInteger(39 - i as i128, IntFormat::I64)).entry("x", 0.0));
+        }
+        assert_eq!(canonical_bytes(a.build()), canonical_bytes(b.build()));
+    }
+
+    #[test]
+    #[cfg(any(feature = "preserve_order", feature = "btree_map"))]
+    fn sorts_map_entries() {
+        let mut a = msgpack!({"b": 1, "a": [{"y": 0, "x": 2}], "c": null});
+        let mut b = msgpack!({"c": null, "a": [{"x": 2, "y": 0}], "b": 1});
+        a.canonicalize();
+        b.canonicalize();
+        assert_eq!(crate::test_util::enc(&a), crate::test_util::enc(&b));
+        assert_eq!(a.to_string(), "{\"a\":[{\"x\":2,\"y\":0}],\"b\":1,\"c\":null}");
+    }
+}
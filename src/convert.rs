@@ -0,0 +1,310 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::hash::{BuildHasher, Hash};
+use std::iter::FromIterator;
+
+use crate::{Error, Result, Value};
+
+impl Value {
+    /// A short name for the kind of value, as used in conversion errors.
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Boolean(_) => "boolean",
+            Value::Integer(_) | Value::FixedInteger(..) => "integer",
+            Value::Float(_) => "float",
+            Value::String(_) | Value::SharedString(_) | Value::InlineString(_) => "string",
+            Value::Bytes(_) => "bytes",
+            Value::Array(_) => "array",
+            Value::Map(_) => "map",
+            Value::Ext(..) => "ext",
+            Value::Raw(_) => "raw"
+        }
+    }
+}
+
+fn mismatch(expected: &'static str, found: &Value) -> Error {
+    Error::TypeMismatch { expected, found: found.kind() }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Value {
+        Value::Boolean(b)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Value {
+        Value::String(s)
+    }
+}
+
+macro_rules! from_integer {
+    ($($t:ty),*) => {
+        $(
+            impl From<$t> for Value {
+                fn from(i: $t) -> Value {
+                    Value::Integer(i as i128)
+                }
+            }
+        )*
+    };
+}
+
+from_integer!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, usize);
+
+impl From<f32> for Value {
+    fn from(f: f32) -> Value {
+        Value::Float(f as f64)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(f: f64) -> Value {
+        Value::Float(f)
+    }
+}
+
+/// Byte vectors become `Value::Bytes`, not arrays of integers.
+impl From<Vec<u8>> for Value {
+    fn from(b: Vec<u8>) -> Value {
+        Value::Bytes(b)
+    }
+}
+
+impl From<&[u8]> for Value {
+    fn from(b: &[u8]) -> Value {
+        Value::Bytes(b.to_vec())
+    }
+}
+
+// A blanket `From<Vec<T>>` would overlap with the `Vec<u8>` impl above, so
+// arrays are provided for each element type instead, plus `FromIterator`.
+macro_rules! from_vec {
+    ($($t:ty),*) => {
+        $(
+            impl From<Vec<$t>> for Value {
+                fn from(items: Vec<$t>) -> Value {
+                    Value::Array(items.into_iter().map(Value::from).collect())
+                }
+            }
+        )*
+    };
+}
+
+from_vec!(Value, bool, &str, String, i8, i16, i32, i64, i128, isize, u16, u32, u64, usize, f32, f64);
+
+/// Fixed-size arrays always become `Value::Array`, even of `u8`.
+impl<T: Into<Value>, const N: usize> From<[T; N]> for Value {
+    fn from(items: [T; N]) -> Value {
+        Value::Array(IntoIterator::into_iter(items).map(Into::into).collect())
+    }
+}
+
+impl<T: Into<Value>> FromIterator<T> for Value {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Value {
+        Value::Array(iter.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<K: Into<Value>, V: Into<Value>, S: BuildHasher> From<HashMap<K, V, S>> for Value {
+    fn from(m: HashMap<K, V, S>) -> Value {
+        Value::Map(m.into_iter().map(|(k, v)| (k.into(), v.into())).collect())
+    }
+}
+
+/// `None` becomes `Value::Null`.
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(o: Option<T>) -> Value {
+        match o {
+            Some(v) => v.into(),
+            None => Value::Null
+        }
+    }
+}
+
+impl TryFrom<Value> for bool {
+    type Error = Error;
+
+    fn try_from(v: Value) -> Result<bool> {
+        match v {
+            Value::Boolean(b) => Ok(b),
+            v => Err(mismatch("boolean", &v))
+        }
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = Error;
+
+    fn try_from(v: Value) -> Result<String> {
+        match v {
+            Value::String(s) => Ok(s),
+            Value::SharedString(s) => Ok(s.to_string()),
+            Value::InlineString(s) => Ok(s.to_string()),
+            v => Err(mismatch("string", &v))
+        }
+    }
+}
+
+/// Only `Value::Bytes` converts to a byte vector; arrays of integers don't.
+impl TryFrom<Value> for Vec<u8> {
+    type Error = Error;
+
+    fn try_from(v: Value) -> Result<Vec<u8>> {
+        match v {
+            Value::Bytes(b) => Ok(b),
+            v => Err(mismatch("bytes", &v))
+        }
+    }
+}
+
+/// Integers convert to any type that can hold them, failing with
+/// `Error::IntegerOutOfRange` otherwise.
+macro_rules! try_from_integer {
+    ($($t:ty),*) => {
+        $(
+            impl TryFrom<Value> for $t {
+                type Error = Error;
+
+                fn try_from(v: Value) -> Result<$t> {
+                    match v {
+                        Value::Integer(i) | Value::FixedInteger(i, _) => {
+                            <$t>::try_from(i).map_err(|_| Error::IntegerOutOfRange)
+                        },
+                        v => Err(mismatch("integer", &v))
+                    }
+                }
+            }
+        )*
+    };
+}
+
+try_from_integer!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+
+impl TryFrom<Value> for f64 {
+    type Error = Error;
+
+    fn try_from(v: Value) -> Result<f64> {
+        match v {
+            Value::Float(f) => Ok(f),
+            v => Err(mismatch("float", &v))
+        }
+    }
+}
+
+impl TryFrom<Value> for f32 {
+    type Error = Error;
+
+    fn try_from(v: Value) -> Result<f32> {
+        f64::try_from(v).map(|f| f as f32)
+    }
+}
+
+macro_rules! try_from_array {
+    ($($t:ty),*) => {
+        $(
+            impl TryFrom<Value> for Vec<$t> {
+                type Error = Error;
+
+                fn try_from(v: Value) -> Result<Vec<$t>> {
+                    match v {
+                        Value::Array(items) => items.into_iter().map(<$t>::try_from).collect(),
+                        v => Err(mismatch("array", &v))
+                    }
+                }
+            }
+        )*
+    };
+}
+
+try_from_array!(bool, String, i8, i16, i32, i64, i128, isize, u16, u32, u64, u128, usize, f32, f64);
+
+impl TryFrom<Value> for Vec<Value> {
+    type Error = Error;
+
+    fn try_from(v: Value) -> Result<Vec<Value>> {
+        match v {
+            Value::Array(items) => Ok(items),
+            v => Err(mismatch("array", &v))
+        }
+    }
+}
+
+impl<K, V, S> TryFrom<Value> for HashMap<K, V, S>
+where
+    K: TryFrom<Value, Error = Error> + Eq + Hash,
+    V: TryFrom<Value, Error = Error>,
+    S: BuildHasher + Default
+{
+    type Error = Error;
+
+    fn try_from(v: Value) -> Result<HashMap<K, V, S>> {
+        match v {
+            Value::Map(m) => m.into_iter().map(|(k, v)| Ok((K::try_from(k)?, V::try_from(v)?))).collect(),
+            v => Err(mismatch("map", &v))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn primitives() {
+        assert_eq!(Value::from(true), Value::Boolean(true));
+        assert_eq!(Value::from("a"), Value::String("a".into()));
+        assert_eq!(Value::from(String::from("b")), Value::String("b".into()));
+        assert_eq!(Value::from(u64::MAX), Value::Integer(u64::MAX as i128));
+        assert_eq!(Value::from(-3i8), Value::Integer(-3));
+        assert_eq!(Value::from(1.5f32), Value::Float(1.5));
+        assert_eq!(Value::from(None::<i32>), Value::Null);
+        assert_eq!(Value::from(Some("x")), Value::String("x".into()));
+    }
+
+    #[test]
+    fn containers() {
+        assert_eq!(Value::from(vec![1u8, 2]), Value::Bytes(vec![1, 2]));
+        assert_eq!(Value::from(vec![1u16, 2]), Value::Array(vec![Value::Integer(1), Value::Integer(2)]));
+        assert_eq!(Value::from(vec!["a"]), Value::Array(vec![Value::String("a".into())]));
+        assert_eq!((0..2).collect::<Value>(), Value::Array(vec![Value::Integer(0), Value::Integer(1)]));
+
+        let mut m = HashMap::new();
+        m.insert("k", vec![Value::Null]);
+        let v = Value::from(m);
+        assert_eq!(v["k"][0], Value::Null);
+    }
+
+    #[test]
+    fn try_from_value() {
+        assert_eq!(bool::try_from(Value::Boolean(true)).ok(), Some(true));
+        assert_eq!(u8::try_from(Value::Integer(255)).ok(), Some(255));
+        assert!(matches!(u8::try_from(Value::Integer(256)), Err(Error::IntegerOutOfRange)));
+        assert_eq!(String::try_from(Value::from("s")).ok(), Some("s".to_string()));
+        assert_eq!(Vec::<u8>::try_from(Value::Bytes(vec![1])).ok(), Some(vec![1]));
+        assert_eq!(Vec::<i64>::try_from(Value::from(vec![1i64, 2])).ok(), Some(vec![1, 2]));
+
+        let mut m = HashMap::new();
+        m.insert("k".to_string(), 2.5f64);
+        let back: Result<HashMap<String, f64>> = HashMap::try_from(Value::from(m.clone()));
+        assert_eq!(back.ok(), Some(m));
+    }
+
+    #[test]
+    fn try_from_reports_kinds() {
+        let e = match String::try_from(Value::Integer(1)) {
+            Err(e) => e,
+            Ok(s) => panic!("converted to {}", s)
+        };
+        assert!(matches!(e, Error::TypeMismatch { expected: "string", found: "integer" }));
+        assert_eq!(e.to_string(), "expected string, found integer");
+        assert!(Vec::<bool>::try_from(Value::from(vec![Value::Null])).is_err());
+    }
+}
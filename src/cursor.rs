@@ -0,0 +1,239 @@
+use std::convert::TryFrom;
+
+use crate::validate::{header_kind, read_header, skip_len, Header};
+use crate::{decode_from_slice, Error, Result, Value};
+
+/// A position in encoded bytes that reads one item at a time, for
+/// consumers that want to walk the wire format themselves. Containers are
+/// entered by reading their header, after which their members follow one
+/// by one; strings and payloads are borrowed from the input and nothing is
+/// allocated. A failed read leaves the cursor where it was.
+#[derive(Debug, Clone, Copy)]
+pub struct Cursor<'a> {
+    buf: &'a [u8],
+    pos: usize
+}
+
+impl<'a> Cursor<'a> {
+    pub fn new(buf: &'a [u8]) -> Cursor<'a> {
+        Cursor { buf, pos: 0 }
+    }
+
+    /// Bytes read so far.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// The bytes not read yet.
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.buf[self.pos..]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos == self.buf.len()
+    }
+
+    /// The kind of the next item, as named by `Error::TypeMismatch`, e.g.
+    /// `"map"` or `"integer"`, without moving.
+    pub fn peek_kind(&self) -> Result<&'static str> {
+        let (header, _) = read_header(self.remaining())?;
+        Ok(header_kind(header, self.remaining()))
+    }
+
+    /// Reads an array header, returning the number of elements that follow.
+    pub fn enter_array(&mut self) -> Result<usize> {
+        match self.header("array")? {
+            (Header::Array(len), n) => {
+                self.pos += n;
+                Ok(len)
+            },
+            (header, _) => Err(self.mismatch("array", header))
+        }
+    }
+
+    /// Reads a map header, returning the number of entries that follow,
+    /// each a key and then its value.
+    pub fn enter_map(&mut self) -> Result<usize> {
+        match self.header("map")? {
+            (Header::Map(len), n) => {
+                self.pos += n;
+                Ok(len)
+            },
+            (header, _) => Err(self.mismatch("map", header))
+        }
+    }
+
+    /// Steps over the next item, containers included, returning its bytes.
+    pub fn skip(&mut self) -> Result<&'a [u8]> {
+        let len = skip_len(self.remaining())?;
+        let bytes = &self.remaining()[..len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    /// Reads a string map key; the same as `read_str`.
+    pub fn key(&mut self) -> Result<&'a str> {
+        self.read_str()
+    }
+
+    pub fn read_str(&mut self) -> Result<&'a str> {
+        let bytes = self.payload("string", |h| match h {
+            Header::Str(len) => Some(*len),
+            _ => None
+        })?;
+        let s = crate::utf8::from_utf8(bytes).map_err(Error::InvalidUtf8)?;
+        self.pos += self.item_len(bytes);
+        Ok(s)
+    }
+
+    pub fn read_bin(&mut self) -> Result<&'a [u8]> {
+        let bytes = self.payload("bytes", |h| match h {
+            Header::Bin(len) => Some(*len),
+            _ => None
+        })?;
+        self.pos += self.item_len(bytes);
+        Ok(bytes)
+    }
+
+    /// Reads an ext, returning its type code and payload.
+    pub fn read_ext(&mut self) -> Result<(i8, &'a [u8])> {
+        let bytes = self.payload("ext", |h| match h {
+            Header::Ext(len) => Some(*len),
+            _ => None
+        })?;
+        let len = self.item_len(bytes);
+        // The type byte is the last of the header.
+        let t = self.buf[self.pos + len - bytes.len() - 1] as i8;
+        self.pos += len;
+        Ok((t, bytes))
+    }
+
+    pub fn read_nil(&mut self) -> Result<()> {
+        match self.scalar("null")? {
+            (Value::Null, len) => {
+                self.pos += len;
+                Ok(())
+            },
+            (v, _) => Err(Error::TypeMismatch { expected: "null", found: v.kind() })
+        }
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool> {
+        self.read_scalar("boolean")
+    }
+
+    pub fn read_i64(&mut self) -> Result<i64> {
+        self.read_scalar("integer")
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64> {
+        self.read_scalar("integer")
+    }
+
+    pub fn read_f64(&mut self) -> Result<f64> {
+        self.read_scalar("float")
+    }
+
+    fn header(&self, expected: &'static str) -> Result<(Header, usize)> {
+        match read_header(self.remaining())? {
+            (Header::Reserved, _) => Err(self.mismatch(expected, Header::Reserved)),
+            h => Ok(h)
+        }
+    }
+
+    fn mismatch(&self, expected: &'static str, header: Header) -> Error {
+        Error::TypeMismatch { expected, found: header_kind(header, self.remaining()) }
+    }
+
+    /// The payload of the next item, if `len` finds a payload length in
+    /// its header.
+    fn payload(&self, expected: &'static str, len: fn(&Header) -> Option<usize>) -> Result<&'a [u8]> {
+        let rest = self.remaining();
+        let (header, n) = self.header(expected)?;
+        match len(&header) {
+            Some(len) if rest.len() - n < len => Err(Error::UnexpectedEof { needed: len, got: rest.len() - n }),
+            Some(len) => Ok(&rest[n..n + len]),
+            None => Err(self.mismatch(expected, header))
+        }
+    }
+
+    /// Header and payload length of the item whose payload is `payload`.
+    fn item_len(&self, payload: &[u8]) -> usize {
+        payload.as_ptr() as usize - self.remaining().as_ptr() as usize + payload.len()
+    }
+
+    /// Decodes the next item if it is a nil, boolean, integer or float,
+    /// along with its length.
+    fn scalar(&self, expected: &'static str) -> Result<(Value, usize)> {
+        match self.header(expected)? {
+            (Header::Scalar(_), _) => decode_from_slice(self.remaining()).map_err(Error::into_root),
+            (header, _) => Err(self.mismatch(expected, header))
+        }
+    }
+
+    fn read_scalar<T: TryFrom<Value, Error = Error>>(&mut self, expected: &'static str) -> Result<T> {
+        let (v, len) = self.scalar(expected)?;
+        let t = T::try_from(v)?;
+        self.pos += len;
+        Ok(t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{enc, ok};
+
+    #[test]
+    fn walks_a_document() {
+        let v = msgpack!([{"id": 7, "name": "ann", "ok": true}, -3, 1.5, null, Value::Bytes(vec![9]), Value::Ext(4, vec![1, 2])]);
+        let buf = enc(&v);
+        let mut cursor = Cursor::new(&buf);
+        assert_eq!(cursor.enter_array().ok(), Some(6));
+        assert_eq!(cursor.peek_kind().ok(), Some("map"));
+        let entries = ok(cursor.enter_map());
+        let (mut id, mut name, mut ok) = (None, None, None);
+        for _ in 0..entries {
+            match cursor.key() {
+                Ok("id") => id = cursor.read_u64().ok(),
+                Ok("name") => name = cursor.read_str().ok(),
+                Ok("ok") => ok = cursor.read_bool().ok(),
+                other => panic!("unexpected key {:?}", other)
+            }
+        }
+        assert_eq!((id, name, ok), (Some(7), Some("ann"), Some(true)));
+        assert_eq!(cursor.read_i64().ok(), Some(-3));
+        assert_eq!(cursor.read_f64().ok(), Some(1.5));
+        assert!(cursor.read_nil().is_ok());
+        assert_eq!(cursor.read_bin().ok(), Some(&[9][..]));
+        assert_eq!(cursor.read_ext().ok(), Some((4, &[1, 2][..])));
+        assert!(cursor.is_empty());
+        assert_eq!(cursor.position(), buf.len());
+    }
+
+    #[test]
+    fn skips_whole_values() {
+        let buf = enc(&msgpack!([[1, [2, 3]], {"a": "b"}, 4]));
+        let mut cursor = Cursor::new(&buf);
+        assert_eq!(cursor.enter_array().ok(), Some(3));
+        assert_eq!(cursor.skip().ok(), Some(&[0x92, 0x01, 0x92, 0x02, 0x03][..]));
+        assert!(cursor.skip().is_ok());
+        assert_eq!(cursor.read_u64().ok(), Some(4));
+        assert!(matches!(cursor.skip(), Err(Error::UnexpectedEof { .. })));
+    }
+
+    #[test]
+    fn mismatches_do_not_move() {
+        let buf = [0xa1, b'x', 0xcd, 0x01, 0x00, 0xc4, 0x05, 0x00];
+        let mut cursor = Cursor::new(&buf);
+        assert!(matches!(cursor.enter_map(), Err(Error::TypeMismatch { expected: "map", found: "string" })));
+        assert!(matches!(cursor.read_u64(), Err(Error::TypeMismatch { expected: "integer", found: "string" })));
+        assert_eq!(cursor.position(), 0);
+        assert_eq!(cursor.read_str().ok(), Some("x"));
+        assert!(matches!(cursor.read_f64(), Err(Error::TypeMismatch { expected: "float", found: "integer" })));
+        assert!(matches!(cursor.read_bool(), Err(Error::TypeMismatch { .. })));
+        assert_eq!(cursor.read_u64().ok(), Some(256));
+        assert!(matches!(cursor.read_bin(), Err(Error::UnexpectedEof { needed: 5, got: 1 })));
+        assert_eq!(cursor.position(), 5);
+    }
+}
@@ -0,0 +1,670 @@
+use std::fmt::Display;
+
+use serde::de::value::{BorrowedBytesDeserializer, I8Deserializer, SeqAccessDeserializer, SeqDeserializer};
+use serde::de::{self, DeserializeSeed, Visitor};
+use serde::forward_to_deserialize_any;
+
+use crate::validate::{read_header, value_len, Header};
+use crate::{DecodeOptions, EnumRepr, Error, Expected, Result, Utf8Policy};
+
+impl de::Error for Error {
+    fn custom<T: Display>(msg: T) -> Error {
+        Error::Custom(msg.to_string())
+    }
+}
+
+/// Reads MessagePack from a slice straight into any `Deserialize` type.
+/// Structs are accepted both as positional arrays and as maps keyed by
+/// field name, and enums in the layout written by `Serializer` with the
+/// same `EnumRepr`. Of the `DecodeOptions`, `max_depth`, `utf8` and
+/// `int128_ext` apply. Strings and bytes are handed out borrowed from the
+/// input, so `&'de str`, `&'de [u8]` and `Cow` fields are filled without
+/// copying.
+pub struct Deserializer<'de> {
+    input: &'de [u8],
+    pos: usize,
+    opts: DecodeOptions,
+    depth: usize,
+    enum_repr: EnumRepr
+}
+
+impl<'de> Deserializer<'de> {
+    pub fn new(input: &'de [u8]) -> Deserializer<'de> {
+        Deserializer::with_options(input, DecodeOptions::default())
+    }
+
+    pub fn with_options(input: &'de [u8], opts: DecodeOptions) -> Deserializer<'de> {
+        Deserializer { input, pos: 0, opts, depth: 0, enum_repr: EnumRepr::External }
+    }
+
+    /// Reads enums in the given layout. Variants may always be named by
+    /// either name or index.
+    pub fn with_enum_repr(mut self, repr: EnumRepr) -> Deserializer<'de> {
+        self.enum_repr = repr;
+        self
+    }
+
+    /// Number of bytes consumed so far.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'de [u8]> {
+        let rest = &self.input[self.pos..];
+        if rest.len() < len {
+            return Err(Error::UnexpectedEof { needed: len, got: rest.len() });
+        }
+        self.pos += len;
+        Ok(&rest[..len])
+    }
+
+    /// Bytes left in the input. Every element takes at least one, so this
+    /// bounds how many a container can really hold, whatever it declares.
+    fn remaining(&self) -> usize {
+        self.input.len() - self.pos
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    /// Reads the next header, leaving `pos` at its payload.
+    fn header(&mut self) -> Result<(u8, Header)> {
+        let start = self.pos;
+        let (header, n) = read_header(&self.input[start..])?;
+        if let Header::Reserved = header {
+            return Err(Error::ReservedMarker { offset: start as u64, expected: Expected::Value });
+        }
+        self.pos += n;
+        Ok((self.input[start], header))
+    }
+
+    fn enter(&mut self) -> Result<()> {
+        if self.depth >= self.opts.max_depth {
+            return Err(Error::DepthLimitExceeded);
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn scalar<V: Visitor<'de>>(&mut self, marker: u8, visitor: V) -> Result<V::Value> {
+        match marker {
+            0x00..=0x7f => visitor.visit_u64(marker as u64),
+            0xe0..=0xff => visitor.visit_i64((marker as i8) as i64),
+            0xc0 => visitor.visit_unit(),
+            0xc2 => visitor.visit_bool(false),
+            0xc3 => visitor.visit_bool(true),
+            0xca => visitor.visit_f32(f32::from_be_bytes(self.array()?)),
+            0xcb => visitor.visit_f64(f64::from_be_bytes(self.array()?)),
+            0xcc => visitor.visit_u64(u8::from_be_bytes(self.array()?) as u64),
+            0xcd => visitor.visit_u64(u16::from_be_bytes(self.array()?) as u64),
+            0xce => visitor.visit_u64(u32::from_be_bytes(self.array()?) as u64),
+            0xcf => visitor.visit_u64(u64::from_be_bytes(self.array()?)),
+            0xd0 => visitor.visit_i64(i8::from_be_bytes(self.array()?) as i64),
+            0xd1 => visitor.visit_i64(i16::from_be_bytes(self.array()?) as i64),
+            0xd2 => visitor.visit_i64(i32::from_be_bytes(self.array()?) as i64),
+            _ => visitor.visit_i64(i64::from_be_bytes(self.array()?))
+        }
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut buf = [0u8; N];
+        buf.copy_from_slice(self.take(N)?);
+        Ok(buf)
+    }
+
+    fn str<V: Visitor<'de>>(&mut self, len: usize, visitor: V) -> Result<V::Value> {
+        let bytes = self.take(len)?;
+        match crate::utf8::from_utf8(bytes) {
+            Ok(s) => visitor.visit_borrowed_str(s),
+            Err(e) => match self.opts.utf8 {
+                Utf8Policy::Strict => Err(Error::InvalidUtf8(e)),
+                Utf8Policy::Lossy => visitor.visit_string(String::from_utf8_lossy(bytes).into_owned()),
+                Utf8Policy::Bytes => visitor.visit_borrowed_bytes(bytes)
+            }
+        }
+    }
+
+    /// The ext type byte is the last byte of the header just read.
+    fn ext<V: Visitor<'de>>(&mut self, len: usize, visitor: V) -> Result<V::Value> {
+        let t = self.input[self.pos - 1] as i8;
+        let data = self.take(len)?;
+        if len == 16 && self.opts.int128_ext == Some(t) {
+            let mut buf = [0u8; 16];
+            buf.copy_from_slice(data);
+            return visitor.visit_i128(i128::from_be_bytes(buf));
+        }
+        visitor.visit_newtype_struct(SeqAccessDeserializer::new(ExtAccess { t, data, step: 0 }))
+    }
+
+    fn seq<V: Visitor<'de>>(&mut self, len: usize, visitor: V) -> Result<V::Value> {
+        self.enter()?;
+        let mut access = Access { de: self, left: len };
+        let value = visitor.visit_seq(&mut access)?;
+        if access.left > 0 {
+            return Err(de::Error::invalid_length(len, &"fewer elements in array"));
+        }
+        self.depth -= 1;
+        Ok(value)
+    }
+
+    /// Consumes a str equal to `key`, the tag or content key of an enum.
+    fn expect_key(&mut self, key: &str) -> Result<()> {
+        match self.header()? {
+            (_, Header::Str(len)) if self.take(len)? == key.as_bytes() => Ok(()),
+            _ => Err(Error::Custom(format!("expected key {:?}", key)))
+        }
+    }
+
+    fn skip(&mut self) -> Result<()> {
+        self.pos += value_len(&self.input[self.pos..])?;
+        Ok(())
+    }
+
+    /// Visits a bin as a sequence of its bytes, so that `Vec<u8>` and
+    /// `[u8; N]` read bin without going through a bytes helper.
+    fn seq_or_bin<V: Visitor<'de>>(&mut self, visitor: V) -> Result<V::Value> {
+        let start = self.pos;
+        match self.header()? {
+            (_, Header::Bin(len)) => {
+                let mut seq = SeqDeserializer::<_, Error>::new(self.take(len)?.iter().copied());
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(value)
+            },
+            _ => {
+                self.pos = start;
+                de::Deserializer::deserialize_any(self, visitor)
+            }
+        }
+    }
+
+    fn map<V: Visitor<'de>>(&mut self, len: usize, visitor: V) -> Result<V::Value> {
+        self.enter()?;
+        let mut access = Access { de: self, left: len };
+        let value = visitor.visit_map(&mut access)?;
+        if access.left > 0 {
+            return Err(de::Error::invalid_length(len, &"fewer entries in map"));
+        }
+        self.depth -= 1;
+        Ok(value)
+    }
+}
+
+/// Decodes a `T` from the front of `buf`. As with rmp-serde, any bytes
+/// after the value are ignored; use `Deserializer::position` to find them.
+pub fn from_slice<'de, T: de::Deserialize<'de>>(buf: &'de [u8]) -> Result<T> {
+    T::deserialize(&mut Deserializer::new(buf))
+}
+
+impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
+    type Error = Error;
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let (marker, header) = self.header()?;
+        match header {
+            Header::Scalar(_) => self.scalar(marker, visitor),
+            Header::Str(len) => self.str(len, visitor),
+            Header::Bin(len) => visitor.visit_borrowed_bytes(self.take(len)?),
+            Header::Ext(len) => self.ext(len, visitor),
+            Header::Array(len) => self.seq(len, visitor),
+            Header::Map(len) => self.map(len, visitor),
+            // `header` already turns these away, at the marker itself.
+            Header::Reserved => Err(Error::ReservedMarker { offset: self.pos as u64 - 1, expected: Expected::Value })
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if self.peek() == Some(0xc0) {
+            self.pos += 1;
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, name: &'static str, visitor: V) -> Result<V::Value> {
+        if name == crate::EXT_STRUCT_NAME {
+            self.deserialize_any(visitor)
+        } else {
+            visitor.visit_newtype_struct(self)
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V
+    ) -> Result<V::Value> {
+        let start = self.pos;
+        let (tag, content) = match self.enum_repr {
+            EnumRepr::External | EnumRepr::Index => {
+                return match self.header()? {
+                    (_, Header::Map(1)) => {
+                        self.enter()?;
+                        let value = visitor.visit_enum(Variant { de: &mut *self, form: Form::External })?;
+                        self.depth -= 1;
+                        Ok(value)
+                    },
+                    (_, Header::Map(_)) | (_, Header::Array(_)) => {
+                        Err(de::Error::invalid_type(de::Unexpected::Other("container"), &"enum"))
+                    },
+                    _ => {
+                        self.pos = start;
+                        visitor.visit_enum(Variant { de: self, form: Form::Unit })
+                    }
+                };
+            },
+            EnumRepr::Internal { tag } => (tag, None),
+            EnumRepr::Adjacent { tag, content } => (tag, Some(content))
+        };
+        let len = match self.header()? {
+            (_, Header::Map(len)) if len > 0 => len,
+            _ => return Err(de::Error::invalid_type(de::Unexpected::Other("non-map value"), &"tagged enum"))
+        };
+        self.enter()?;
+        self.expect_key(tag)?;
+        let form = match content {
+            Some(content) => Form::Adjacent { content, left: len - 1 },
+            None => Form::Internal { left: len - 1 }
+        };
+        let value = visitor.visit_enum(Variant { de: &mut *self, form })?;
+        self.depth -= 1;
+        Ok(value)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.seq_or_bin(visitor)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        self.seq_or_bin(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.skip()?;
+        visitor.visit_unit()
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct tuple_struct map struct identifier
+    }
+}
+
+/// Elements of an array, or entries of a map, still to be read.
+struct Access<'a, 'de> {
+    de: &'a mut Deserializer<'de>,
+    left: usize
+}
+
+impl<'de> de::SeqAccess<'de> for Access<'_, 'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        if self.left == 0 {
+            return Ok(None);
+        }
+        self.left -= 1;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.left.min(self.de.remaining()))
+    }
+}
+
+impl<'de> de::MapAccess<'de> for Access<'_, 'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        if self.left == 0 {
+            return Ok(None);
+        }
+        self.left -= 1;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        seed.deserialize(&mut *self.de)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.left.min(self.de.remaining() / 2))
+    }
+}
+
+/// Where an enum's content is, relative to its variant name.
+enum Form {
+    /// A bare name or index with no content.
+    Unit,
+    /// The value of the single-entry map keyed by the variant.
+    External,
+    /// The `left` map entries after the tag.
+    Internal { left: usize },
+    /// The value under `content`, if `left` is 1.
+    Adjacent { content: &'static str, left: usize }
+}
+
+struct Variant<'a, 'de> {
+    de: &'a mut Deserializer<'de>,
+    form: Form
+}
+
+impl<'de> Variant<'_, 'de> {
+    /// Positions the reader at an adjacently tagged variant's content.
+    fn content(&mut self) -> Result<()> {
+        match self.form {
+            Form::Adjacent { content, left: 1 } => self.de.expect_key(content),
+            Form::Unit | Form::Internal { .. } | Form::Adjacent { .. } => {
+                Err(de::Error::invalid_type(de::Unexpected::UnitVariant, &"variant with content"))
+            },
+            Form::External => Ok(())
+        }
+    }
+}
+
+impl<'a, 'de> de::EnumAccess<'de> for Variant<'a, 'de> {
+    type Error = Error;
+    type Variant = Variant<'a, 'de>;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Variant<'a, 'de>)> {
+        let variant = seed.deserialize(&mut *self.de)?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Variant<'_, 'de> {
+    type Error = Error;
+
+    /// Ignores any content, as well as fields after an internal tag.
+    fn unit_variant(self) -> Result<()> {
+        match self.form {
+            Form::Unit => Ok(()),
+            Form::External => self.de.skip(),
+            Form::Internal { left } | Form::Adjacent { left, .. } => {
+                for _ in 0..left {
+                    self.de.skip()?;
+                    self.de.skip()?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(mut self, seed: T) -> Result<T::Value> {
+        if let Form::Internal { left } = self.form {
+            return seed.deserialize(Rest { de: self.de, left });
+        }
+        self.content()?;
+        seed.deserialize(self.de)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(mut self, _len: usize, visitor: V) -> Result<V::Value> {
+        self.content()?;
+        de::Deserializer::deserialize_any(self.de, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(mut self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value> {
+        if let Form::Internal { left } = self.form {
+            return de::Deserializer::deserialize_any(Rest { de: self.de, left }, visitor);
+        }
+        self.content()?;
+        de::Deserializer::deserialize_any(self.de, visitor)
+    }
+}
+
+/// The map entries following an internal tag, read as a map of their own.
+struct Rest<'a, 'de> {
+    de: &'a mut Deserializer<'de>,
+    left: usize
+}
+
+impl<'de> de::Deserializer<'de> for Rest<'_, 'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let len = self.left;
+        let mut access = Access { de: self.de, left: len };
+        let value = visitor.visit_map(&mut access)?;
+        if access.left > 0 {
+            return Err(de::Error::invalid_length(len, &"fewer entries in map"));
+        }
+        Ok(value)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+/// Presents an ext as the `(i8, bytes)` tuple under `EXT_STRUCT_NAME`.
+struct ExtAccess<'de> {
+    t: i8,
+    data: &'de [u8],
+    step: u8
+}
+
+impl<'de> de::SeqAccess<'de> for ExtAccess<'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        self.step += 1;
+        match self.step {
+            1 => seed.deserialize(I8Deserializer::new(self.t)).map(Some),
+            2 => seed.deserialize(BorrowedBytesDeserializer::new(self.data)).map(Some),
+            _ => Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::test_util::{de, enc, ok, ser};
+    use crate::Value;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Point {
+        x: i32,
+        y: i32
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Shape {
+        Empty,
+        Dot(Point),
+        Line(Point, Point),
+        Circle { r: f64 }
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Drawing {
+        name: String,
+        shapes: Vec<Shape>,
+        note: Option<String>,
+        tags: HashMap<String, u8>,
+        #[serde(with = "bytes")]
+        thumbnail: Vec<u8>,
+        layers: Option<Box<Drawing>>
+    }
+
+    /// Writes bytes as bin, as serde_bytes does.
+    mod bytes {
+        pub fn serialize<S: serde::Serializer>(b: &[u8], s: S) -> Result<S::Ok, S::Error> {
+            s.serialize_bytes(b)
+        }
+
+        pub fn deserialize<'de, D: serde::Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
+            struct V;
+            impl<'de> serde::de::Visitor<'de> for V {
+                type Value = Vec<u8>;
+                fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                    write!(f, "bytes")
+                }
+                fn visit_bytes<E>(self, b: &[u8]) -> Result<Vec<u8>, E> {
+                    Ok(b.to_vec())
+                }
+            }
+            d.deserialize_bytes(V)
+        }
+    }
+
+    #[test]
+    fn round_trips_derived_types() {
+        let inner = Drawing {
+            name: "inner".into(),
+            shapes: vec![Shape::Empty],
+            note: None,
+            tags: HashMap::new(),
+            thumbnail: vec![],
+            layers: None
+        };
+        let drawing = Drawing {
+            name: "d".into(),
+            shapes: vec![
+                Shape::Dot(Point { x: 1, y: 2 }),
+                Shape::Line(Point { x: -1, y: 0 }, Point { x: 300, y: 70000 }),
+                Shape::Circle { r: 1.5 }
+            ],
+            note: Some("n".into()),
+            tags: vec![("a".to_string(), 1)].into_iter().collect(),
+            thumbnail: vec![0, 255, 7],
+            layers: Some(Box::new(inner))
+        };
+        assert_eq!(de::<Drawing>(&ser(&drawing)), drawing);
+    }
+
+    #[test]
+    fn structs_accept_maps() {
+        let buf = enc(&msgpack!({"y": 2, "x": 1}));
+        assert_eq!(de::<Point>(&buf), Point { x: 1, y: 2 });
+    }
+
+    #[test]
+    fn reads_values_and_ext() {
+        let v = msgpack!({"a": [1, null, true, -2.5, "s"], "big": (u64::MAX), "ext": (Value::Ext(3, vec![1]))});
+        assert_eq!(de::<Value>(&enc(&v.clone())), v);
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Event {
+        Ping,
+        Moved(Point),
+        Resized { w: u32, h: u32 }
+    }
+
+    fn with_repr(events: &[Event], repr: EnumRepr) -> Value {
+        let mut buf = Vec::new();
+        ok(events.serialize(&mut crate::Serializer::new(&mut buf).with_enum_repr(repr)));
+        let back = Vec::<Event>::deserialize(&mut Deserializer::new(&buf).with_enum_repr(repr));
+        assert_eq!(back.ok().as_deref(), Some(events));
+        de(&buf)
+    }
+
+    #[test]
+    fn enum_representations() {
+        let events = [Event::Ping, Event::Moved(Point { x: 1, y: 2 }), Event::Resized { w: 3, h: 4 }];
+        assert_eq!(with_repr(&events, EnumRepr::External), msgpack!(["Ping", {"Moved": [1, 2]}, {"Resized": [3, 4]}]));
+        assert_eq!(with_repr(&events, EnumRepr::Index), msgpack!([0, {1: [1, 2]}, {2: [3, 4]}]));
+        assert_eq!(
+            with_repr(&events, EnumRepr::Internal { tag: "t" }),
+            msgpack!([{"t": "Ping"}, {"t": "Moved", "x": 1, "y": 2}, {"t": "Resized", "w": 3, "h": 4}])
+        );
+        assert_eq!(
+            with_repr(&events, EnumRepr::Adjacent { tag: "t", content: "c" }),
+            msgpack!([{"t": "Ping"}, {"t": "Moved", "c": [1, 2]}, {"t": "Resized", "c": [3, 4]}])
+        );
+    }
+
+    #[test]
+    fn internal_tags_need_maps() {
+        let mut buf = Vec::new();
+        let mut ser = crate::Serializer::new(&mut buf).with_enum_repr(EnumRepr::Internal { tag: "t" });
+        let r = Shape::Line(Point { x: 1, y: 2 }, Point { x: 3, y: 4 }).serialize(&mut ser);
+        assert!(matches!(r, Err(Error::Custom(_))));
+        let buf = enc(&msgpack!({"Ping": null}));
+        let r = Event::deserialize(&mut Deserializer::new(&buf).with_enum_repr(EnumRepr::Internal { tag: "t" }));
+        assert!(matches!(r, Err(Error::Custom(_))));
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Request<'a> {
+        method: &'a str,
+        #[serde(borrow)]
+        path: std::borrow::Cow<'a, str>,
+        body: &'a [u8]
+    }
+
+    #[test]
+    fn borrows_from_input() {
+        let buf = enc(&msgpack!(["GET", "/index", (vec![1u8, 2])]));
+        let req: Request = de(&buf);
+        assert_eq!(req.method, "GET");
+        assert!(matches!(req.path, std::borrow::Cow::Borrowed("/index")));
+        assert_eq!(req.body, &[1, 2]);
+        let range = buf.as_ptr_range();
+        assert!(range.contains(&req.method.as_ptr()) && range.contains(&req.body.as_ptr()));
+    }
+
+    #[test]
+    fn reports_errors() {
+        assert!(matches!(from_slice::<Point>(&[0x92, 0x01]), Err(Error::UnexpectedEof { .. })));
+        assert!(matches!(from_slice::<Point>(&[0x93, 0x01, 0x02, 0x03]), Err(Error::Custom(_))));
+        assert!(matches!(from_slice::<String>(&[0xa1, 0xff]), Err(Error::InvalidUtf8(_))));
+        assert!(matches!(from_slice::<u8>(&[0xc1]), Err(Error::ReservedMarker { .. })));
+        assert!(matches!(from_slice::<Value>(&[0x92, 0x01, 0xc1]), Err(Error::ReservedMarker { offset: 2, .. })));
+        let nested = enc(&msgpack!([[[1]]]));
+        let opts = DecodeOptions { max_depth: 2, ..DecodeOptions::default() };
+        let r = Value::deserialize(&mut Deserializer::with_options(&nested, opts));
+        assert!(matches!(r, Err(Error::DepthLimitExceeded)));
+    }
+
+    /// Records what a visitor would be told to preallocate.
+    struct HintVisitor<'a>(&'a std::cell::Cell<Option<usize>>);
+
+    impl<'de> Visitor<'de> for HintVisitor<'_> {
+        type Value = ();
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(f, "a container")
+        }
+
+        fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> std::result::Result<(), A::Error> {
+            self.0.set(seq.size_hint());
+            while seq.next_element::<de::IgnoredAny>()?.is_some() {}
+            Ok(())
+        }
+
+        fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> std::result::Result<(), A::Error> {
+            self.0.set(map.size_hint());
+            while map.next_entry::<de::IgnoredAny, de::IgnoredAny>()?.is_some() {}
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn size_hints_are_capped_by_the_input() {
+        let hint = |buf: &[u8]| {
+            let hint = std::cell::Cell::new(None);
+            let _ = de::Deserializer::deserialize_any(&mut Deserializer::new(buf), HintVisitor(&hint));
+            hint.get()
+        };
+        assert_eq!(hint(&[0x93, 0x01, 0x02, 0x03]), Some(3));
+        assert_eq!(hint(&[0xdd, 0xff, 0xff, 0xff, 0xff, 0x01, 0x02]), Some(2));
+        assert_eq!(hint(&[0x82, 0x01, 0x02, 0x03, 0x04]), Some(2));
+        assert_eq!(hint(&[0xdf, 0xff, 0xff, 0xff, 0xff, 0x01, 0x02, 0x03]), Some(1));
+        assert!(from_slice::<Vec<u64>>(&[0xdd, 0xff, 0xff, 0xff, 0xff]).is_err());
+    }
+}
@@ -0,0 +1,128 @@
+use std::io::{BufRead, Read};
+
+use crate::{decode_value, Buffered, DecodeOptions, Interner, Plain, Pool, Reader, Result, Source, Value};
+
+/// Decodes message after message, reusing the buffers of values handed
+/// back through `reclaim` and the stack used to walk nested input, so a
+/// long-running consumer puts far less pressure on the allocator. With
+/// `DecodeOptions::intern_keys`, map keys are shared across decodes too.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    opts: DecodeOptions,
+    pool: Pool,
+    keys: Interner
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder::default()
+    }
+
+    pub fn with_options(opts: DecodeOptions) -> Decoder {
+        Decoder { opts, pool: Pool::default(), keys: Interner::default() }
+    }
+
+    pub fn options(&self) -> &DecodeOptions {
+        &self.opts
+    }
+
+    /// Decodes one value from the front of `buf`, returning it with the
+    /// number of bytes it occupied, like `decode_from_slice`.
+    pub fn decode(&mut self, buf: &[u8]) -> Result<(Value, usize)> {
+        let mut r = buf;
+        self.run(Buffered(&mut r), 0).map(|(v, len)| (v, len as usize))
+    }
+
+    /// Decodes one value from `r`, like `decode_with`.
+    pub fn decode_from<R: Read + ?Sized>(&mut self, r: &mut R) -> Result<Value> {
+        self.run(Plain(r), 0).map(|(v, _)| v)
+    }
+
+    /// Decodes one value from `r`, counting offsets in errors from
+    /// `offset`, and returns it with the offset just past it.
+    pub(crate) fn decode_buf_at<R: BufRead + ?Sized>(&mut self, r: &mut R, offset: u64) -> Result<(Value, u64)> {
+        self.run(Buffered(r), offset)
+    }
+
+    /// Takes `value` apart, keeping its array, string and byte buffers for
+    /// later decodes. A bounded number of each is kept; the rest are freed.
+    pub fn reclaim(&mut self, value: Value) {
+        self.pool.reclaim(value);
+    }
+
+    fn run<S: Source>(&mut self, source: S, offset: u64) -> Result<(Value, u64)> {
+        let mut reader = Reader::new(source);
+        reader.offset = offset;
+        reader.pool = std::mem::take(&mut self.pool);
+        reader.keys = std::mem::take(&mut self.keys);
+        let result = decode_value(&mut reader, &self.opts);
+        self.pool = std::mem::take(&mut reader.pool);
+        self.keys = std::mem::take(&mut reader.keys);
+        match result {
+            Ok(v) => Ok((v, reader.offset)),
+            Err(e) => Err(reader.context(e))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::test_util::{enc, ok};
+    use crate::{Error, POOL_LIMIT};
+
+    #[test]
+    fn reuses_reclaimed_buffers() {
+        let v = msgpack!({"name": "a".repeat(100), "items": [1, 2, 3, Value::Bytes(vec![7; 50])]});
+        let buf = enc(&v);
+        let mut decoder = Decoder::new();
+        for _ in 0..100 {
+            let (decoded, len) = ok(decoder.decode(&buf));
+            assert_eq!(len, buf.len());
+            assert_eq!(decoded, v);
+            decoder.reclaim(decoded);
+        }
+        // The pending stack and the items array; both keys, the name and
+        // the bin payload. Steady state, so nothing new was allocated.
+        assert_eq!(decoder.pool.arrays.len(), 2);
+        assert_eq!(decoder.pool.bytes.len(), 4);
+        assert!(decoder.pool.frames.capacity() > 0);
+    }
+
+    #[test]
+    fn reclaims_deep_values_without_recursing() {
+        let mut v = Value::Null;
+        for _ in 0..200_000 {
+            v = Value::Array(vec![v]);
+        }
+        let mut decoder = Decoder::new();
+        decoder.reclaim(v);
+        assert!(decoder.pool.arrays.len() <= POOL_LIMIT);
+    }
+
+    #[test]
+    fn errors_leave_the_decoder_usable() {
+        let mut decoder = Decoder::with_options(DecodeOptions { max_depth: 2, ..DecodeOptions::default() });
+        assert!(matches!(decoder.decode(&[0x91, 0x91, 0x91, 0xc0]).map_err(Error::into_root), Err(Error::DepthLimitExceeded)));
+        assert!(matches!(decoder.decode(&[0x92, 0xa1]).map_err(Error::into_root), Err(Error::UnexpectedEof { .. })));
+        let mut r: &[u8] = &[0x91, 0xa1, b'x', 0xc0];
+        assert_eq!(decoder.decode_from(&mut r).ok(), Some(msgpack!(["x"])));
+        assert_eq!(r, [0xc0]);
+    }
+
+    #[test]
+    fn shares_keys_between_decodes() {
+        let mut decoder = Decoder::with_options(DecodeOptions { intern_keys: true, ..DecodeOptions::default() });
+        let buf = enc(&msgpack!({"id": 1}));
+        let mut keys = Vec::new();
+        for _ in 0..2 {
+            if let Ok((v, _)) = decoder.decode(&buf) {
+                keys.extend(v.entries().map(|(k, _)| k.clone()));
+            }
+        }
+        assert!(matches!(&keys[..], [Value::SharedString(a), Value::SharedString(b)] if Arc::ptr_eq(a, b)));
+        assert_eq!(decoder.keys.len(), 1);
+    }
+}
@@ -0,0 +1,188 @@
+use std::convert::TryFrom;
+
+use crate::validate::{read_header, skip_len, Header};
+use crate::{decode_from_slice, Result, Value};
+
+/// A read-only view of an encoded value that decodes nothing up front.
+/// Fields and elements are found by skipping over their siblings, and only
+/// the parts actually asked for are decoded, so pulling a few fields out
+/// of a large message costs a scan rather than a full decode.
+///
+/// Lookups return `None` for missing keys, out-of-range indices, values of
+/// the wrong type and malformed input alike; `as_encoded` and `decode`
+/// report what went wrong.
+#[derive(Debug, Clone, Copy)]
+pub struct Document<'a> {
+    /// Starts with the value; anything after it is ignored.
+    buf: &'a [u8]
+}
+
+impl<'a> Document<'a> {
+    /// Wraps `buf` without looking at it.
+    pub fn new(buf: &'a [u8]) -> Document<'a> {
+        Document { buf }
+    }
+
+    /// The encoded bytes of this value alone.
+    pub fn as_encoded(&self) -> Result<&'a [u8]> {
+        let len = skip_len(self.buf)?;
+        Ok(&self.buf[..len])
+    }
+
+    /// Fully decodes this value.
+    pub fn decode(&self) -> Result<Value> {
+        decode_from_slice(self.buf).map(|(v, _)| v)
+    }
+
+    /// The number of elements of an array or entries of a map.
+    pub fn len(&self) -> Option<usize> {
+        match read_header(self.buf).ok()? {
+            (Header::Array(len), _) | (Header::Map(len), _) => Some(len),
+            _ => None
+        }
+    }
+
+    pub fn is_empty(&self) -> Option<bool> {
+        self.len().map(|len| len == 0)
+    }
+
+    /// The value under the string key `key`, if this is a map holding one.
+    /// With duplicate keys the first wins.
+    pub fn get(&self, key: &str) -> Option<Document<'a>> {
+        let (len, n) = match read_header(self.buf).ok()? {
+            (Header::Map(len), n) => (len, n),
+            _ => return None
+        };
+        let mut pos = n;
+        for _ in 0..len {
+            let rest = &self.buf[pos..];
+            let key_len = skip_len(rest).ok()?;
+            let matched = match read_header(rest).ok()? {
+                (Header::Str(len), n) => &rest[n..n + len] == key.as_bytes(),
+                _ => false
+            };
+            pos += key_len;
+            if matched {
+                return Some(Document::new(&self.buf[pos..]));
+            }
+            pos += skip_len(&self.buf[pos..]).ok()?;
+        }
+        None
+    }
+
+    /// The element at `index`, if this is an array that long.
+    pub fn index(&self, index: usize) -> Option<Document<'a>> {
+        let (len, n) = match read_header(self.buf).ok()? {
+            (Header::Array(len), n) => (len, n),
+            _ => return None
+        };
+        if index >= len {
+            return None;
+        }
+        let mut pos = n;
+        for _ in 0..index {
+            pos += skip_len(&self.buf[pos..]).ok()?;
+        }
+        Some(Document::new(&self.buf[pos..]))
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.buf.first() == Some(&0xc0)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        self.scalar().and_then(|v| bool::try_from(v).ok())
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        self.scalar().and_then(|v| i64::try_from(v).ok())
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        self.scalar().and_then(|v| u64::try_from(v).ok())
+    }
+
+    /// Floats as they are; integers are not converted.
+    pub fn as_f64(&self) -> Option<f64> {
+        self.scalar().and_then(|v| f64::try_from(v).ok())
+    }
+
+    /// Borrows a string from the underlying buffer.
+    pub fn as_str(&self) -> Option<&'a str> {
+        match read_header(self.buf).ok()? {
+            (Header::Str(len), n) => crate::utf8::from_utf8(self.buf.get(n..n + len)?).ok(),
+            _ => None
+        }
+    }
+
+    /// Borrows a bin payload from the underlying buffer.
+    pub fn as_bin(&self) -> Option<&'a [u8]> {
+        match read_header(self.buf).ok()? {
+            (Header::Bin(len), n) => self.buf.get(n..n + len),
+            _ => None
+        }
+    }
+
+    /// Decodes a nil, boolean, integer or float; nothing else is touched.
+    fn scalar(&self) -> Option<Value> {
+        match read_header(self.buf).ok()? {
+            (Header::Scalar(_), _) => decode_from_slice(self.buf).ok().map(|(v, _)| v),
+            _ => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::enc;
+
+    fn sample() -> Vec<u8> {
+        let v = msgpack!({
+            "user": {"id": 42, "name": "ann", "score": 1.5, "admin": false, "key": Value::Bytes(vec![1, 2])},
+            "tags": ["a", "b", "c"],
+            "none": null
+        });
+        enc(&v)
+    }
+
+    #[test]
+    fn resolves_paths_lazily() {
+        let buf = sample();
+        let doc = Document::new(&buf);
+        let user = doc.get("user");
+        assert_eq!(user.and_then(|u| u.get("id")).and_then(|d| d.as_u64()), Some(42));
+        assert_eq!(user.and_then(|u| u.get("id")).and_then(|d| d.as_i64()), Some(42));
+        assert_eq!(user.and_then(|u| u.get("name")).and_then(|d| d.as_str()), Some("ann"));
+        assert_eq!(user.and_then(|u| u.get("score")).and_then(|d| d.as_f64()), Some(1.5));
+        assert_eq!(user.and_then(|u| u.get("admin")).and_then(|d| d.as_bool()), Some(false));
+        assert_eq!(user.and_then(|u| u.get("key")).and_then(|d| d.as_bin()), Some(&[1, 2][..]));
+        assert_eq!(user.and_then(|u| u.len()), Some(5));
+        assert_eq!(doc.get("tags").and_then(|t| t.index(2)).and_then(|d| d.as_str()), Some("c"));
+        assert!(doc.get("tags").and_then(|t| t.index(3)).is_none());
+        assert!(doc.get("none").map(|d| d.is_null()).unwrap_or_default());
+        assert!(doc.get("missing").is_none());
+        assert!(doc.get("user").and_then(|u| u.get("name")).and_then(|d| d.as_u64()).is_none());
+    }
+
+    #[test]
+    fn decodes_and_slices_sub_values() {
+        let buf = sample();
+        let tags = Document::new(&buf).get("tags");
+        assert_eq!(tags.and_then(|t| t.decode().ok()), Some(msgpack!(["a", "b", "c"])));
+        let encoded = tags.and_then(|t| t.as_encoded().ok()).unwrap_or_default();
+        assert_eq!(encoded, [0x93, 0xa1, b'a', 0xa1, b'b', 0xa1, b'c']);
+    }
+
+    #[test]
+    fn malformed_input_is_not_found() {
+        // A map whose first value is cut short.
+        let buf = [0x82, 0xa1, b'a', 0xa5, b'x', 0xa1, b'b', 0x01];
+        let doc = Document::new(&buf);
+        assert!(doc.get("b").is_none());
+        assert!(doc.as_encoded().is_err());
+        assert!(Document::new(&[]).len().is_none());
+        // Scalars only ever look at their own bytes.
+        assert_eq!(Document::new(&[0x07, 0xc1]).as_u64(), Some(7));
+    }
+}
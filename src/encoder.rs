@@ -0,0 +1,121 @@
+use crate::{encode_value_in, recycle, EncodeOptions, EncodeValue, Pending, Result, Value};
+
+/// Encodes message after message into one buffer that is kept between
+/// them, along with the stack used to walk nested values, so a service
+/// encoding many small messages stops allocating once both have grown to
+/// fit the largest.
+#[derive(Debug, Default)]
+pub struct Encoder {
+    buf: Vec<u8>,
+    stack: Vec<Pending<'static>>,
+    opts: EncodeOptions
+}
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        Encoder::default()
+    }
+
+    pub fn with_options(opts: EncodeOptions) -> Encoder {
+        Encoder { buf: Vec::new(), stack: Vec::new(), opts }
+    }
+
+    /// Starts with room for `capacity` bytes of output.
+    pub fn with_capacity(capacity: usize) -> Encoder {
+        Encoder { buf: Vec::with_capacity(capacity), stack: Vec::new(), opts: EncodeOptions::default() }
+    }
+
+    /// Encodes `value` in place of whatever was buffered before, returning
+    /// its bytes.
+    pub fn encode(&mut self, value: &Value) -> Result<&[u8]> {
+        self.reset();
+        self.append(value)?;
+        Ok(&self.buf)
+    }
+
+    /// Like `encode`, for any `EncodeValue` type. Typed encoding has no
+    /// options, so those of the encoder don't apply.
+    pub fn encode_value<T: EncodeValue + ?Sized>(&mut self, value: &T) -> Result<&[u8]> {
+        self.reset();
+        if let Err(e) = value.encode(&mut self.buf) {
+            self.buf.clear();
+            return Err(e);
+        }
+        Ok(&self.buf)
+    }
+
+    /// Encodes `value` after what is already buffered, for batching several
+    /// values into one write. On error the buffer is left as it was.
+    pub fn append(&mut self, value: &Value) -> Result<()> {
+        let len = self.buf.len();
+        let mut stack = recycle(std::mem::take(&mut self.stack));
+        let result = encode_value_in(&mut self.buf, value, &self.opts, &mut stack);
+        self.stack = recycle(stack);
+        if result.is_err() {
+            self.buf.truncate(len);
+        }
+        result
+    }
+
+    /// The bytes encoded since the last reset.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// Empties the buffer, keeping its allocation.
+    pub fn reset(&mut self) {
+        self.buf.clear();
+    }
+
+    pub fn options(&self) -> &EncodeOptions {
+        &self.opts
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::enc;
+    use crate::Error;
+
+    #[test]
+    fn reuses_its_buffer() {
+        let mut encoder = Encoder::with_capacity(64);
+        let first = msgpack!({"id": 1, "tags": ["a", "b"]});
+        let expected = enc(&first);
+        assert_eq!(encoder.encode(&first).ok(), Some(&expected[..]));
+
+        let ptr = encoder.as_bytes().as_ptr();
+        for i in 0..1000 {
+            let v = msgpack!([i, "x"]);
+            let expected = enc(&v);
+            assert_eq!(encoder.encode(&v).ok(), Some(&expected[..]));
+        }
+        assert_eq!(encoder.as_bytes().as_ptr(), ptr);
+        assert!(encoder.stack.capacity() > 0);
+    }
+
+    #[test]
+    fn appends_and_rolls_back() {
+        let opts = EncodeOptions { max_depth: 1, ..EncodeOptions::default() };
+        let mut encoder = Encoder::with_options(opts);
+        assert!(encoder.append(&msgpack!(1)).is_ok());
+        assert!(encoder.append(&msgpack!([2])).is_ok());
+        assert!(matches!(encoder.append(&msgpack!([[3]])), Err(Error::DepthLimitExceeded)));
+        assert_eq!(encoder.as_bytes(), [0x01, 0x91, 0x02]);
+        encoder.reset();
+        assert!(encoder.as_bytes().is_empty());
+    }
+
+    #[test]
+    fn encodes_typed_values() {
+        let mut encoder = Encoder::new();
+        assert_eq!(encoder.encode_value(&vec![1u8, 2]).ok(), Some(&[0x92, 0x01, 0x02][..]));
+        assert_eq!(encoder.encode_value("hi").ok(), Some(&[0xa2, b'h', b'i'][..]));
+        assert_eq!(encoder.into_inner(), [0xa2, b'h', b'i']);
+    }
+}
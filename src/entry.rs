@@ -0,0 +1,89 @@
+use crate::{map_remove, Map, Value};
+
+/// A view into one key of a map value, obtained from `Value::entry`.
+pub struct Entry<'a> {
+    map: &'a mut Map,
+    key: Value
+}
+
+impl<'a> Entry<'a> {
+    pub fn key(&self) -> &Value {
+        &self.key
+    }
+
+    /// The current value, if the key is present.
+    pub fn get(&self) -> Option<&Value> {
+        self.map.get(&self.key)
+    }
+
+    pub fn or_insert(self, default: Value) -> &'a mut Value {
+        self.map.entry(self.key).or_insert(default)
+    }
+
+    pub fn or_insert_with<F: FnOnce() -> Value>(self, default: F) -> &'a mut Value {
+        self.map.entry(self.key).or_insert_with(default)
+    }
+
+    /// Inserts `Value::Null` if the key is missing.
+    pub fn or_default(self) -> &'a mut Value {
+        self.or_insert(Value::Null)
+    }
+
+    /// Runs `f` on the value if the key is present.
+    pub fn and_modify<F: FnOnce(&mut Value)>(self, f: F) -> Entry<'a> {
+        if let Some(v) = self.map.get_mut(&self.key) {
+            f(v);
+        }
+        self
+    }
+
+    /// Stores `value` under the key, returning the previous value.
+    pub fn insert(self, value: Value) -> Option<Value> {
+        self.map.insert(self.key, value)
+    }
+
+    pub fn remove(self) -> Option<Value> {
+        map_remove(self.map, &self.key)
+    }
+}
+
+impl Value {
+    /// Returns the entry for `key` if this is a map. `Value::Null` is
+    /// turned into an empty map first, so documents can be built up from
+    /// nothing; any other kind of value gives `None`.
+    pub fn entry<K: Into<Value>>(&mut self, key: K) -> Option<Entry<'_>> {
+        if let Value::Null = self {
+            *self = Value::Map(Map::default());
+        }
+        match self {
+            Value::Map(map) => Some(Entry { map, key: key.into() }),
+            _ => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Value;
+
+    #[test]
+    fn entry_operations() {
+        let mut counts = Value::Null;
+        for word in ["a", "b", "a"].iter() {
+            if let Some(e) = counts.entry(*word) {
+                e.and_modify(|n| *n = Value::Integer(2)).or_insert(Value::Integer(1));
+            }
+        }
+        assert_eq!(counts, msgpack!({"a": 2, "b": 1}));
+
+        if let Some(list) = counts.entry("list").map(|e| e.or_insert_with(|| msgpack!([]))) {
+            list.set_at("/-", Value::Boolean(true)).ok();
+        }
+        assert_eq!(counts["list"], msgpack!([true]));
+
+        assert_eq!(counts.entry("b").and_then(|e| e.remove()), Some(Value::Integer(1)));
+        assert_eq!(counts.entry("b").and_then(|e| e.get().cloned()), None);
+        assert_eq!(counts.entry("z").map(|e| e.or_default().clone()), Some(Value::Null));
+        assert!(Value::Integer(1).entry("a").is_none());
+    }
+}
@@ -0,0 +1,87 @@
+use std::hash::{BuildHasherDefault, Hasher};
+
+/// A fast, non-cryptographic hasher in the style of rustc's FxHash. It is
+/// much cheaper than the default SipHash on short keys, but an attacker
+/// who controls the keys can force collisions, so only use it for trusted
+/// input.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FastHasher {
+    hash: u64
+}
+
+const SEED: u64 = 0x51_7c_c1_b7_27_22_0a_95;
+
+impl FastHasher {
+    fn add(&mut self, word: u64) {
+        self.hash = (self.hash.rotate_left(5) ^ word).wrapping_mul(SEED);
+    }
+}
+
+impl Hasher for FastHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        let mut chunks = bytes.chunks_exact(8);
+        for chunk in &mut chunks {
+            let mut word = [0u8; 8];
+            word.copy_from_slice(chunk);
+            self.add(u64::from_le_bytes(word));
+        }
+        for b in chunks.remainder() {
+            self.add(*b as u64);
+        }
+    }
+
+    fn write_u8(&mut self, i: u8) {
+        self.add(i as u64);
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.add(i as u64);
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.add(i as u64);
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.add(i);
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.add(i as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+/// The hasher used by `Map`. It is the randomly seeded, DoS-resistant
+/// `RandomState` unless the `fast_hash` feature selects `FastHasher`.
+#[cfg(not(feature = "fast_hash"))]
+pub type MapHasher = std::collections::hash_map::RandomState;
+#[cfg(feature = "fast_hash")]
+pub type MapHasher = BuildHasherDefault<FastHasher>;
+
+/// Builds `FastHasher`s, for use with any `HashMap` or `HashSet`.
+pub type BuildFastHasher = BuildHasherDefault<FastHasher>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::hash::{BuildHasher, Hash};
+
+    fn hash<T: Hash>(t: &T) -> u64 {
+        BuildFastHasher::default().hash_one(t)
+    }
+
+    #[test]
+    fn fast_hasher_is_deterministic() {
+        assert_eq!(hash(&"name"), hash(&"name"));
+        assert_ne!(hash(&"name"), hash(&"nome"));
+        assert_ne!(hash(&"a-longer-key-than-eight"), hash(&"a-longer-key-than-eighT"));
+
+        let mut m = crate::Map::default();
+        m.insert(crate::Value::from("id"), crate::Value::Null);
+        assert!(m.contains_key(&crate::Value::from("id")));
+    }
+}
@@ -0,0 +1,120 @@
+use crate::Value;
+
+static NULL: Value = Value::Null;
+
+/// Types that can address a member of a `Value`: `usize` positions into
+/// arrays and string keys into maps.
+pub trait ValueIndex {
+    fn index_into<'a>(&self, v: &'a Value) -> Option<&'a Value>;
+    fn index_into_mut<'a>(&self, v: &'a mut Value) -> Option<&'a mut Value>;
+}
+
+impl ValueIndex for usize {
+    fn index_into<'a>(&self, v: &'a Value) -> Option<&'a Value> {
+        match v {
+            Value::Array(a) => a.get(*self),
+            _ => None
+        }
+    }
+
+    fn index_into_mut<'a>(&self, v: &'a mut Value) -> Option<&'a mut Value> {
+        match v {
+            Value::Array(a) => a.get_mut(*self),
+            _ => None
+        }
+    }
+}
+
+impl ValueIndex for str {
+    fn index_into<'a>(&self, v: &'a Value) -> Option<&'a Value> {
+        match v {
+            Value::Map(m) => m.get(&Value::String(self.to_string())),
+            _ => None
+        }
+    }
+
+    fn index_into_mut<'a>(&self, v: &'a mut Value) -> Option<&'a mut Value> {
+        match v {
+            Value::Map(m) => m.get_mut(&Value::String(self.to_string())),
+            _ => None
+        }
+    }
+}
+
+impl ValueIndex for String {
+    fn index_into<'a>(&self, v: &'a Value) -> Option<&'a Value> {
+        self.as_str().index_into(v)
+    }
+
+    fn index_into_mut<'a>(&self, v: &'a mut Value) -> Option<&'a mut Value> {
+        self.as_str().index_into_mut(v)
+    }
+}
+
+impl<T: ValueIndex + ?Sized> ValueIndex for &T {
+    fn index_into<'a>(&self, v: &'a Value) -> Option<&'a Value> {
+        (**self).index_into(v)
+    }
+
+    fn index_into_mut<'a>(&self, v: &'a mut Value) -> Option<&'a mut Value> {
+        (**self).index_into_mut(v)
+    }
+}
+
+impl Value {
+    /// Looks up an array element or a string-keyed map entry, returning
+    /// `None` when this isn't a matching container or the member is missing.
+    pub fn get<I: ValueIndex>(&self, index: I) -> Option<&Value> {
+        index.index_into(self)
+    }
+
+    pub fn get_mut<I: ValueIndex>(&mut self, index: I) -> Option<&mut Value> {
+        index.index_into_mut(self)
+    }
+}
+
+/// Indexing never panics: missing members read as `Value::Null`, so
+/// lookups like `doc["user"]["roles"][0]` can be chained.
+impl<I: ValueIndex> std::ops::Index<I> for Value {
+    type Output = Value;
+
+    fn index(&self, index: I) -> &Value {
+        index.index_into(self).unwrap_or(&NULL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::dec;
+    use crate::Value;
+
+    fn doc() -> Value {
+        let bytes = [
+            0x81, 0xa4, b'u', b's', b'e', b'r', 0x82,
+            0xa4, b'n', b'a', b'm', b'e', 0xa2, b'j', b'o',
+            0xa5, b'r', b'o', b'l', b'e', b's', 0x92, 0xa5, b'a', b'd', b'm', b'i', b'n', 0xa3, b'o', b'p', b's'
+        ];
+        dec(&bytes)
+    }
+
+    #[test]
+    fn index_chains() {
+        let doc = doc();
+        assert_eq!(doc["user"]["roles"][0], Value::String("admin".into()));
+        assert_eq!(doc["user"][String::from("name")], Value::String("jo".into()));
+        assert_eq!(doc["user"]["roles"][5], Value::Null);
+        assert_eq!(doc["nobody"]["roles"][0], Value::Null);
+        assert_eq!(doc[0], Value::Null);
+    }
+
+    #[test]
+    fn get_and_get_mut() {
+        let mut doc = doc();
+        assert!(doc.get("user").and_then(|u| u.get("missing")).is_none());
+        if let Some(name) = doc.get_mut("user").and_then(|u| u.get_mut("name")) {
+            *name = Value::String("kim".into());
+        }
+        assert_eq!(doc["user"]["name"], Value::String("kim".into()));
+        assert_eq!(doc.get("user").and_then(|u| u.get(0)), None);
+    }
+}
@@ -0,0 +1,153 @@
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use crate::{IntFormat, RawValue, Value};
+
+/// The variants of `Value` other than `InlineString`, laid out the same
+/// way, to size `INLINE_CAP` by. Element types don't change the size of
+/// a `Vec` or a map, so `()` stands in for `Value`.
+#[allow(dead_code)]
+enum Others {
+    Null,
+    Boolean(bool),
+    Integer(i128),
+    FixedInteger(i128, IntFormat),
+    Float(f64),
+    String(String),
+    SharedString(Arc<str>),
+    Bytes(Vec<u8>),
+    Array(Vec<()>),
+    Map(MapSized),
+    Ext(i8, Vec<u8>),
+    Raw(RawValue)
+}
+
+#[cfg(not(any(feature = "preserve_order", feature = "btree_map")))]
+type MapSized = std::collections::HashMap<(), (), crate::MapHasher>;
+#[cfg(feature = "preserve_order")]
+type MapSized = indexmap::IndexMap<(), (), crate::MapHasher>;
+#[cfg(all(feature = "btree_map", not(feature = "preserve_order")))]
+type MapSized = std::collections::BTreeMap<(), ()>;
+
+/// Longest string, in bytes, that an `InlineStr` holds: what fits next to
+/// the tag and the length byte in the largest other variant, so that
+/// `Value::InlineString` never makes a `Value` bigger. It depends on the
+/// map backend.
+pub const INLINE_CAP: usize = std::mem::size_of::<Others>() - 2;
+
+/// A short string stored inside the `Value` holding it instead of on the
+/// heap, see `Value::InlineString`. Keys and short strings make up most of
+/// a typical document, so decoding them this way with
+/// `DecodeOptions::inline_strings` saves an allocation for each.
+#[derive(Clone, Copy)]
+pub struct InlineStr {
+    len: u8,
+    /// Valid UTF-8 up to `len`, zeroes after.
+    bytes: [u8; INLINE_CAP]
+}
+
+impl InlineStr {
+    /// Copies `s`, or returns `None` if it is longer than `INLINE_CAP`.
+    pub fn new(s: &str) -> Option<InlineStr> {
+        if s.len() > INLINE_CAP {
+            return None;
+        }
+        let mut bytes = [0u8; INLINE_CAP];
+        bytes[..s.len()].copy_from_slice(s.as_bytes());
+        Some(InlineStr { len: s.len() as u8, bytes })
+    }
+
+    pub fn as_str(&self) -> &str {
+        // SAFETY: `new` only ever copies a whole `str` into `bytes`.
+        unsafe { std::str::from_utf8_unchecked(&self.bytes[..self.len as usize]) }
+    }
+}
+
+impl std::ops::Deref for InlineStr {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl std::fmt::Debug for InlineStr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl PartialEq for InlineStr {
+    fn eq(&self, that: &InlineStr) -> bool {
+        self.as_str() == that.as_str()
+    }
+}
+
+impl Eq for InlineStr {}
+
+impl Hash for InlineStr {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state)
+    }
+}
+
+impl Value {
+    /// A string value that avoids allocating when `s` is short enough to
+    /// be stored inline.
+    pub fn short_str(s: &str) -> Value {
+        match InlineStr::new(s) {
+            Some(inline) => Value::InlineString(inline),
+            None => Value::String(s.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{enc, ok};
+    use crate::{decode_with, DecodeOptions};
+
+    #[test]
+    fn fits_in_a_value() {
+        assert_eq!(std::mem::size_of::<Value>(), std::mem::size_of::<Others>());
+        let long = "x".repeat(INLINE_CAP + 1);
+        assert!(InlineStr::new(&long).is_none());
+        assert!(matches!(Value::short_str(&long), Value::String(_)));
+        assert!(matches!(Value::short_str(&long[1..]), Value::InlineString(ref s) if s.len() == INLINE_CAP));
+    }
+
+    #[test]
+    fn behaves_like_a_string() {
+        let inline = Value::short_str("héllo");
+        let owned = Value::from("héllo");
+        assert_eq!(inline, owned);
+        assert_eq!(inline.as_str(), Some("héllo"));
+        assert_eq!(inline.cmp(&Value::from("z")), owned.cmp(&Value::from("z")));
+        assert_eq!(inline.to_string(), owned.to_string());
+        assert_eq!(enc(&inline), enc(&owned));
+        let mut m = crate::Map::default();
+        m.insert(owned, Value::Integer(1));
+        assert_eq!(m.get(&inline), Some(&Value::Integer(1)));
+    }
+
+    #[test]
+    fn decodes_short_strings_inline() {
+        let long = "y".repeat(100);
+        let v = msgpack!({"id": 1, "tags": ["a", long.as_str()]});
+        let buf = enc(&v);
+        let opts = DecodeOptions { inline_strings: true, ..DecodeOptions::default() };
+        let decoded = ok(decode_with(&mut &buf[..], &opts));
+        assert_eq!(decoded, v);
+        assert!(decoded.entries().all(|(k, _)| matches!(k, Value::InlineString(_))));
+        let tags: Vec<&Value> = decoded.pointer("/tags").map(|t| t.members().collect()).unwrap_or_default();
+        assert!(matches!(tags[..], [Value::InlineString(_), Value::String(_)]));
+
+        let bad = [0xa2, 0xc3, 0x28];
+        let lossy = DecodeOptions { utf8: crate::Utf8Policy::Lossy, ..opts.clone() };
+        assert_eq!(decode_with(&mut &bad[..], &lossy).ok(), Some(Value::from("\u{fffd}(")));
+        assert!(decode_with(&mut &bad[..], &opts).is_err());
+        let short = DecodeOptions { max_payload_len: 1, ..opts };
+        assert!(decode_with(&mut &[0xa2, b'a', b'b'][..], &short).is_err());
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use crate::Value;
+
+/// Hands out one shared allocation per distinct string, so documents that
+/// repeat the same keys thousands of times hold each of them only once.
+#[derive(Debug, Default)]
+pub struct Interner {
+    strings: HashSet<Arc<str>>
+}
+
+impl Interner {
+    pub fn new() -> Interner {
+        Interner::default()
+    }
+
+    pub fn intern(&mut self, s: &str) -> Arc<str> {
+        if let Some(shared) = self.strings.get(s) {
+            return shared.clone();
+        }
+        let shared: Arc<str> = Arc::from(s);
+        self.strings.insert(shared.clone());
+        shared
+    }
+
+    /// The shared copy of `s`, if it was interned before.
+    pub fn get(&self, s: &str) -> Option<Arc<str>> {
+        self.strings.get(s).cloned()
+    }
+
+    /// Interns `s` as a `Value::SharedString`.
+    pub fn value(&mut self, s: &str) -> Value {
+        Value::SharedString(self.intern(s))
+    }
+
+    /// Number of distinct strings held.
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+}
+
+impl From<Arc<str>> for Value {
+    fn from(s: Arc<str>) -> Value {
+        Value::SharedString(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{dec, enc};
+
+    #[test]
+    fn shares_allocations() {
+        let mut interner = Interner::new();
+        let a = interner.intern("id");
+        let b = interner.intern("id");
+        assert!(Arc::ptr_eq(&a, &b));
+        assert_eq!(interner.len(), 1);
+        interner.intern("name");
+        assert_eq!(interner.len(), 2);
+    }
+
+    #[test]
+    fn behaves_like_string() {
+        let mut interner = Interner::new();
+        let shared = interner.value("id");
+        let owned = Value::from("id");
+        assert_eq!(shared, owned);
+        assert_eq!(shared.cmp(&owned), std::cmp::Ordering::Equal);
+        assert!(shared < Value::from("idx"));
+        assert_eq!(shared.to_string(), owned.to_string());
+
+        let mut rows = Vec::new();
+        for i in 0..3 {
+            rows.push(Value::Map(vec![(interner.value("id"), Value::from(i))].into_iter().collect()));
+        }
+        let doc = Value::Array(rows);
+        assert_eq!(doc[2]["id"], Value::Integer(2));
+        let buf = enc(&doc);
+        assert_eq!(dec(&buf), doc);
+    }
+}
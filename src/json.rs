@@ -0,0 +1,64 @@
+use std::io::{Read, Write};
+
+use crate::{Deserializer, Error, Result, Serializer};
+
+fn json_error(e: serde_json::Error) -> Error {
+    if e.is_io() {
+        Error::Io(e.into())
+    } else {
+        Error::Custom(e.to_string())
+    }
+}
+
+/// Converts one JSON document read from `r` into MessagePack written to
+/// `w`, event by event through serde-transcode, without building a
+/// `Value`. JSON doesn't announce array and object lengths, so each
+/// container is held as encoded bytes until it closes.
+pub fn json_to_msgpack<R: Read, W: Write>(r: R, w: W) -> Result<()> {
+    let mut de = serde_json::Deserializer::from_reader(r);
+    serde_transcode::transcode(&mut de, &mut Serializer::new(w))?;
+    de.end().map_err(json_error)
+}
+
+/// Converts the MessagePack value at the front of `buf` into JSON written
+/// to `w`, returning the number of bytes it spanned. Bin and ext payloads
+/// become arrays of integers, as JSON has no byte strings.
+pub fn msgpack_to_json<W: Write>(buf: &[u8], w: W) -> Result<usize> {
+    let mut de = Deserializer::new(buf);
+    serde_transcode::transcode(&mut de, &mut serde_json::Serializer::new(w)).map_err(json_error)?;
+    Ok(de.position())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{dec, enc, ok};
+    use crate::Value;
+
+    #[test]
+    fn json_round_trip() {
+        let json = r#"{"a": [1, -2, 3.5, "s", null, true], "b": {"c": []}}"#;
+        let mut packed = Vec::new();
+        ok(json_to_msgpack(json.as_bytes(), &mut packed));
+        assert_eq!(dec(&packed), msgpack!({"a": [1, -2, 3.5, "s", null, true], "b": {"c": []}}));
+        let mut out = Vec::new();
+        assert_eq!(msgpack_to_json(&packed, &mut out).ok(), Some(packed.len()));
+        let back: serde_json::Value = ok(serde_json::from_slice(&out));
+        let expected: serde_json::Value = ok(serde_json::from_str(json));
+        assert_eq!(back, expected);
+    }
+
+    #[test]
+    fn bytes_become_arrays() {
+        let packed = enc(&msgpack!([(vec![1u8, 2]), (Value::Ext(3, vec![4]))]));
+        let mut out = Vec::new();
+        assert!(msgpack_to_json(&packed, &mut out).is_ok());
+        assert_eq!(String::from_utf8_lossy(&out), "[[1,2],[3,[4]]]");
+    }
+
+    #[test]
+    fn rejects_bad_json() {
+        assert!(matches!(json_to_msgpack(&b"[1,"[..], Vec::new()), Err(Error::Custom(_))));
+        assert!(matches!(json_to_msgpack(&b"1 2"[..], Vec::new()), Err(Error::Custom(_))));
+    }
+}
@@ -1,392 +1,3183 @@
-use std::collections::HashMap;
-use std::io::Write;
+//! MessagePack encoding and decoding around a dynamic `Value` tree.
+//!
+//! The core has no dependencies. Everything else is opt-in:
+//!
+//! - `derive`: `#[derive(EncodeValue, DecodeValue)]` without serde.
+//! - `serde`: `Serializer`, `Deserializer`, `to_value` and `from_value`.
+//! - `json`: JSON transcoding; implies `serde`.
+//! - `bytes`: encode into `bytes::BufMut` and decode from `bytes::Buf`.
+//! - `chrono`: conversions between `Timestamp` and `chrono::DateTime<Utc>`.
+//! - `preserve_order`: keep map entries in insertion order (indexmap).
+//! - `btree_map`: keep map entries sorted by key.
+//! - `fast_hash`: hash map keys with `FastHasher`.
+//! - `mmap`: decode files through a memory map (memmap2).
+//! - `parallel`: encode and decode large arrays on several threads (rayon).
+//! - `simd_utf8`: validate strings with SIMD instructions (simdutf8).
 
+use std::convert::TryFrom;
+use std::io::{BufRead, IoSlice, Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use validate::Header;
+
+// Lets code generated by the derive macros name this crate from inside it.
+extern crate self as stuff;
+
+#[macro_use]
+mod macros;
+
+mod array_index;
+#[cfg(feature = "serde")]
+pub mod bin;
+#[cfg(feature = "bytes")]
+mod buf;
+mod builder;
+mod canonical;
+mod convert;
+mod cursor;
+#[cfg(feature = "serde")]
+mod de;
+mod decoder;
+mod document;
+mod encoder;
+mod entry;
+mod hash;
+mod index;
+mod inline;
+mod intern;
+#[cfg(feature = "json")]
+mod json;
+mod merge;
+#[cfg(feature = "mmap")]
+mod mmap;
+#[cfg(feature = "parallel")]
+mod parallel;
+mod patch;
+mod pointer;
+mod push;
+mod raw;
+#[cfg(feature = "serde")]
+mod ser;
+#[cfg(feature = "serde")]
+mod serde_value;
+mod shared;
+mod splice;
+mod stream;
+#[cfg(test)]
+mod test_util;
+mod timestamp;
+mod typed;
+mod utf8;
+mod validate;
+mod value_ref;
+mod writer;
+
+pub use array_index::ArrayIndex;
+#[cfg(feature = "bytes")]
+pub use buf::{get_value, get_value_with, put_value, put_value_with};
+pub use builder::{ArrayBuilder, MapBuilder};
+#[cfg(feature = "serde")]
+pub use de::{from_slice, Deserializer};
+#[cfg(feature = "derive")]
+pub use stuff_derive::{DecodeValue, EncodeValue};
+pub use cursor::Cursor;
+pub use decoder::Decoder;
+pub use document::Document;
+pub use encoder::Encoder;
+pub use entry::Entry;
+pub use hash::{BuildFastHasher, FastHasher, MapHasher};
+pub use index::ValueIndex;
+pub use inline::{InlineStr, INLINE_CAP};
+pub use intern::Interner;
+#[cfg(feature = "json")]
+pub use json::{json_to_msgpack, msgpack_to_json};
+pub use merge::MergeStrategy;
+#[cfg(feature = "mmap")]
+pub use mmap::{decode_from_path, MappedFile};
+#[cfg(feature = "parallel")]
+pub use parallel::{par_decode_from_slice, par_decode_with, par_encode_to_vec, par_encode_with};
+pub use patch::{diff, Patch, PatchOp};
+pub use push::PushParser;
+pub use raw::RawValue;
+#[cfg(feature = "serde")]
+pub use ser::{to_vec, to_vec_named, to_writer, Compound, EnumRepr, Serializer};
+#[cfg(feature = "serde")]
+pub use serde_value::{from_value, to_value, EXT_STRUCT_NAME};
+pub use shared::SharedValue;
+pub use splice::splice_at;
+pub use stream::{encode_all, encode_all_with, StreamDecoder};
+pub use timestamp::{Timestamp, TIMESTAMP_EXT};
+#[doc(hidden)]
+pub use typed::__private;
+pub use typed::{DecodeValue, EncodeValue};
+pub use validate::{validate, validate_all, validate_with};
+pub use value_ref::{encode_ref, ValueRef};
+pub use writer::{begin_array, begin_array_with, begin_map, begin_map_with, ArrayWriter, MapWriter};
+
+#[derive(Debug)]
+#[non_exhaustive]
 pub enum Error {
-    Error
+    /// The underlying reader or writer failed.
+    Io(std::io::Error),
+    /// The input ended in the middle of a value: `needed` bytes were
+    /// requested for the current item but only `got` were available.
+    UnexpectedEof { needed: usize, got: usize },
+    /// A str payload was not valid UTF-8.
+    InvalidUtf8(std::str::Utf8Error),
+    /// The reserved 0xc1 marker was found at the given stream offset,
+    /// where the decoder expected the start of `expected`.
+    ReservedMarker { offset: u64, expected: Expected },
+    /// A string, binary, array, map or ext is too long to encode.
+    TooLong,
+    /// Strict decoding found a value not written in its shortest form.
+    NonCanonical(u8),
+    /// An integer doesn't fit the format it has to be written in.
+    IntegerOutOfRange,
+    /// A map repeated a key and `DuplicateKeys::Error` was requested.
+    DuplicateKey,
+    /// Values were nested deeper than the configured limit.
+    DepthLimitExceeded,
+    /// An array or map declared more elements than
+    /// `DecodeOptions::max_container_len` allows.
+    ContainerTooLong { len: usize, limit: usize },
+    /// A str, bin or ext payload was longer than
+    /// `DecodeOptions::max_payload_len` allows.
+    PayloadTooLong { len: usize, limit: usize },
+    /// Decoding would have allocated more than `DecodeOptions::max_alloc`.
+    AllocLimitExceeded { limit: usize },
+    /// A decoded length doesn't fit in `usize` on this platform.
+    LengthOutOfRange,
+    /// A timestamp extension had a malformed payload.
+    InvalidTimestamp,
+    /// An ext type not in `DecodeOptions::known_ext` was rejected.
+    UnknownExt(i8),
+    /// A path-based edit could not be applied.
+    InvalidPath(String),
+    /// A conversion out of `Value` found a different kind of value.
+    TypeMismatch { expected: &'static str, found: &'static str },
+    /// Operation `index` of a patch could not be applied; the document was
+    /// left unchanged.
+    PatchFailed { index: usize, reason: String },
+    /// A message from a serde `Serialize` or `Deserialize` implementation.
+    Custom(String),
+    /// A typed decode found no value for a required field.
+    MissingField(&'static str),
+    /// A typed decode found an enum variant it doesn't know.
+    UnknownVariant(String),
+    /// This many bytes followed a value that was meant to fill the input.
+    TrailingBytes(usize),
+    /// `encode_to_slice` was given `available` bytes but the value takes
+    /// `needed`.
+    BufferTooSmall { needed: usize, available: usize },
+    /// A streamed array or map got a different number of members than its
+    /// header declared.
+    LengthMismatch { declared: usize, written: usize },
+    /// A streamed array or map was ended after one of its members failed
+    /// part way through, or while a nested container was left unended, so
+    /// what it wrote is unusable.
+    Unfinished,
+    /// A decode failure, with the number of bytes consumed when it was
+    /// detected and the path to the offending value, e.g. `$.items[3].name`.
+    Decode { offset: u64, path: String, error: Box<Error> }
+}
+
+/// What the decoder was about to read when it hit a bad marker. Every
+/// marker except 0xc1 may start any of these.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Expected {
+    Value,
+    ArrayElement,
+    MapKey,
+    MapValue
+}
+
+impl std::fmt::Display for Expected {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Expected::Value => write!(f, "value"),
+            Expected::ArrayElement => write!(f, "array element"),
+            Expected::MapKey => write!(f, "map key"),
+            Expected::MapValue => write!(f, "map value")
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::UnexpectedEof { needed, got } => {
+                write!(f, "unexpected end of input: needed {} bytes, got {}", needed, got)
+            },
+            Error::InvalidUtf8(e) => write!(f, "invalid utf-8 in string: {}", e),
+            Error::ReservedMarker { offset, expected } => {
+                write!(f, "expected {}, got reserved marker 0xc1 at byte {}", expected, offset)
+            },
+            Error::TooLong => write!(f, "length exceeds the 32-bit limit of the format"),
+            Error::NonCanonical(m) => write!(f, "non-canonical encoding with marker {:#04x}", m),
+            Error::IntegerOutOfRange => write!(f, "integer out of range for its format"),
+            Error::DuplicateKey => write!(f, "duplicate map key"),
+            Error::DepthLimitExceeded => write!(f, "nesting depth limit exceeded"),
+            Error::ContainerTooLong { len, limit } => {
+                write!(f, "container of {} elements exceeds the limit of {}", len, limit)
+            },
+            Error::PayloadTooLong { len, limit } => write!(f, "payload of {} bytes exceeds the limit of {}", len, limit),
+            Error::AllocLimitExceeded { limit } => write!(f, "decoding would allocate more than {} bytes", limit),
+            Error::LengthOutOfRange => write!(f, "length does not fit in usize"),
+            Error::InvalidTimestamp => write!(f, "invalid timestamp extension"),
+            Error::UnknownExt(t) => write!(f, "unknown ext type {}", t),
+            Error::InvalidPath(reason) => write!(f, "invalid path {}", reason),
+            Error::TypeMismatch { expected, found } => write!(f, "expected {}, found {}", expected, found),
+            Error::PatchFailed { index, reason } => write!(f, "patch operation {} failed: {}", index, reason),
+            Error::Custom(msg) => write!(f, "{}", msg),
+            Error::MissingField(name) => write!(f, "missing field {}", name),
+            Error::UnknownVariant(name) => write!(f, "unknown variant {}", name),
+            Error::TrailingBytes(len) => write!(f, "{} bytes after the end of the value", len),
+            Error::BufferTooSmall { needed, available } => {
+                write!(f, "value needs {} bytes but the buffer holds {}", needed, available)
+            },
+            Error::LengthMismatch { declared, written } => {
+                write!(f, "container declared {} members but {} were written", declared, written)
+            },
+            Error::Unfinished => write!(f, "container has a member that wasn't fully written"),
+            Error::Decode { offset, path, error } => write!(f, "{} at {} (byte {})", error, path, offset)
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::InvalidUtf8(e) => Some(e),
+            Error::Decode { error, .. } => Some(error.as_ref()),
+            _ => None
+        }
+    }
+}
+
+impl Error {
+    /// Strips any decode context, returning the underlying error.
+    pub fn into_root(self) -> Error {
+        match self {
+            Error::Decode { error, .. } => error.into_root(),
+            e => e
+        }
+    }
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+#[derive(Clone, Debug, Default)]
 pub enum Value {
+    #[default]
     Null,
     Boolean(bool),
     Integer(i128),
+    /// An integer pinned to a specific wire format, see `IntFormat`.
+    FixedInteger(i128, IntFormat),
     Float(f64),
     String(String),
+    /// A string sharing its allocation with other values, e.g. one handed
+    /// out by an `Interner`. It compares, hashes and encodes exactly like
+    /// `Value::String`.
+    SharedString(Arc<str>),
+    /// A short string stored without a heap allocation, see `InlineStr`.
+    /// It too compares, hashes and encodes exactly like `Value::String`.
+    InlineString(InlineStr),
     Bytes(Vec<u8>),
     Array(Vec<Value>),
-    Map(HashMap<Value, Value>)
+    Map(Map),
+    Ext(i8, Vec<u8>),
+    /// An already-encoded value, written out verbatim; see `RawValue`.
+    Raw(RawValue)
 }
 
-impl PartialEq for Value {
-    fn eq(&self, that: &Value) -> bool {
+/// The map type behind `Value::Map`. Iteration order is unspecified by
+/// default. The `preserve_order` feature keeps entries in the order they
+/// were inserted or decoded; otherwise the `btree_map` feature keeps them
+/// sorted by key, without any extra dependency.
+/// Hashed maps use `MapHasher`.
+#[cfg(not(any(feature = "preserve_order", feature = "btree_map")))]
+pub type Map = std::collections::HashMap<Value, Value, MapHasher>;
+#[cfg(feature = "preserve_order")]
+pub type Map = indexmap::IndexMap<Value, Value, MapHasher>;
+#[cfg(all(feature = "btree_map", not(feature = "preserve_order")))]
+pub type Map = std::collections::BTreeMap<Value, Value>;
+
+#[cfg(not(any(feature = "preserve_order", feature = "btree_map")))]
+type MapIter<'a> = std::collections::hash_map::Iter<'a, Value, Value>;
+#[cfg(feature = "preserve_order")]
+type MapIter<'a> = indexmap::map::Iter<'a, Value, Value>;
+#[cfg(all(feature = "btree_map", not(feature = "preserve_order")))]
+type MapIter<'a> = std::collections::btree_map::Iter<'a, Value, Value>;
+
+/// Number of entries the map has room for.
+fn map_capacity(m: &Map) -> usize {
+    #[cfg(all(feature = "btree_map", not(feature = "preserve_order")))]
+    return m.len();
+    #[cfg(not(all(feature = "btree_map", not(feature = "preserve_order"))))]
+    return m.capacity();
+}
+
+/// An empty map with room for `len` entries where the backend supports it.
+fn map_with_capacity(len: usize) -> Map {
+    #[cfg(all(feature = "btree_map", not(feature = "preserve_order")))]
+    return { let _ = len; Map::default() };
+    #[cfg(not(all(feature = "btree_map", not(feature = "preserve_order"))))]
+    return Map::with_capacity_and_hasher(len, MapHasher::default());
+}
+
+/// Removes an entry, keeping the order of the others under
+/// `preserve_order`.
+pub(crate) fn map_remove(m: &mut Map, key: &Value) -> Option<Value> {
+    #[cfg(feature = "preserve_order")]
+    return m.shift_remove(key);
+    #[cfg(not(feature = "preserve_order"))]
+    return m.remove(key);
+}
+
+/// The sized integer formats of the wire format. Integers carrying one of
+/// these are always written with that marker rather than the shortest one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IntFormat {
+    U8,
+    U16,
+    U32,
+    U64,
+    I8,
+    I16,
+    I32,
+    I64
+}
+
+impl IntFormat {
+    fn from_marker(marker: u8) -> Option<IntFormat> {
+        match marker {
+            0xcc => Some(IntFormat::U8),
+            0xcd => Some(IntFormat::U16),
+            0xce => Some(IntFormat::U32),
+            0xcf => Some(IntFormat::U64),
+            0xd0 => Some(IntFormat::I8),
+            0xd1 => Some(IntFormat::I16),
+            0xd2 => Some(IntFormat::I32),
+            0xd3 => Some(IntFormat::I64),
+            _ => None
+        }
+    }
+}
+
+impl Value {
+    /// Moves the value out, leaving `Value::Null` in its place, so members
+    /// of a decoded tree can be taken without cloning.
+    pub fn take(&mut self) -> Value {
+        std::mem::take(self)
+    }
+
+    /// The contents of a `String`, `SharedString` or `InlineString`.
+    pub fn as_str(&self) -> Option<&str> {
         match self {
-            Value::Null => match that {
-                Value::Null => true,
-                _ => false
-            },
-            Value::Boolean(b) => match that {
-                Value::Boolean(b2) => b == b2,
-                _ => false
+            Value::String(s) => Some(s),
+            Value::SharedString(s) => Some(s),
+            Value::InlineString(s) => Some(s),
+            _ => None
+        }
+    }
+
+    /// Stores `value` here and returns the previous value.
+    pub fn replace(&mut self, value: Value) -> Value {
+        std::mem::replace(self, value)
+    }
+
+    /// Approximate memory held by this value: its own size plus every heap
+    /// allocation below it, counted by capacity. Map tables are estimated
+    /// at one control byte per slot on top of the entries themselves.
+    pub fn deep_size_of(&self) -> usize {
+        std::mem::size_of::<Value>() + self.heap_size()
+    }
+
+    fn heap_size(&self) -> usize {
+        match self {
+            Value::String(s) => s.capacity(),
+            Value::SharedString(s) => s.len(),
+            Value::Bytes(b) | Value::Ext(_, b) => b.capacity(),
+            Value::Raw(raw) => raw.as_bytes().len(),
+            Value::Array(a) => {
+                a.capacity() * std::mem::size_of::<Value>() + a.iter().map(Value::heap_size).sum::<usize>()
             },
-            Value::Integer(i) => match that {
-                Value::Integer(i2) => i == i2,
-                _ => false
+            Value::Map(m) => {
+                let slot = std::mem::size_of::<(Value, Value)>() + 1;
+                map_capacity(m) * slot + m.iter().map(|(k, v)| k.heap_size() + v.heap_size()).sum::<usize>()
             },
-            Value::Float(f) => match that {
-                Value::Float(f2) => f == f2,
-                _ => false
+            _ => 0
+        }
+    }
+
+    /// Iterates over the elements of an array; other values have none.
+    pub fn members(&self) -> std::slice::Iter<'_, Value> {
+        match self {
+            Value::Array(a) => a.iter(),
+            _ => [].iter()
+        }
+    }
+
+    pub fn members_mut(&mut self) -> std::slice::IterMut<'_, Value> {
+        match self {
+            Value::Array(a) => a.iter_mut(),
+            _ => [].iter_mut()
+        }
+    }
+
+    /// Iterates over the entries of a map, in no particular order; other
+    /// values have none.
+    pub fn entries(&self) -> impl Iterator<Item = (&Value, &Value)> {
+        match self {
+            Value::Map(m) => Some(m.iter()),
+            _ => None
+        }.into_iter().flatten()
+    }
+
+    pub fn entries_mut(&mut self) -> impl Iterator<Item = (&Value, &mut Value)> {
+        match self {
+            Value::Map(m) => Some(m.iter_mut()),
+            _ => None
+        }.into_iter().flatten()
+    }
+}
+
+/// Floats compare by bit pattern, so `NaN` equals itself and `-0.0` differs
+/// from `0.0`, keeping equality consistent with `Hash` and `Ord`.
+impl PartialEq for Value {
+    fn eq(&self, that: &Value) -> bool {
+        match (self, that) {
+            (Value::Null, Value::Null) => true,
+            (Value::Boolean(b), Value::Boolean(b2)) => b == b2,
+            (Value::Integer(i), Value::Integer(i2)) => i == i2,
+            (Value::Integer(i), Value::FixedInteger(i2, _)) => i == i2,
+            (Value::FixedInteger(i, _), Value::Integer(i2)) => i == i2,
+            (Value::FixedInteger(i, _), Value::FixedInteger(i2, _)) => i == i2,
+            (Value::Float(f), Value::Float(f2)) => f.to_bits() == f2.to_bits(),
+            (Value::String(s), Value::String(s2)) => s == s2,
+            (Value::SharedString(s), Value::SharedString(s2)) => s == s2,
+            (Value::String(_), _) | (Value::SharedString(_), _) | (Value::InlineString(_), _) => {
+                that.as_str().is_some() && self.as_str() == that.as_str()
             },
-            Value::String(s) => match that {
-                Value::String(s2) => s == s2,
-                _ => false
+            (Value::Bytes(b), Value::Bytes(b2)) => b == b2,
+            (Value::Array(a), Value::Array(a2)) => a == a2,
+            (Value::Map(m), Value::Map(m2)) => {
+                m.len() == m2.len() && m.iter().all(|(k, v)| m2.get(k) == Some(v))
             },
-            Value::Bytes(b) => match that {
-                Value::Bytes(b2) => b == b2,
-                _ => false
+            (Value::Ext(t, d), Value::Ext(t2, d2)) => t == t2 && d == d2,
+            (Value::Raw(r), Value::Raw(r2)) => r == r2,
+            _ => false
+        }
+    }
+}
+
+impl Eq for Value {}
+
+impl Value {
+    /// Position of each kind of value in the ordering across variants.
+    fn rank(&self) -> u8 {
+        match self {
+            Value::Null => 0,
+            Value::Boolean(_) => 1,
+            Value::Integer(_) | Value::FixedInteger(..) => 2,
+            Value::Float(_) => 3,
+            Value::String(_) | Value::SharedString(_) | Value::InlineString(_) => 4,
+            Value::Bytes(_) => 5,
+            Value::Array(_) => 6,
+            Value::Map(_) => 7,
+            Value::Ext(..) => 8,
+            Value::Raw(_) => 9
+        }
+    }
+}
+
+fn sorted_entries(m: &Map) -> Vec<(&Value, &Value)> {
+    let mut entries: Vec<_> = m.iter().collect();
+    entries.sort();
+    entries
+}
+
+/// A total order over all values: first by kind, in the order null,
+/// boolean, integer, float, string, bytes, array, map, ext, raw, then by
+/// contents. Floats use `f64::total_cmp`; maps compare their entries in
+/// sorted order.
+impl Ord for Value {
+    fn cmp(&self, that: &Value) -> std::cmp::Ordering {
+        match (self, that) {
+            (Value::Boolean(b), Value::Boolean(b2)) => b.cmp(b2),
+            (Value::Integer(i), Value::Integer(i2))
+            | (Value::Integer(i), Value::FixedInteger(i2, _))
+            | (Value::FixedInteger(i, _), Value::Integer(i2))
+            | (Value::FixedInteger(i, _), Value::FixedInteger(i2, _)) => i.cmp(i2),
+            (Value::Float(f), Value::Float(f2)) => f.total_cmp(f2),
+            (Value::String(_), _) | (Value::SharedString(_), _) | (Value::InlineString(_), _) if that.rank() == 4 => {
+                self.as_str().cmp(&that.as_str())
             },
-            Value::Array(a) => match that {
-                Value::Array(a2) => a == a2,
-                _ => false
+            (Value::Bytes(b), Value::Bytes(b2)) => b.cmp(b2),
+            (Value::Array(a), Value::Array(a2)) => a.cmp(a2),
+            (Value::Map(m), Value::Map(m2)) => sorted_entries(m).cmp(&sorted_entries(m2)),
+            (Value::Ext(t, d), Value::Ext(t2, d2)) => (t, d).cmp(&(t2, d2)),
+            (Value::Raw(r), Value::Raw(r2)) => r.as_bytes().cmp(r2.as_bytes()),
+            _ => self.rank().cmp(&that.rank())
+        }
+    }
+}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, that: &Value) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(that))
+    }
+}
+
+fn write_json_str(f: &mut std::fmt::Formatter, s: &str) -> std::fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?
+        }
+    }
+    write!(f, "\"")
+}
+
+fn write_hex(f: &mut std::fmt::Formatter, b: &[u8]) -> std::fmt::Result {
+    write!(f, "h\"")?;
+    for byte in b {
+        write!(f, "{:02x}", byte)?;
+    }
+    write!(f, "\"")
+}
+
+/// Renders a JSON-like text form for logs: bytes appear as `h"00ff"` and
+/// ext values as `{"$ext":5,"data":h"00ff"}`. Map entries follow the
+/// map's iteration order.
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::Integer(i) | Value::FixedInteger(i, _) => write!(f, "{}", i),
+            Value::Float(x) => write!(f, "{:?}", x),
+            Value::String(s) => write_json_str(f, s),
+            Value::SharedString(s) => write_json_str(f, s),
+            Value::InlineString(s) => write_json_str(f, s),
+            Value::Bytes(b) => write_hex(f, b),
+            Value::Array(a) => {
+                write!(f, "[")?;
+                for (i, v) in a.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", v)?;
+                }
+                write!(f, "]")
             },
-            Value::Map(m) => match that {
-                Value::Map(m2) => {
-                    if m.len() == m2.len() {
-                        let mut eq = true;
-                        for (k, v) in m {
-                            match m2.get(k) {
-                                Some(v2) => eq = eq && v == v2,
-                                None => eq = false
-                            }
-                        }
-                        eq
-                    } else {
-                        false
+            Value::Map(m) => {
+                write!(f, "{{")?;
+                for (i, (k, v)) in m.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
                     }
-                },
-                _ => false
+                    write!(f, "{}:{}", k, v)?;
+                }
+                write!(f, "}}")
+            },
+            Value::Ext(t, d) => {
+                write!(f, "{{\"$ext\":{},\"data\":", t)?;
+                write_hex(f, d)?;
+                write!(f, "}}")
+            },
+            Value::Raw(raw) => {
+                write!(f, "{{\"$raw\":")?;
+                write_hex(f, raw.as_bytes())?;
+                write!(f, "}}")
             }
         }
     }
 }
 
-impl Eq for Value {}
-
 impl std::hash::Hash for Value {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         match self {
             Value::Null => 0.hash(state),
             Value::Boolean(b) => b.hash(state),
             Value::Integer(i) => i.hash(state),
+            Value::FixedInteger(i, _) => i.hash(state),
             Value::Float(f) => f.to_bits().hash(state),
             Value::String(s) => s.hash(state),
+            Value::SharedString(s) => s.hash(state),
+            Value::InlineString(s) => s.hash(state),
             Value::Bytes(b) => b.hash(state),
             Value::Array(a) => a.hash(state),
-            Value::Map(m) => for (k, v) in m {
-                k.hash(state);
-                v.hash(state);
-            }
+            Value::Map(m) => {
+                // Combine entry hashes order-independently, as equal maps
+                // may iterate in different orders.
+                let mut sum: u64 = 0;
+                for entry in m {
+                    let mut h = std::collections::hash_map::DefaultHasher::new();
+                    entry.hash(&mut h);
+                    sum = sum.wrapping_add(std::hash::Hasher::finish(&h));
+                }
+                m.len().hash(state);
+                sum.hash(state);
+            },
+            Value::Ext(t, d) => {
+                t.hash(state);
+                d.hash(state);
+            },
+            Value::Raw(raw) => raw.as_bytes().hash(state)
         }
     }
 }
 
 impl From<std::io::Error> for Error {
-    fn from(_e: std::io::Error) -> Error {
-        Error::Error
+    fn from(e: std::io::Error) -> Error {
+        Error::Io(e)
     }
 }
 
 impl From<std::string::FromUtf8Error> for Error {
-    fn from(_e: std::string::FromUtf8Error) -> Error {
-        Error::Error
+    fn from(e: std::string::FromUtf8Error) -> Error {
+        Error::InvalidUtf8(e.utf8_error())
     }
 }
 
-pub fn encode_to(w: &mut dyn Write, value: Value) -> Result<()> {
-    match value {
-        Value::Null => {
-            w.write(&[0xc0])?;
+/// Default for `EncodeOptions::max_depth` and `DecodeOptions::max_depth`.
+pub const DEFAULT_MAX_DEPTH: usize = 128;
+
+/// Knobs controlling how `encode_with` chooses among equivalent encodings.
+#[derive(Debug, Clone)]
+pub struct EncodeOptions {
+    /// Emit float32 instead of float64 when the value survives the
+    /// round trip through `f32` unchanged.
+    pub prefer_f32: bool,
+    /// Extension type code used for integers outside the i64/u64 range,
+    /// written as fixext16 holding the big-endian i128. When `None`, such
+    /// integers fail with `Error::IntegerOutOfRange`.
+    pub int128_ext: Option<i8>,
+    /// Write every NaN with the single bit pattern of `f64::NAN`.
+    pub canonical_nan: bool,
+    /// Write `-0.0` as `0.0`.
+    pub normalize_negative_zero: bool,
+    /// Maximum number of nested arrays and maps; deeper values fail with
+    /// `Error::DepthLimitExceeded`. Encoding doesn't recurse, so this can
+    /// be raised for legitimately deep documents, but dropping, cloning and
+    /// comparing a `Value` do, so one far deeper than the default can
+    /// still exhaust the stack elsewhere.
+    pub max_depth: usize,
+    /// Only emit the pre-2013 raw family (fixraw, raw16, raw32) for
+    /// strings and bytes, for peers that predate str8 and bin.
+    pub legacy_raw: bool,
+    /// Write map entries in ascending key order, as defined by `Ord` for
+    /// `Value`, so that equal maps always encode to the same bytes.
+    pub sort_keys: bool
+}
+
+impl Default for EncodeOptions {
+    fn default() -> EncodeOptions {
+        EncodeOptions {
+            prefer_f32: false,
+            int128_ext: None,
+            canonical_nan: false,
+            normalize_negative_zero: false,
+            max_depth: DEFAULT_MAX_DEPTH,
+            legacy_raw: false,
+            sort_keys: false
+        }
+    }
+}
+
+/// Every length field in the format is at most 32 bits wide.
+fn check_len(len: usize) -> Result<usize> {
+    match u32::try_from(len) {
+        Ok(_) => Ok(len),
+        Err(_) => Err(Error::TooLong)
+    }
+}
+
+fn encode_int128<W: Write + ?Sized>(w: &mut W, i: i128, opts: &EncodeOptions) -> Result<()> {
+    match opts.int128_ext {
+        Some(t) => {
+            w.write_all(&[0xd8, t as u8])?;
+            w.write_all(&i.to_be_bytes())?;
             Ok(())
         },
-        Value::Boolean(b) => {
-            let v: u8 = if b { 0xc3 } else { 0xc2 };
-            w.write(&[v])?;
-            Ok(())
+        None => Err(Error::IntegerOutOfRange)
+    }
+}
+
+pub(crate) fn encode_fixed_int<W: Write + ?Sized>(w: &mut W, i: i128, format: IntFormat) -> Result<()> {
+    fn narrow<T: TryFrom<i128>>(i: i128) -> Result<T> {
+        T::try_from(i).map_err(|_| Error::IntegerOutOfRange)
+    }
+    match format {
+        IntFormat::U8 => w.write_all(&[0xcc, narrow::<u8>(i)?])?,
+        IntFormat::U16 => {
+            w.write_all(&[0xcd])?;
+            w.write_all(&narrow::<u16>(i)?.to_be_bytes())?;
         },
-        Value::Integer(i) => {
-            if 0 <= i && i <= 0x7f {
-                w.write(&[i as u8])?;
-                Ok(())
-            } else if -32 <= i && i <= -1 {
-                w.write(&[i as u8])?;
-                Ok(())
-            } else if -128 <= i && i <= 127 {
-                w.write(&[0xd0, i as u8])?;
-                Ok(())
-            } else if -32768 <= i && i <= 32767 {
-                w.write(&[0xd1])?;
-                w.write(&(i as u16).to_be_bytes())?;
-                Ok(())
-            } else if -2147483648 <= i && i <= 2147483647 {
-                w.write(&[0xd2])?;
-                w.write(&(i as u32).to_be_bytes())?;
-                Ok(())
-            } else {
-                w.write(&[0xd3])?;
-                w.write(&i.to_be_bytes());
-                Ok(())
-            }
-        }
-        Value::Float(f) => {
-            w.write(&[0xcb]);
-            w.write(&f.to_bits().to_be_bytes());
-            Ok(())
+        IntFormat::U32 => {
+            w.write_all(&[0xce])?;
+            w.write_all(&narrow::<u32>(i)?.to_be_bytes())?;
         },
-        Value::String(s) => {
-            let len = s.len();
-            if s.len() <= 31 {
-                w.write(&[0xa0 | len as u8])?;
-                w.write(&s.as_bytes())?;
-                Ok(())
-            } else if s.len() <= 255 {
-                w.write(&[0xd9, len as u8])?;
-                w.write(&s.as_bytes())?;
-                Ok(())
-            } else if s.len() <= 65535 {
-                w.write(&[0xd9])?;
-                w.write(&(len as u16).to_be_bytes())?;
-                w.write(&s.as_bytes())?;
-                Ok(())
-            } else {
-                w.write(&[0xd9])?;
-                w.write(&(len as u32).to_be_bytes())?;
-                w.write(&s.as_bytes())?;
-                Ok(())
-            }
+        IntFormat::U64 => {
+            w.write_all(&[0xcf])?;
+            w.write_all(&narrow::<u64>(i)?.to_be_bytes())?;
         },
-        Value::Bytes(b) => {
-            let len = b.len();
-            if len <= 255 {
-                w.write(&[0xc4, len as u8])?;
-                w.write(&b)?;
-                Ok(())
-            } else if len <= 65535 {
-                w.write(&[0xc5])?;
-                w.write(&(len as u16).to_be_bytes())?;
-                w.write(&b)?;
-                Ok(())
-            } else {
-                let buf = [0xc6];
-                w.write(&[0xc6])?;
-                w.write(&(len as u32).to_be_bytes())?;
-                w.write(&b)?;
-                Ok(())
-            }
+        IntFormat::I8 => w.write_all(&[0xd0, narrow::<i8>(i)? as u8])?,
+        IntFormat::I16 => {
+            w.write_all(&[0xd1])?;
+            w.write_all(&narrow::<i16>(i)?.to_be_bytes())?;
         },
-        Value::Array(a) => {
-            let len = a.len();
-            if len <= 15 {
-                w.write(&[0x90 | len as u8])?;
-                for v in a {
-                    encode_to(w, v)?;
-                }
-                Ok(())
-            } else if len <= 65535 {
-                w.write(&[0xdc])?;
-                w.write(&(len as u16).to_be_bytes())?;
-                for v in a {
-                    encode_to(w, v)?;
-                }
-                Ok(())
-            } else {
-                w.write(&[0xdd])?;
-                w.write(&(len as u16).to_be_bytes())?;
-                for v in a {
-                    encode_to(w, v)?;
-                }
-                Ok(())
-            }
-        }
-        Value::Map(m) => {
-            let len = m.len();
-            if len <= 15 {
-                w.write(&[0x80 | len as u8])?;
-                for (k, v) in m {
-                    encode_to(w, k)?;
-                    encode_to(w, v)?;
-                }
-                Ok(())
-            } else if len <= 65535 {
-                w.write(&[0xde])?;
-                w.write(&(len as u16).to_be_bytes())?;
-                for (k, v) in m {
-                    encode_to(w, k)?;
-                    encode_to(w, v)?;
-                }
-                Ok(())
-            } else {
-                w.write(&[0xdf])?;
-                w.write(&(len as u32).to_be_bytes())?;
-                for (k, v) in m {
-                    encode_to(w, k)?;
-                    encode_to(w, v)?;
-                }
-                Ok(())
-            }
+        IntFormat::I32 => {
+            w.write_all(&[0xd2])?;
+            w.write_all(&narrow::<i32>(i)?.to_be_bytes())?;
+        },
+        IntFormat::I64 => {
+            w.write_all(&[0xd3])?;
+            w.write_all(&narrow::<i64>(i)?.to_be_bytes())?;
         }
     }
+    Ok(())
 }
 
-pub fn decode_from(r: &mut dyn std::io::Read) -> Result<Value> {
-    let mut b: u8 = 0;
-    r.read(std::slice::from_mut(&mut b))?;
-    match b {
-        0x00..=0x7f => Ok(Value::Integer(b as i128)),
-        0x80..=0x8f => {
-            let len = b & 0xf;
-            let mut m = HashMap::new();
-            for _i in 0..len {
-                let k = decode_from(r)?;
-                let v = decode_from(r)?;
-                m.insert(k, v);
-            }
-            Ok(Value::Map(m))
+pub(crate) fn check_depth(opts: &EncodeOptions, depth: usize) -> Result<()> {
+    if depth >= opts.max_depth {
+        Err(Error::DepthLimitExceeded)
+    } else {
+        Ok(())
+    }
+}
+
+/// Encodes `value` into `w`. Generic so each writer gets its own inlined
+/// copy; a `&mut dyn Write` still works where a single copy is preferred.
+pub fn encode_to<W: Write + ?Sized>(w: &mut W, value: &Value) -> Result<()> {
+    encode_with(w, value, &EncodeOptions::default())
+}
+
+/// Encodes `value` into a new buffer. The encoded length is measured
+/// first, so the buffer is allocated once at exactly the right size
+/// instead of growing and copying as the output is written.
+pub fn encode_to_vec(value: &Value) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(value.encoded_len()?);
+    encode_to(&mut buf, value)?;
+    Ok(buf)
+}
+
+pub fn encode_with<W: Write + ?Sized>(w: &mut W, value: &Value, opts: &EncodeOptions) -> Result<()> {
+    encode_value(w, value, opts)
+}
+
+/// Encodes `value` into the front of `buf`, returning the number of bytes
+/// written, for fixed buffers such as shared-memory rings. If the value
+/// doesn't fit this fails with `Error::BufferTooSmall`, and what was
+/// written to `buf` should be ignored.
+pub fn encode_to_slice(value: &Value, buf: &mut [u8]) -> Result<usize> {
+    encode_slice_with(value, buf, &EncodeOptions::default())
+}
+
+pub fn encode_slice_with(value: &Value, buf: &mut [u8], opts: &EncodeOptions) -> Result<usize> {
+    let available = buf.len();
+    let mut rest = &mut buf[..];
+    match encode_with(&mut rest, value, opts) {
+        Ok(()) => Ok(available - rest.len()),
+        Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::WriteZero => {
+            Err(Error::BufferTooSmall { needed: value.encoded_len_with(opts)?, available })
         },
-        0x90..=0x9f => {
-            let len = b & 0xf;
-            let mut v = Vec::new();
-            for _i in 0..len {
-                v.push(decode_from(r)?);
+        Err(e) => Err(e)
+    }
+}
+
+/// A writer that only counts the bytes it is given.
+struct ByteCounter(usize);
+
+impl Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice]) -> std::io::Result<usize> {
+        let len = bufs.iter().map(|b| b.len()).sum();
+        self.0 += len;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Value {
+    /// The exact number of bytes `encode_to` would write for this value,
+    /// computed without buffering any output. Fails where encoding would.
+    pub fn encoded_len(&self) -> Result<usize> {
+        self.encoded_len_with(&EncodeOptions::default())
+    }
+
+    pub fn encoded_len_with(&self, opts: &EncodeOptions) -> Result<usize> {
+        let mut counter = ByteCounter(0);
+        encode_with(&mut counter, self, opts)?;
+        Ok(counter.0)
+    }
+}
+
+pub(crate) fn encode_str<W: Write + ?Sized>(w: &mut W, s: &str, opts: &EncodeOptions) -> Result<()> {
+    let len = check_len(s.len())?;
+    let mut header = [0u8; 5];
+    let n = if s.len() <= 31 {
+        header[0] = 0xa0 | len as u8;
+        1
+    } else if s.len() <= 255 && !opts.legacy_raw {
+        header[..2].copy_from_slice(&[0xd9, len as u8]);
+        2
+    } else if s.len() <= 65535 {
+        header[0] = 0xda;
+        header[1..3].copy_from_slice(&(len as u16).to_be_bytes());
+        3
+    } else {
+        header[0] = 0xdb;
+        header[1..5].copy_from_slice(&(len as u32).to_be_bytes());
+        5
+    };
+    write_payload(w, &header[..n], s.as_bytes())
+}
+
+/// Payloads at least this long go out together with their header in one
+/// vectored write, so a socket sees a single write without the payload
+/// being copied next to the header first.
+const VECTORED_MIN_LEN: usize = 4096;
+
+/// Payloads up to this long are copied next to their header and written
+/// in one call, as keys and short strings make up most small messages.
+/// Leaves room for the longest header in a 64-byte staging buffer.
+const COALESCE_MAX_LEN: usize = 59;
+
+fn write_payload<W: Write + ?Sized>(w: &mut W, mut header: &[u8], mut payload: &[u8]) -> Result<()> {
+    if payload.len() <= COALESCE_MAX_LEN {
+        let mut buf = [0u8; COALESCE_MAX_LEN + 5];
+        let n = header.len() + payload.len();
+        buf[..header.len()].copy_from_slice(header);
+        buf[header.len()..n].copy_from_slice(payload);
+        w.write_all(&buf[..n])?;
+        return Ok(());
+    }
+    if payload.len() >= VECTORED_MIN_LEN {
+        while !header.is_empty() {
+            match w.write_vectored(&[IoSlice::new(header), IoSlice::new(payload)]) {
+                Ok(0) => return Err(std::io::Error::from(std::io::ErrorKind::WriteZero).into()),
+                Ok(n) if n < header.len() => header = &header[n..],
+                Ok(n) => {
+                    payload = &payload[n - header.len()..];
+                    header = &[];
+                },
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e.into())
             }
-            Ok(Value::Array(v))
-        },
-        0xa0..=0xbf => {
-            let len = b & 0x1f;
-            let mut v = vec![0 as u8; len as usize];
-            r.read(&mut v)?;
-            Ok(Value::String(String::from_utf8(v)?))
-        },
-        0xc0 => Ok(Value::Null),
-        0xc1 => Err(Error::Error),
-        0xc2 => Ok(Value::Boolean(false)),
-        0xc3 => Ok(Value::Boolean(true)),
-        0xc4 => {
-            let mut len: u8 = 0;
-            r.read(std::slice::from_mut(&mut len))?;
-            let mut v = vec![0 as u8; len as usize];
-            r.read(&mut v)?;
-            Ok(Value::Bytes(v))
-        },
-        0xc5 => {
-            let mut l = [0 as u8; 2];
-            r.read(&mut l)?;
-            let len = u16::from_be_bytes(l);
-            let mut v = vec![0 as u8; len as usize];
-            r.read(&mut v)?;
-            Ok(Value::Bytes(v))
-        },
-        0xc6 => {
-            let mut l = [0 as u8; 4];
-            r.read(&mut l)?;
-            let len = u32::from_be_bytes(l);
-            let mut v = vec![0 as u8; len as usize];
-            r.read(&mut v)?;
-            Ok(Value::Bytes(v))
-        },
-        0xc7 => unimplemented!(),
-        0xc8 => unimplemented!(),
-        0xc9 => unimplemented!(),
-        0xca => {
-            let mut buf = [0 as u8; 4];
-            r.read(&mut buf)?;
-            Ok(Value::Float(f32::from_be_bytes(buf) as f64))
-        },
-        0xcb => {
-            let mut buf = [0 as u8; 8];
-            r.read(&mut buf)?;
-            Ok(Value::Float(f64::from_be_bytes(buf)))
-        },
-        0xcc => {
-            let mut v: u8 = 0;
-            r.read(std::slice::from_mut(&mut v))?;
-            Ok(Value::Integer(v as i128))
         }
-        0xcd => {
-            let mut buf = [0 as u8; 2];
-            r.read(&mut buf)?;
-            Ok(Value::Integer(u16::from_be_bytes(buf) as i128))
-        },
-        0xce => {
-            let mut buf = [0 as u8; 4];
-            r.read(&mut buf)?;
-            Ok(Value::Integer(u32::from_be_bytes(buf) as i128))
-        },
-        0xcf => {
-            let mut buf = [0 as u8; 8];
-            r.read(&mut buf)?;
-            Ok(Value::Integer(u64::from_be_bytes(buf) as i128))
-        },
-        0xd0 => {
-            let mut v: u8 = 0;
-            r.read(std::slice::from_mut(&mut v))?;
-            Ok(Value::Integer((v as i8) as i128))
-        },
-        0xd1 => {
-            let mut buf = [0 as u8; 2];
-            r.read(&mut buf)?;
-            Ok(Value::Integer(i16::from_be_bytes(buf) as i128))
-        },
-        0xd2 => {
-            let mut buf = [0 as u8; 4];
-            r.read(&mut buf)?;
-            Ok(Value::Integer(i32::from_be_bytes(buf) as i128))
-        },
-        0xd3 => {
-            let mut buf = [0 as u8; 8];
-            r.read(&mut buf)?;
-            Ok(Value::Integer(i64::from_be_bytes(buf) as i128))
-        },
-        0xd4 => unimplemented!(),
-        0xd5 => unimplemented!(),
-        0xd6 => unimplemented!(),
-        0xd7 => unimplemented!(),
-        0xd8 => unimplemented!(),
-        0xd9 => {
-            let mut len: u8 = 0;
-            r.read(std::slice::from_mut(&mut len))?;
-            let mut v = vec![0 as u8; len as usize];
-            r.read(&mut v)?;
-            Ok(Value::String(String::from_utf8(v)?))
-        },
-        0xda => {
-            let mut buf = [0; 2];
-            r.read(&mut buf)?;
-            let len = u16::from_be_bytes(buf);
-            let mut v = vec![0 as u8; len as usize];
-            r.read(&mut v);
-            Ok(Value::String(String::from_utf8(v)?))
-        },
-        0xdb => {
-            let mut buf = [0; 4];
-            r.read(&mut buf)?;
-            let len = u32::from_be_bytes(buf);
-            let mut v = vec![0 as u8; len as usize];
-            r.read(&mut v);
-            Ok(Value::String(String::from_utf8(v)?))
-        },
-        0xdc => {
-            
-        },
-        0xdd => unimplemented!(),
-        0xde => unimplemented!(),
-        0xdf => unimplemented!(),
     }
+    w.write_all(header)?;
+    w.write_all(payload)?;
+    Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn it_works() {
-        assert_eq!(2 + 2, 4);
+/// Puts `marker` and then `bytes` at the front of `buf`, returning how
+/// much of it was used, so a header goes out in a single write.
+fn put(buf: &mut [u8; 9], marker: u8, bytes: &[u8]) -> usize {
+    buf[0] = marker;
+    buf[1..=bytes.len()].copy_from_slice(bytes);
+    bytes.len() + 1
+}
+
+pub(crate) fn encode_int<W: Write + ?Sized>(w: &mut W, i: i128, opts: &EncodeOptions) -> Result<()> {
+    let mut buf = [0u8; 9];
+    let n = if (-32..=0x7f).contains(&i) {
+        buf[0] = i as u8;
+        1
+    } else if i >= 0 {
+        if i <= 0xff {
+            put(&mut buf, 0xcc, &[i as u8])
+        } else if i <= 0xffff {
+            put(&mut buf, 0xcd, &(i as u16).to_be_bytes())
+        } else if i <= 0xffff_ffff {
+            put(&mut buf, 0xce, &(i as u32).to_be_bytes())
+        } else if i <= u64::MAX as i128 {
+            put(&mut buf, 0xcf, &(i as u64).to_be_bytes())
+        } else {
+            return encode_int128(w, i, opts);
+        }
+    } else if i >= -128 {
+        put(&mut buf, 0xd0, &[i as u8])
+    } else if i >= -32768 {
+        put(&mut buf, 0xd1, &(i as u16).to_be_bytes())
+    } else if i >= -2147483648 {
+        put(&mut buf, 0xd2, &(i as u32).to_be_bytes())
+    } else if i >= i64::MIN as i128 {
+        put(&mut buf, 0xd3, &(i as i64).to_be_bytes())
+    } else {
+        return encode_int128(w, i, opts);
+    };
+    w.write_all(&buf[..n])?;
+    Ok(())
+}
+
+pub(crate) fn encode_float<W: Write + ?Sized>(w: &mut W, mut f: f64, opts: &EncodeOptions) -> Result<()> {
+    if opts.canonical_nan && f.is_nan() {
+        f = f64::NAN;
+    }
+    if opts.normalize_negative_zero && f == 0.0 {
+        f = 0.0;
+    }
+    let mut buf = [0u8; 9];
+    let n = if opts.prefer_f32 && (f as f32) as f64 == f {
+        put(&mut buf, 0xca, &(f as f32).to_bits().to_be_bytes())
+    } else {
+        put(&mut buf, 0xcb, &f.to_bits().to_be_bytes())
+    };
+    w.write_all(&buf[..n])?;
+    Ok(())
+}
+
+pub(crate) fn encode_bin<W: Write + ?Sized>(w: &mut W, b: &[u8], opts: &EncodeOptions) -> Result<()> {
+    let len = check_len(b.len())?;
+    let mut header = [0u8; 5];
+    let n = if opts.legacy_raw {
+        if len <= 31 {
+            header[0] = 0xa0 | len as u8;
+            1
+        } else if len <= 65535 {
+            header[0] = 0xda;
+            header[1..3].copy_from_slice(&(len as u16).to_be_bytes());
+            3
+        } else {
+            header[0] = 0xdb;
+            header[1..5].copy_from_slice(&(len as u32).to_be_bytes());
+            5
+        }
+    } else if len <= 255 {
+        header[..2].copy_from_slice(&[0xc4, len as u8]);
+        2
+    } else if len <= 65535 {
+        header[0] = 0xc5;
+        header[1..3].copy_from_slice(&(len as u16).to_be_bytes());
+        3
+    } else {
+        header[0] = 0xc6;
+        header[1..5].copy_from_slice(&(len as u32).to_be_bytes());
+        5
+    };
+    write_payload(w, &header[..n], b)
+}
+
+pub(crate) fn encode_ext<W: Write + ?Sized>(w: &mut W, t: i8, d: &[u8]) -> Result<()> {
+    let len = check_len(d.len())?;
+    let mut header = [0u8; 9];
+    let n = match len {
+        1 => put(&mut header, 0xd4, &[]),
+        2 => put(&mut header, 0xd5, &[]),
+        4 => put(&mut header, 0xd6, &[]),
+        8 => put(&mut header, 0xd7, &[]),
+        16 => put(&mut header, 0xd8, &[]),
+        _ if len <= 255 => put(&mut header, 0xc7, &[len as u8]),
+        _ if len <= 65535 => put(&mut header, 0xc8, &(len as u16).to_be_bytes()),
+        _ => put(&mut header, 0xc9, &(len as u32).to_be_bytes())
+    };
+    header[n] = t as u8;
+    write_payload(w, &header[..n + 1], d)
+}
+
+pub(crate) fn write_array_header<W: Write + ?Sized>(w: &mut W, len: usize) -> Result<()> {
+    let len = check_len(len)?;
+    let mut buf = [0u8; 9];
+    let n = if len <= 15 {
+        put(&mut buf, 0x90 | len as u8, &[])
+    } else if len <= 65535 {
+        put(&mut buf, 0xdc, &(len as u16).to_be_bytes())
+    } else {
+        put(&mut buf, 0xdd, &(len as u32).to_be_bytes())
+    };
+    w.write_all(&buf[..n])?;
+    Ok(())
+}
+
+pub(crate) fn write_map_header<W: Write + ?Sized>(w: &mut W, len: usize) -> Result<()> {
+    let len = check_len(len)?;
+    let mut buf = [0u8; 9];
+    let n = if len <= 15 {
+        put(&mut buf, 0x80 | len as u8, &[])
+    } else if len <= 65535 {
+        put(&mut buf, 0xde, &(len as u16).to_be_bytes())
+    } else {
+        put(&mut buf, 0xdf, &(len as u32).to_be_bytes())
+    };
+    w.write_all(&buf[..n])?;
+    Ok(())
+}
+
+/// A document `encode_value_in` can walk without recursing:
+/// `Value`, and `ValueRef` with its entries in listed order.
+pub(crate) trait Walk<'a>: Sized + 'a {
+    type Entries: Iterator<Item = (&'a Self, &'a Self)>;
+
+    /// The contents of an array or map, or `None` for anything else.
+    /// `sort_keys` asks for map entries in key order where that applies.
+    fn open(&'a self, sort_keys: bool) -> Option<Open<'a, Self>>;
+
+    /// Writes anything but an array or map.
+    fn encode_scalar<W: Write + ?Sized>(&self, w: &mut W, opts: &EncodeOptions) -> Result<()>;
+}
+
+/// The contents of a container, as `Walk::open` returns them.
+pub(crate) enum Open<'a, T: Walk<'a>> {
+    Array(&'a [T]),
+    /// The entry count and the entries.
+    Map(usize, T::Entries)
+}
+
+impl<'a> Walk<'a> for Value {
+    type Entries = Entries<'a>;
+
+    fn open(&'a self, sort_keys: bool) -> Option<Open<'a, Value>> {
+        match *self {
+            Value::Array(ref a) => Some(Open::Array(a)),
+            Value::Map(ref m) => {
+                let entries = if sort_keys {
+                    Entries::Sorted(sorted_entries(m).into_iter())
+                } else {
+                    Entries::Map(m.iter())
+                };
+                Some(Open::Map(m.len(), entries))
+            },
+            _ => None
+        }
+    }
+
+    fn encode_scalar<W: Write + ?Sized>(&self, w: &mut W, opts: &EncodeOptions) -> Result<()> {
+        encode_scalar(w, self, opts)
+    }
+}
+
+/// What is left to write of a container `encode_value` has opened.
+#[derive(Debug)]
+pub(crate) enum Pending<'a, T: Walk<'a> = Value> {
+    Items(std::slice::Iter<'a, T>),
+    /// The entries still to come, and the value of the entry whose key
+    /// was just written.
+    Entries(T::Entries, Option<&'a T>)
+}
+
+#[derive(Debug)]
+pub(crate) enum Entries<'a> {
+    Map(MapIter<'a>),
+    Sorted(std::vec::IntoIter<(&'a Value, &'a Value)>)
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = (&'a Value, &'a Value);
+
+    fn next(&mut self) -> Option<(&'a Value, &'a Value)> {
+        match self {
+            Entries::Map(it) => it.next(),
+            Entries::Sorted(it) => it.next()
+        }
+    }
+}
+
+/// Empties `stack` and hands its allocation back for use with values of
+/// another lifetime; collecting in place keeps the buffer.
+pub(crate) fn recycle<'b>(mut stack: Vec<Pending>) -> Vec<Pending<'b>> {
+    stack.clear();
+    stack.into_iter().map(|_| unreachable!("the stack was cleared")).collect()
+}
+
+fn encode_value<W: Write + ?Sized>(w: &mut W, value: &Value, opts: &EncodeOptions) -> Result<()> {
+    encode_value_in(w, value, opts, &mut Vec::new())
+}
+
+/// Writes `value`, keeping open containers on an explicit stack so that
+/// nesting is bounded by `EncodeOptions::max_depth` rather than by the
+/// thread's stack. `stack` starts empty and is left empty.
+pub(crate) fn encode_value_in<'a, T: Walk<'a>, W: Write + ?Sized>(
+    w: &mut W,
+    value: &'a T,
+    opts: &EncodeOptions,
+    stack: &mut Vec<Pending<'a, T>>
+) -> Result<()> {
+    let mut current = value;
+    loop {
+        match current.open(opts.sort_keys) {
+            Some(Open::Array(a)) => {
+                check_depth(opts, stack.len())?;
+                write_array_header(w, a.len())?;
+                stack.push(Pending::Items(a.iter()));
+            },
+            Some(Open::Map(len, entries)) => {
+                check_depth(opts, stack.len())?;
+                write_map_header(w, len)?;
+                stack.push(Pending::Entries(entries, None));
+            },
+            None => current.encode_scalar(w, opts)?
+        }
+        // Move on to the next value, closing every container that is done.
+        current = loop {
+            match stack.last_mut() {
+                None => return Ok(()),
+                Some(Pending::Items(items)) => {
+                    if let Some(v) = items.next() {
+                        break v;
+                    }
+                },
+                Some(Pending::Entries(entries, pending)) => {
+                    if let Some(v) = pending.take() {
+                        break v;
+                    }
+                    if let Some((k, v)) = entries.next() {
+                        *pending = Some(v);
+                        break k;
+                    }
+                }
+            }
+            stack.pop();
+        };
+    }
+}
+
+/// Writes anything but an array or map.
+fn encode_scalar<W: Write + ?Sized>(w: &mut W, value: &Value, opts: &EncodeOptions) -> Result<()> {
+    match *value {
+        Value::Null => {
+            w.write_all(&[0xc0])?;
+            Ok(())
+        },
+        Value::Boolean(b) => {
+            let v: u8 = if b { 0xc3 } else { 0xc2 };
+            w.write_all(&[v])?;
+            Ok(())
+        },
+        Value::Integer(i) => encode_int(w, i, opts),
+        Value::FixedInteger(i, format) => encode_fixed_int(w, i, format),
+        Value::Float(f) => encode_float(w, f, opts),
+        Value::String(ref s) => encode_str(w, s, opts),
+        Value::SharedString(ref s) => encode_str(w, s, opts),
+        Value::InlineString(ref s) => encode_str(w, s, opts),
+        Value::Bytes(ref b) => encode_bin(w, b, opts),
+        Value::Array(_) | Value::Map(_) => Ok(()),
+        Value::Ext(t, ref d) => encode_ext(w, t, d),
+        Value::Raw(ref raw) => {
+            w.write_all(raw.as_bytes())?;
+            Ok(())
+        }
+    }
+}
+
+/// Initial step for growing payload buffers in `Reader::read_vec`.
+const READ_CHUNK: usize = 64 * 1024;
+
+/// Most elements or entries a decoded container has room for up front, so
+/// typical containers are allocated once while a forged length still
+/// can't force a large allocation before the elements arrive.
+const PREALLOC_LEN: usize = 1024;
+
+/// Where a `Reader` gets its bytes.
+trait Source {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize>;
+
+    /// The bytes already buffered, refilled if empty, for sources that can
+    /// be parsed in place; `None` for plain readers.
+    fn fill_buf(&mut self) -> Option<std::io::Result<&[u8]>>;
+
+    fn consume(&mut self, n: usize);
+}
+
+/// A reader read through `Read` alone.
+struct Plain<'a, R: ?Sized>(&'a mut R);
+
+impl<R: Read + ?Sized> Source for Plain<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf)
+    }
+
+    fn fill_buf(&mut self) -> Option<std::io::Result<&[u8]>> {
+        None
+    }
+
+    fn consume(&mut self, _n: usize) {}
+}
+
+/// A reader whose buffer is parsed directly, skipping a `read` call per
+/// marker and a zero-filled staging buffer per payload.
+struct Buffered<'a, R: ?Sized>(&'a mut R);
+
+impl<R: BufRead + ?Sized> Source for Buffered<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf)
+    }
+
+    fn fill_buf(&mut self) -> Option<std::io::Result<&[u8]>> {
+        Some(self.0.fill_buf())
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.0.consume(n)
+    }
+}
+
+/// Wraps the caller's reader to track how far into the stream we are,
+/// so errors can report where they happened.
+struct Reader<S> {
+    inner: S,
+    offset: u64,
+    /// Path segments to the value that failed, innermost first; only
+    /// filled in while an error unwinds.
+    path: Vec<String>,
+    /// The containers built before the error, also filled in while unwinding.
+    partial: Option<Value>,
+    /// Number of arrays and maps currently being decoded.
+    depth: usize,
+    /// Bytes counted against `DecodeOptions::max_alloc` so far.
+    allocated: usize,
+    /// Bytes charged so far by every reader decoding part of the same
+    /// document, for `par_decode_with`. When set, this total is what
+    /// `max_alloc` limits rather than `allocated`.
+    shared_alloc: Option<Arc<AtomicUsize>>,
+    /// Buffers to decode into before allocating new ones.
+    pool: Pool,
+    /// What the next marker read is meant to start.
+    expected: Expected,
+    /// Map keys seen so far, for `DecodeOptions::intern_keys`.
+    keys: Interner
+}
+
+impl<S: Source> Reader<S> {
+    fn new(inner: S) -> Reader<S> {
+        Reader {
+            inner,
+            offset: 0,
+            path: Vec::new(),
+            partial: None,
+            depth: 0,
+            allocated: 0,
+            shared_alloc: None,
+            pool: Pool::default(),
+            expected: Expected::Value,
+            keys: Interner::default()
+        }
+    }
+
+    /// Wraps an error from `decode_value` with where it happened.
+    fn context(&self, e: Error) -> Error {
+        let mut path = String::from("$");
+        for segment in self.path.iter().rev() {
+            path.push_str(segment);
+        }
+        Error::Decode { offset: self.offset, path, error: Box::new(e) }
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        if let Some(Ok(avail)) = self.inner.fill_buf() {
+            if avail.len() >= buf.len() {
+                buf.copy_from_slice(&avail[..buf.len()]);
+                self.inner.consume(buf.len());
+                self.offset += buf.len() as u64;
+                return Ok(());
+            }
+        }
+        let mut got = 0;
+        while got < buf.len() {
+            match self.inner.read(&mut buf[got..]) {
+                Ok(0) => {
+                    self.offset += got as u64;
+                    return Err(Error::UnexpectedEof { needed: buf.len(), got });
+                },
+                Ok(n) => got += n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {},
+                Err(e) => {
+                    self.offset += got as u64;
+                    return Err(e.into());
+                }
+            }
+        }
+        self.offset += got as u64;
+        Ok(())
+    }
+
+    fn read_u8(&mut self) -> Result<u8> {
+        let mut b: u8 = 0;
+        self.read_exact(std::slice::from_mut(&mut b))?;
+        Ok(b)
+    }
+
+    fn read_buf<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut buf = [0u8; N];
+        self.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Copies the bytes of one complete value without decoding it, then
+    /// checks them as `RawValue::from_slice` would. Lengths are checked
+    /// against the limits, and bytes charged to `max_alloc`, as each header
+    /// arrives, before anything is buffered for it.
+    fn read_raw(&mut self, opts: &DecodeOptions) -> Result<RawValue> {
+        let mut out = Vec::new();
+        let mut pending: usize = 1;
+        while pending > 0 {
+            pending -= 1;
+            let start = out.len();
+            let marker = self.read_u8()?;
+            if marker == 0xc1 {
+                return Err(Error::ReservedMarker { offset: self.offset - 1, expected: self.expected });
+            }
+            out.push(marker);
+            out.extend(self.read_vec(validate::header_extra(marker))?);
+            charge(self, out.len() - start, opts)?;
+            match validate::read_header(&out[start..])?.0 {
+                Header::Scalar(len) => {
+                    charge(self, len, opts)?;
+                    out.extend(self.read_vec(len)?);
+                },
+                Header::Str(len) | Header::Bin(len) | Header::Ext(len) => {
+                    check_payload(len, opts)?;
+                    charge(self, len, opts)?;
+                    out.extend(self.read_vec(len)?);
+                },
+                Header::Array(len) | Header::Map(len) if len > opts.max_container_len => {
+                    return Err(Error::ContainerTooLong { len, limit: opts.max_container_len });
+                },
+                Header::Array(len) => pending = pending.saturating_add(len),
+                Header::Map(len) => pending = pending.saturating_add(len.saturating_mul(2)),
+                Header::Reserved => {}
+            }
+        }
+        validate::value_len(&out)?;
+        Ok(RawValue::from_vec_unchecked(out))
+    }
+
+    /// Reads a length-prefixed payload. The buffer grows as data actually
+    /// arrives, so a forged length can't force a huge up-front allocation.
+    fn read_vec(&mut self, len: usize) -> Result<Vec<u8>> {
+        self.read_vec_into(Vec::new(), len)
+    }
+
+    /// Like `read_vec`, reusing the allocation of `v`.
+    fn read_vec_into(&mut self, mut v: Vec<u8>, len: usize) -> Result<Vec<u8>> {
+        v.clear();
+        while v.len() < len {
+            let start = v.len();
+            match self.inner.fill_buf() {
+                Some(Ok([])) => return Err(Error::UnexpectedEof { needed: len, got: start }),
+                Some(Ok(avail)) => {
+                    let n = avail.len().min(len - start);
+                    v.extend_from_slice(&avail[..n]);
+                    self.inner.consume(n);
+                    self.offset += n as u64;
+                    continue;
+                },
+                Some(Err(e)) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Some(Err(e)) => return Err(e.into()),
+                None => {}
+            }
+            v.resize(start + (len - start).min(start.max(READ_CHUNK)), 0);
+            match self.read_exact(&mut v[start..]) {
+                Err(Error::UnexpectedEof { got, .. }) => {
+                    return Err(Error::UnexpectedEof { needed: len, got: start + got });
+                },
+                r => r?
+            }
+        }
+        Ok(v)
+    }
+    /// Discards `len` bytes.
+    fn skip(&mut self, len: usize) -> Result<()> {
+        let mut scratch = [0u8; 512];
+        let mut left = len;
+        while left > 0 {
+            match self.inner.fill_buf() {
+                Some(Ok([])) => return Err(Error::UnexpectedEof { needed: len, got: len - left }),
+                Some(Ok(avail)) => {
+                    let n = avail.len().min(left);
+                    self.inner.consume(n);
+                    self.offset += n as u64;
+                    left -= n;
+                    continue;
+                },
+                Some(Err(e)) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Some(Err(e)) => return Err(e.into()),
+                None => {}
+            }
+            let n = left.min(scratch.len());
+            match self.read_exact(&mut scratch[..n]) {
+                Err(Error::UnexpectedEof { got, .. }) => {
+                    return Err(Error::UnexpectedEof { needed: len, got: len - left + got });
+                },
+                r => r?
+            }
+            left -= n;
+        }
+        Ok(())
+    }
+
+    /// Reads past one complete value without building it or looking
+    /// inside its strings.
+    fn skip_value(&mut self) -> Result<()> {
+        let mut header = [0u8; 6];
+        let mut pending: usize = 1;
+        while pending > 0 {
+            pending -= 1;
+            let marker = self.read_u8()?;
+            if marker == 0xc1 {
+                return Err(Error::ReservedMarker { offset: self.offset - 1, expected: Expected::Value });
+            }
+            let n = 1 + validate::header_extra(marker);
+            header[0] = marker;
+            self.read_exact(&mut header[1..n])?;
+            match validate::read_header(&header[..n])?.0 {
+                Header::Scalar(len) | Header::Str(len) | Header::Bin(len) | Header::Ext(len) => self.skip(len)?,
+                Header::Array(len) => pending = pending.saturating_add(len),
+                Header::Map(len) => pending = pending.saturating_add(len.saturating_mul(2)),
+                Header::Reserved => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Most buffers of each kind a `Pool` keeps, and most distinct keys
+/// interned by one decode or `Decoder`.
+const POOL_LIMIT: usize = 1024;
+
+/// Longest map key, in bytes, that `DecodeOptions::intern_keys` interns.
+pub const INTERN_MAX_LEN: usize = 64;
+
+/// Buffers kept between decodes by a `Decoder`. Empty, and so free, for
+/// one-off decodes.
+#[derive(Debug, Default)]
+struct Pool {
+    frames: Vec<Frame>,
+    arrays: Vec<Vec<Value>>,
+    bytes: Vec<Vec<u8>>
+}
+
+impl Pool {
+    fn keep<T>(pool: &mut Vec<Vec<T>>, mut v: Vec<T>) {
+        if pool.len() < POOL_LIMIT && v.capacity() > 0 {
+            v.clear();
+            pool.push(v);
+        }
+    }
+
+    /// Takes `value` apart without recursing, keeping its buffers.
+    fn reclaim(&mut self, value: Value) {
+        let mut pending = self.arrays.pop().unwrap_or_default();
+        pending.push(value);
+        while let Some(v) = pending.pop() {
+            match v {
+                Value::Array(mut items) => {
+                    pending.append(&mut items);
+                    Pool::keep(&mut self.arrays, items);
+                },
+                Value::Map(m) => {
+                    for (k, v) in m {
+                        pending.push(k);
+                        pending.push(v);
+                    }
+                },
+                Value::String(s) => Pool::keep(&mut self.bytes, s.into_bytes()),
+                Value::Bytes(b) | Value::Ext(_, b) => Pool::keep(&mut self.bytes, b),
+                Value::Raw(raw) => Pool::keep(&mut self.bytes, raw.into_bytes()),
+                _ => {}
+            }
+        }
+        Pool::keep(&mut self.arrays, pending);
+    }
+}
+
+/// Knobs controlling what `decode_with` accepts.
+#[derive(Debug, Clone)]
+pub struct DecodeOptions {
+    /// Reject values that were not written with the shortest available
+    /// encoding, so that every value has exactly one accepted form.
+    /// Floats are exempt, as float32 and float64 are not interchangeable.
+    pub strict: bool,
+    /// What to do with str payloads that are not valid UTF-8.
+    pub utf8: Utf8Policy,
+    /// Extension type code whose 16-byte payloads are decoded back into
+    /// `Value::Integer`, mirroring `EncodeOptions::int128_ext`.
+    pub int128_ext: Option<i8>,
+    /// Which value wins when a map repeats a key.
+    pub duplicate_keys: DuplicateKeys,
+    /// Decode integers that were not written in their shortest form as
+    /// `Value::FixedInteger`, so re-encoding reproduces the original bytes.
+    pub preserve_int_width: bool,
+    /// Maximum number of nested arrays and maps; deeper input fails with
+    /// `Error::DepthLimitExceeded`. Decoding and encoding don't recurse,
+    /// but dropping, cloning and comparing a `Value` do, so keep this modest.
+    pub max_depth: usize,
+    /// What to do with ext types that aren't known; see `known_ext`.
+    pub unknown_ext: ExtPolicy,
+    /// Ext type codes the caller understands, in addition to the timestamp
+    /// extension and `int128_ext`, which are always known.
+    pub known_ext: Vec<i8>,
+    /// Keep array elements and map values nested at least this many
+    /// containers deep as `Value::Raw`, checked but not decoded. `Some(1)`
+    /// decodes only the top-level container, e.g. a proxy's envelope.
+    pub raw_depth: Option<usize>,
+    /// Maximum number of elements in an array, or entries in a map.
+    pub max_container_len: usize,
+    /// Maximum length of a single str, bin or ext payload.
+    pub max_payload_len: usize,
+    /// Maximum bytes a single decode may allocate, counted from declared
+    /// lengths as each header is read: payload bytes, `Value`-sized slots
+    /// for array elements and map keys and values, and whole raw values.
+    pub max_alloc: usize,
+    /// Decode strings of up to `INLINE_CAP` bytes, map keys included, as
+    /// `Value::InlineString`, saving an allocation for each. Arrays can't
+    /// be stored inline, as a `Value` can't contain its own elements;
+    /// `Decoder` reuses their buffers instead.
+    pub inline_strings: bool,
+    /// Decode string map keys of up to `INTERN_MAX_LEN` bytes as
+    /// `Value::SharedString`, with every repeat of a key sharing one
+    /// allocation across the document, or across every decode of a
+    /// `Decoder`. Takes precedence over `inline_strings` for keys.
+    pub intern_keys: bool
+}
+
+/// How `decode_with` handles ext types outside `DecodeOptions::known_ext`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExtPolicy {
+    /// Keep them opaquely as `Value::Ext`.
+    #[default]
+    Preserve,
+    /// Fail the decode with `Error::UnknownExt`.
+    Reject
+}
+
+impl Default for DecodeOptions {
+    fn default() -> DecodeOptions {
+        DecodeOptions {
+            strict: false,
+            utf8: Utf8Policy::Strict,
+            int128_ext: None,
+            duplicate_keys: DuplicateKeys::KeepLast,
+            preserve_int_width: false,
+            max_depth: DEFAULT_MAX_DEPTH,
+            unknown_ext: ExtPolicy::Preserve,
+            known_ext: Vec::new(),
+            raw_depth: None,
+            max_container_len: usize::MAX,
+            max_payload_len: usize::MAX,
+            max_alloc: usize::MAX,
+            inline_strings: false,
+            intern_keys: false
+        }
+    }
+}
+
+/// How `decode_with` handles a map that contains the same key twice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicateKeys {
+    /// Keep the value seen last.
+    #[default]
+    KeepLast,
+    /// Keep the value seen first.
+    KeepFirst,
+    /// Fail the decode with `Error::DuplicateKey`.
+    Error
+}
+
+/// How `decode_with` handles str payloads that are not valid UTF-8.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Utf8Policy {
+    /// Fail the decode.
+    #[default]
+    Strict,
+    /// Replace invalid sequences with U+FFFD.
+    Lossy,
+    /// Return the raw payload as `Value::Bytes`.
+    Bytes
+}
+
+fn check_canonical(opts: &DecodeOptions, marker: u8, canonical: bool) -> Result<()> {
+    if opts.strict && !canonical {
+        Err(Error::NonCanonical(marker))
+    } else {
+        Ok(())
+    }
+}
+
+/// Length fields are 32 bits wide, which may not fit `usize` on every target.
+fn decode_len(len: u32) -> Result<usize> {
+    usize::try_from(len).map_err(|_| Error::LengthOutOfRange)
+}
+
+fn decode_int(opts: &DecodeOptions, marker: u8, v: i128, canonical: bool) -> Result<Value> {
+    check_canonical(opts, marker, canonical)?;
+    match IntFormat::from_marker(marker) {
+        Some(format) if !canonical && opts.preserve_int_width => Ok(Value::FixedInteger(v, format)),
+        _ => Ok(Value::Integer(v))
+    }
+}
+
+pub(crate) fn key_segment(key: &Value) -> String {
+    match key {
+        Value::String(s) => format!(".{}", s),
+        Value::SharedString(s) => format!(".{}", s),
+        Value::InlineString(s) => format!(".{}", s.as_str()),
+        Value::Integer(i) | Value::FixedInteger(i, _) => format!(".{}", i),
+        Value::Boolean(b) => format!(".{}", b),
+        Value::Null => ".null".to_string(),
+        _ => ".<key>".to_string()
+    }
+}
+
+fn enter<S: Source>(r: &mut Reader<S>, opts: &DecodeOptions) -> Result<()> {
+    if r.depth >= opts.max_depth {
+        return Err(Error::DepthLimitExceeded);
+    }
+    r.depth += 1;
+    Ok(())
+}
+
+/// Counts `bytes` against `DecodeOptions::max_alloc`.
+fn charge<S: Source>(r: &mut Reader<S>, bytes: usize, opts: &DecodeOptions) -> Result<()> {
+    r.allocated = r.allocated.saturating_add(bytes);
+    let total = match r.shared_alloc {
+        Some(ref shared) => {
+            let before = shared.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| Some(n.saturating_add(bytes)));
+            before.unwrap_or_else(|n| n).saturating_add(bytes)
+        },
+        None => r.allocated
+    };
+    if total > opts.max_alloc {
+        return Err(Error::AllocLimitExceeded { limit: opts.max_alloc });
+    }
+    Ok(())
+}
+
+/// Checks a container header against the limits, then enters it.
+fn open<S: Source>(r: &mut Reader<S>, len: usize, slots: usize, opts: &DecodeOptions) -> Result<()> {
+    if len > opts.max_container_len {
+        return Err(Error::ContainerTooLong { len, limit: opts.max_container_len });
+    }
+    charge(r, len.saturating_mul(slots).saturating_mul(std::mem::size_of::<Value>()), opts)?;
+    enter(r, opts)
+}
+
+fn read_payload<S: Source>(r: &mut Reader<S>, len: usize, opts: &DecodeOptions) -> Result<Vec<u8>> {
+    check_payload(len, opts)?;
+    charge(r, len, opts)?;
+    let buf = r.pool.bytes.pop().unwrap_or_default();
+    r.read_vec_into(buf, len)
+}
+
+fn check_payload(len: usize, opts: &DecodeOptions) -> Result<()> {
+    if len > opts.max_payload_len {
+        return Err(Error::PayloadTooLong { len, limit: opts.max_payload_len });
+    }
+    Ok(())
+}
+
+/// A container `decode_value` is part way through filling in.
+#[derive(Debug)]
+enum Frame {
+    Array { items: Vec<Value>, len: usize },
+    /// `key` holds a decoded key until its value arrives.
+    Map { map: Map, left: usize, key: Option<Value> }
+}
+
+impl Frame {
+    /// What the next value read into this container is.
+    fn expected(&self) -> Expected {
+        match self {
+            Frame::Array { .. } => Expected::ArrayElement,
+            Frame::Map { key: None, .. } => Expected::MapKey,
+            Frame::Map { key: Some(_), .. } => Expected::MapValue
+        }
+    }
+}
+
+/// One step of decoding: a finished value, or the header of a container
+/// whose contents follow.
+enum Item {
+    Value(Value),
+    Array(usize),
+    Map(usize)
+}
+
+/// Adds a map entry as `opts.duplicate_keys` says; the key is handed back
+/// if it was rejected.
+fn insert_entry(m: &mut Map, k: Value, v: Value, opts: &DecodeOptions) -> std::result::Result<(), Value> {
+    match opts.duplicate_keys {
+        DuplicateKeys::KeepLast => {
+            m.insert(k, v);
+        },
+        DuplicateKeys::KeepFirst => {
+            m.entry(k).or_insert(v);
+        },
+        DuplicateKeys::Error => {
+            if m.contains_key(&k) {
+                return Err(k);
+            }
+            m.insert(k, v);
+        }
+    }
+    Ok(())
+}
+
+/// Records where an error happened in each container still open, innermost
+/// first, keeping what they held so far as the partial value.
+fn unwind<S: Source>(r: &mut Reader<S>, stack: &mut Vec<Frame>, e: Error) -> Error {
+    while let Some(frame) = stack.pop() {
+        r.partial = Some(match frame {
+            Frame::Array { mut items, .. } => {
+                r.path.push(format!("[{}]", items.len()));
+                items.extend(r.partial.take());
+                Value::Array(items)
+            },
+            Frame::Map { map, key: None, .. } => {
+                r.path.push(".<key>".to_string());
+                Value::Map(map)
+            },
+            Frame::Map { mut map, key: Some(k), .. } => {
+                r.path.push(key_segment(&k));
+                if let Some(v) = r.partial.take() {
+                    map.insert(k, v);
+                }
+                Value::Map(map)
+            }
+        });
+    }
+    e
+}
+
+/// Decodes one value. Containers are tracked on an explicit stack rather
+/// than by recursion, so nesting depth is bounded only by
+/// `DecodeOptions::max_depth` and never by the thread's stack.
+fn decode_value<S: Source>(r: &mut Reader<S>, opts: &DecodeOptions) -> Result<Value> {
+    let mut stack = std::mem::take(&mut r.pool.frames);
+    let result = decode_value_in(r, opts, &mut stack);
+    r.pool.frames = stack;
+    result
+}
+
+/// `decode_value` with the stack to use, which is left empty.
+fn decode_value_in<S: Source>(r: &mut Reader<S>, opts: &DecodeOptions, stack: &mut Vec<Frame>) -> Result<Value> {
+    loop {
+        if let Some(frame) = stack.last() {
+            r.expected = frame.expected();
+        }
+        let mut value = match decode_item(r, opts) {
+            Ok(Item::Value(v)) => v,
+            Ok(item) => {
+                let opened = match item {
+                    Item::Array(len) => open(r, len, 1, opts),
+                    Item::Map(len) => open(r, len, 2, opts),
+                    Item::Value(_) => Ok(())
+                };
+                if let Err(e) = opened {
+                    return Err(unwind(r, stack, e));
+                }
+                match item {
+                    Item::Array(len) if len > 0 => {
+                        let mut items = r.pool.arrays.pop().unwrap_or_default();
+                        items.reserve(len.min(PREALLOC_LEN));
+                        stack.push(Frame::Array { items, len });
+                        continue;
+                    },
+                    Item::Map(len) if len > 0 => {
+                        stack.push(Frame::Map { map: map_with_capacity(len.min(PREALLOC_LEN)), left: len, key: None });
+                        continue;
+                    },
+                    Item::Map(_) => {
+                        r.depth -= 1;
+                        Value::Map(Map::default())
+                    },
+                    _ => {
+                        r.depth -= 1;
+                        Value::Array(Vec::new())
+                    }
+                }
+            },
+            Err(e) => return Err(unwind(r, stack, e))
+        };
+        // Hand the value to its container, closing every container it fills.
+        loop {
+            match stack.last_mut() {
+                None => return Ok(value),
+                Some(Frame::Array { items, len }) => {
+                    items.push(value);
+                    if items.len() < *len {
+                        break;
+                    }
+                    value = Value::Array(std::mem::take(items));
+                },
+                Some(Frame::Map { map, left, key }) => {
+                    let k = match key.take() {
+                        Some(k) => k,
+                        None => {
+                            *key = Some(value);
+                            break;
+                        }
+                    };
+                    if let Err(k) = insert_entry(map, k, value, opts) {
+                        r.path.push(key_segment(&k));
+                        r.partial = Some(Value::Map(std::mem::take(map)));
+                        stack.pop();
+                        return Err(unwind(r, stack, Error::DuplicateKey));
+                    }
+                    *left -= 1;
+                    if *left > 0 {
+                        break;
+                    }
+                    value = Value::Map(std::mem::take(map));
+                }
+            }
+            stack.pop();
+            r.depth -= 1;
+        }
+    }
+}
+
+fn decode_ext<S: Source>(r: &mut Reader<S>, len: usize, opts: &DecodeOptions) -> Result<Value> {
+    let t = r.read_u8()? as i8;
+    let known = t == TIMESTAMP_EXT || opts.int128_ext == Some(t) || opts.known_ext.contains(&t);
+    if !known && opts.unknown_ext == ExtPolicy::Reject {
+        return Err(Error::UnknownExt(t));
+    }
+    if len == 16 && opts.int128_ext == Some(t) {
+        return Ok(Value::Integer(i128::from_be_bytes(r.read_buf()?)));
+    }
+    Ok(Value::Ext(t, read_payload(r, len, opts)?))
+}
+
+fn decode_str<S: Source>(r: &mut Reader<S>, len: usize, opts: &DecodeOptions) -> Result<Value> {
+    if opts.intern_keys && r.expected == Expected::MapKey && len <= INTERN_MAX_LEN {
+        check_payload(len, opts)?;
+        let mut buf = [0u8; INTERN_MAX_LEN];
+        r.read_exact(&mut buf[..len])?;
+        if let Ok(s) = utf8::from_utf8(&buf[..len]) {
+            if let Some(shared) = r.keys.get(s) {
+                return Ok(Value::SharedString(shared));
+            }
+            charge(r, len, opts)?;
+            // Past the limit, only keys already seen are shared, so hostile
+            // input can't grow the interner without bound.
+            if r.keys.len() < POOL_LIMIT {
+                return Ok(Value::SharedString(r.keys.intern(s)));
+            }
+            return Ok(Value::String(s.to_string()));
+        }
+        charge(r, len, opts)?;
+        return str_value(buf[..len].to_vec(), opts);
+    }
+    if opts.inline_strings && len <= INLINE_CAP {
+        check_payload(len, opts)?;
+        let mut buf = [0u8; INLINE_CAP];
+        r.read_exact(&mut buf[..len])?;
+        if let Some(s) = utf8::from_utf8(&buf[..len]).ok().and_then(InlineStr::new) {
+            return Ok(Value::InlineString(s));
+        }
+        charge(r, len, opts)?;
+        return str_value(buf[..len].to_vec(), opts);
+    }
+    str_value(read_payload(r, len, opts)?, opts)
+}
+
+fn str_value(v: Vec<u8>, opts: &DecodeOptions) -> Result<Value> {
+    match utf8::into_string(v) {
+        Ok(s) => Ok(Value::String(s)),
+        Err(e) => match opts.utf8 {
+            Utf8Policy::Strict => Err(e.into()),
+            Utf8Policy::Lossy => Ok(Value::String(String::from_utf8_lossy(e.as_bytes()).into_owned())),
+            Utf8Policy::Bytes => Ok(Value::Bytes(e.into_bytes()))
+        }
+    }
+}
+
+/// Decodes one value from `r`.
+///
+/// Decoding never panics, whatever bytes it is given: malformed input is
+/// reported through `Error`, nesting is bounded by `DecodeOptions::max_depth`
+/// and buffers only grow as payload bytes actually arrive.
+pub fn decode_from<R: Read + ?Sized>(r: &mut R) -> Result<Value> {
+    decode_with(r, &DecodeOptions::default())
+}
+
+pub fn decode_with<R: Read + ?Sized>(r: &mut R, opts: &DecodeOptions) -> Result<Value> {
+    let mut reader = Reader::new(Plain(r));
+    decode_value(&mut reader, opts).map_err(|e| reader.context(e))
+}
+
+/// Like `decode_from`, but parses straight out of the reader's buffer,
+/// which is much faster for a `BufReader` over a file or socket. Bytes
+/// after the value stay buffered in `r`.
+pub fn decode_from_buf<R: BufRead + ?Sized>(r: &mut R) -> Result<Value> {
+    decode_buf_with(r, &DecodeOptions::default())
+}
+
+pub fn decode_buf_with<R: BufRead + ?Sized>(r: &mut R, opts: &DecodeOptions) -> Result<Value> {
+    let mut reader = Reader::new(Buffered(r));
+    decode_value(&mut reader, opts).map_err(|e| reader.context(e))
+}
+
+/// A failed `decode_partial`, with whatever could be recovered.
+pub struct Partial {
+    pub error: Error,
+    /// Bytes consumed before the error was detected.
+    pub consumed: usize,
+    /// The enclosing containers with the elements decoded before the
+    /// error, or `None` if the failing value was not inside a container.
+    pub value: Option<Value>
+}
+
+/// Decodes one value from the front of `buf`, returning it with the
+/// number of bytes it occupied; anything after that is left untouched.
+pub fn decode_from_slice(buf: &[u8]) -> Result<(Value, usize)> {
+    decode_partial(buf, &DecodeOptions::default()).map_err(|p| p.error)
+}
+
+/// Like `decode_from_slice`, but on failure reports how far decoding got
+/// and the partially built value, so callers can resume or report.
+#[allow(clippy::result_large_err)]
+pub fn decode_partial(buf: &[u8], opts: &DecodeOptions) -> std::result::Result<(Value, usize), Partial> {
+    let mut r = buf;
+    let mut reader = Reader::new(Buffered(&mut r));
+    match decode_value(&mut reader, opts) {
+        Ok(v) => Ok((v, reader.offset as usize)),
+        Err(e) => Err(Partial {
+            error: reader.context(e),
+            consumed: reader.offset as usize,
+            value: reader.partial.take()
+        })
+    }
+}
+
+/// Advances `r` past one complete value without decoding it, for
+/// filtering or searching a stream without paying for the values passed
+/// over. Strings are not checked for valid UTF-8.
+pub fn skip_value<R: Read + ?Sized>(r: &mut R) -> Result<()> {
+    let mut reader = Reader::new(Plain(r));
+    reader.skip_value().map_err(|e| reader.context(e))
+}
+
+/// Finds the value starting at `buf[offset]` without decoding it,
+/// returning the range of bytes it occupies; the next value, if any,
+/// starts at the range's end. Errors carry offsets into `buf`.
+pub fn skip_value_at(buf: &[u8], offset: usize) -> Result<std::ops::Range<usize>> {
+    let mut r = buf.get(offset..).unwrap_or_default();
+    let mut reader = Reader::new(Buffered(&mut r));
+    reader.offset = offset as u64;
+    match reader.skip_value() {
+        Ok(()) => Ok(offset..reader.offset as usize),
+        Err(e) => Err(reader.context(e))
+    }
+}
+
+/// Reads the next marker and whatever follows it, up to the contents of
+/// an array or map.
+fn decode_item<S: Source>(r: &mut Reader<S>, opts: &DecodeOptions) -> Result<Item> {
+    match opts.raw_depth {
+        Some(d) if r.depth >= d && r.expected != Expected::MapKey => {
+            let raw = r.read_raw(opts)?;
+            return Ok(Item::Value(Value::Raw(raw)));
+        },
+        _ => {}
+    }
+    // One-byte items, the bulk of most documents, are taken straight from
+    // the buffer when there is one.
+    if let Some(Ok(&[b, ..])) = r.inner.fill_buf() {
+        let item = match b {
+            0x00..=0x7f => Some(Item::Value(Value::Integer(b as i128))),
+            0x80..=0x8f => Some(Item::Map((b & 0xf) as usize)),
+            0x90..=0x9f => Some(Item::Array((b & 0xf) as usize)),
+            0xc0 => Some(Item::Value(Value::Null)),
+            0xc2 => Some(Item::Value(Value::Boolean(false))),
+            0xc3 => Some(Item::Value(Value::Boolean(true))),
+            0xe0..=0xff => Some(Item::Value(Value::Integer((b as i8) as i128))),
+            _ => None
+        };
+        if let Some(item) = item {
+            r.inner.consume(1);
+            r.offset += 1;
+            return Ok(item);
+        }
+    }
+    let b = r.read_u8()?;
+    match b {
+        0x00..=0x7f => Ok(Item::Value(Value::Integer(b as i128))),
+        0x80..=0x8f => Ok(Item::Map((b & 0xf) as usize)),
+        0x90..=0x9f => Ok(Item::Array((b & 0xf) as usize)),
+        0xa0..=0xbf => decode_str(r, (b & 0x1f) as usize, opts).map(Item::Value),
+        0xc0 => Ok(Item::Value(Value::Null)),
+        0xc1 => Err(Error::ReservedMarker { offset: r.offset - 1, expected: r.expected }),
+        0xc2 => Ok(Item::Value(Value::Boolean(false))),
+        0xc3 => Ok(Item::Value(Value::Boolean(true))),
+        0xc4 => {
+            let len = r.read_u8()?;
+            Ok(Item::Value(Value::Bytes(read_payload(r, usize::from(len), opts)?)))
+        },
+        0xc5 => {
+            let len = u16::from_be_bytes(r.read_buf()?);
+            check_canonical(opts, b, len > 0xff)?;
+            Ok(Item::Value(Value::Bytes(read_payload(r, usize::from(len), opts)?)))
+        },
+        0xc6 => {
+            let len = u32::from_be_bytes(r.read_buf()?);
+            check_canonical(opts, b, len > 0xffff)?;
+            Ok(Item::Value(Value::Bytes(read_payload(r, decode_len(len)?, opts)?)))
+        },
+        0xc7 => {
+            let len = r.read_u8()?;
+            check_canonical(opts, b, !matches!(len, 1 | 2 | 4 | 8 | 16))?;
+            decode_ext(r, usize::from(len), opts).map(Item::Value)
+        },
+        0xc8 => {
+            let len = u16::from_be_bytes(r.read_buf()?);
+            check_canonical(opts, b, len > 0xff)?;
+            decode_ext(r, usize::from(len), opts).map(Item::Value)
+        },
+        0xc9 => {
+            let len = u32::from_be_bytes(r.read_buf()?);
+            check_canonical(opts, b, len > 0xffff)?;
+            decode_ext(r, decode_len(len)?, opts).map(Item::Value)
+        },
+        0xca => Ok(Item::Value(Value::Float(f32::from_be_bytes(r.read_buf()?) as f64))),
+        0xcb => Ok(Item::Value(Value::Float(f64::from_be_bytes(r.read_buf()?)))),
+        0xcc => {
+            let v = r.read_u8()?;
+            decode_int(opts, b, v as i128, v > 0x7f).map(Item::Value)
+        },
+        0xcd => {
+            let v = u16::from_be_bytes(r.read_buf()?);
+            decode_int(opts, b, v as i128, v > 0xff).map(Item::Value)
+        },
+        0xce => {
+            let v = u32::from_be_bytes(r.read_buf()?);
+            decode_int(opts, b, v as i128, v > 0xffff).map(Item::Value)
+        },
+        0xcf => {
+            let v = u64::from_be_bytes(r.read_buf()?);
+            decode_int(opts, b, v as i128, v > 0xffff_ffff).map(Item::Value)
+        },
+        0xd0 => {
+            let v = r.read_u8()? as i8;
+            decode_int(opts, b, v as i128, v < -32).map(Item::Value)
+        },
+        0xd1 => {
+            let v = i16::from_be_bytes(r.read_buf()?);
+            decode_int(opts, b, v as i128, v < -128).map(Item::Value)
+        },
+        0xd2 => {
+            let v = i32::from_be_bytes(r.read_buf()?);
+            decode_int(opts, b, v as i128, v < -32768).map(Item::Value)
+        },
+        0xd3 => {
+            let v = i64::from_be_bytes(r.read_buf()?);
+            decode_int(opts, b, v as i128, v < -2147483648).map(Item::Value)
+        },
+        0xd4 => decode_ext(r, 1, opts).map(Item::Value),
+        0xd5 => decode_ext(r, 2, opts).map(Item::Value),
+        0xd6 => decode_ext(r, 4, opts).map(Item::Value),
+        0xd7 => decode_ext(r, 8, opts).map(Item::Value),
+        0xd8 => decode_ext(r, 16, opts).map(Item::Value),
+        0xd9 => {
+            let len = r.read_u8()?;
+            check_canonical(opts, b, len > 31)?;
+            decode_str(r, usize::from(len), opts).map(Item::Value)
+        },
+        0xda => {
+            let len = u16::from_be_bytes(r.read_buf()?);
+            check_canonical(opts, b, len > 0xff)?;
+            decode_str(r, usize::from(len), opts).map(Item::Value)
+        },
+        0xdb => {
+            let len = u32::from_be_bytes(r.read_buf()?);
+            check_canonical(opts, b, len > 0xffff)?;
+            decode_str(r, decode_len(len)?, opts).map(Item::Value)
+        },
+        0xdc => {
+            let len = u16::from_be_bytes(r.read_buf()?);
+            check_canonical(opts, b, len > 15)?;
+            Ok(Item::Array(usize::from(len)))
+        },
+        0xdd => {
+            let len = u32::from_be_bytes(r.read_buf()?);
+            check_canonical(opts, b, len > 0xffff)?;
+            Ok(Item::Array(decode_len(len)?))
+        },
+        0xde => {
+            let len = u16::from_be_bytes(r.read_buf()?);
+            check_canonical(opts, b, len > 15)?;
+            Ok(Item::Map(usize::from(len)))
+        },
+        0xdf => {
+            let len = u32::from_be_bytes(r.read_buf()?);
+            check_canonical(opts, b, len > 0xffff)?;
+            Ok(Item::Map(decode_len(len)?))
+        },
+        0xe0..=0xff => Ok(Item::Value(Value::Integer((b as i8) as i128))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{dec, enc, ok};
+
+    fn int_array(len: usize) -> Value {
+        Value::Array((0..len).map(|i| Value::Integer(i as i128)).collect())
+    }
+
+    fn int_map(len: usize) -> Value {
+        Value::Map((0..len).map(|i| (Value::Integer(i as i128), Value::Boolean(i % 2 == 0))).collect())
+    }
+
+    #[test]
+    fn it_works() {
+        assert_eq!(2 + 2, 4);
+    }
+
+    #[test]
+    fn generic_and_dyn_streams_agree() {
+        let v = msgpack!({"a": [1, 2.5, "x"]});
+        let mut concrete = Vec::new();
+        let mut erased = Vec::new();
+        assert!(encode_to(&mut concrete, &v).is_ok());
+        assert!(encode_to(&mut erased as &mut dyn Write, &v).is_ok());
+        assert_eq!(concrete, erased);
+
+        let mut r: &[u8] = &concrete;
+        assert_eq!(decode_from(&mut r).ok().as_ref(), Some(&v));
+        let mut r: &[u8] = &erased;
+        assert_eq!(decode_from(&mut r as &mut dyn Read).ok(), Some(v));
+    }
+
+    /// Accepts one byte per call, interrupting every other call.
+    struct Trickle {
+        out: Vec<u8>,
+        interrupt: bool
+    }
+
+    impl Write for Trickle {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.interrupt = !self.interrupt;
+            if self.interrupt {
+                return Err(std::io::ErrorKind::Interrupted.into());
+            }
+            self.out.extend(buf.iter().take(1));
+            Ok(buf.len().min(1))
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Stops accepting bytes once `limit` have been written.
+    struct Full {
+        limit: usize
+    }
+
+    impl Write for Full {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(self.limit);
+            self.limit -= n;
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Records each call, accepting at most `max` bytes per call.
+    struct Calls {
+        out: Vec<u8>,
+        writes: usize,
+        vectored: usize,
+        max: usize
+    }
+
+    impl Write for Calls {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.writes += 1;
+            let n = buf.len().min(self.max);
+            self.out.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn write_vectored(&mut self, bufs: &[IoSlice]) -> std::io::Result<usize> {
+            self.vectored += 1;
+            let mut n = 0;
+            for buf in bufs {
+                let take = buf.len().min(self.max - n);
+                self.out.extend_from_slice(&buf[..take]);
+                n += take;
+            }
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn large_payloads_are_written_vectored() {
+        let s = "x".repeat(70_000);
+        let v = msgpack!([s.as_str(), Value::Bytes(vec![1; 5000]), "short"]);
+        let mut w = Calls { out: Vec::new(), writes: 0, vectored: 0, max: usize::MAX };
+        assert!(encode_to(&mut w, &v).is_ok());
+        assert_eq!(Some(&w.out), encode_to_vec(&v).ok().as_ref());
+        assert_eq!(w.vectored, 2);
+        // The array header, and the short string with its header.
+        assert_eq!(w.writes, 2);
+
+        // Each scalar and header goes out in one call.
+        let v = msgpack!([300, -70_000, 1.5, "key", Value::Bytes(vec![1; 20]), {"a": u64::MAX}]);
+        let mut w = Calls { out: Vec::new(), writes: 0, vectored: 0, max: usize::MAX };
+        assert!(encode_to(&mut w, &v).is_ok());
+        assert_eq!(Some(&w.out), encode_to_vec(&v).ok().as_ref());
+        assert_eq!(w.writes, 9);
+
+        // A short vectored write is finished off.
+        let mut w = Calls { out: Vec::new(), writes: 0, vectored: 0, max: 3 };
+        assert!(encode_to(&mut w, &v).is_ok());
+        assert_eq!(Some(&w.out), encode_to_vec(&v).ok().as_ref());
+        let mut w = Trickle { out: Vec::new(), interrupt: false };
+        assert!(encode_to(&mut w, &v).is_ok());
+        assert_eq!(Some(&w.out), encode_to_vec(&v).ok().as_ref());
+        assert!(matches!(encode_to(&mut Full { limit: 2 }, &v), Err(Error::Io(ref e)) if e.kind() == std::io::ErrorKind::WriteZero));
+    }
+
+    #[test]
+    fn short_writes_are_retried() {
+        let v = msgpack!({"name": "a".repeat(40), "items": [1, -200, 3.5, null, true], "bin": Value::Bytes(vec![7; 300])});
+        let mut w = Trickle { out: Vec::new(), interrupt: false };
+        assert!(encode_to(&mut w, &v).is_ok());
+        assert_eq!(dec(&w.out), v);
+        assert_eq!(w.out.len(), enc(&v).len());
+    }
+
+    #[test]
+    fn writes_that_stall_fail() {
+        let v = msgpack!(["abcdef", 1, 2, 3]);
+        for limit in 0..enc(&v).len() {
+            match encode_to(&mut Full { limit }, &v) {
+                Err(Error::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::WriteZero),
+                r => panic!("expected a write error at {}, got {:?}", limit, r.err())
+            }
+        }
+    }
+
+    /// Hands out at most one byte per read, interrupting every other call,
+    /// like a slow socket.
+    struct Drip<'a> {
+        input: &'a [u8],
+        interrupt: bool
+    }
+
+    impl Read for Drip<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.interrupt = !self.interrupt;
+            if self.interrupt {
+                return Err(std::io::ErrorKind::Interrupted.into());
+            }
+            let n = self.input.len().min(buf.len()).min(1);
+            buf[..n].copy_from_slice(&self.input[..n]);
+            self.input = &self.input[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn short_reads_are_completed() {
+        let v = msgpack!({"name": "a".repeat(40), "n": [300, -70000, 1.5, 2e100], "ext": Value::Ext(3, vec![1, 2, 3])});
+        let buf = enc(&v.clone());
+        let mut r = Drip { input: &buf, interrupt: false };
+        assert_eq!(decode_from(&mut r).ok(), Some(v));
+        assert!(r.input.is_empty());
+    }
+
+    #[test]
+    fn short_reads_then_eof_fail() {
+        let buf = enc(&msgpack!(["abcdef", 1000]));
+        for cut in 0..buf.len() {
+            let mut r = Drip { input: &buf[..cut], interrupt: false };
+            assert!(matches!(decode_from(&mut r).map_err(Error::into_root), Err(Error::UnexpectedEof { .. })));
+        }
+        let mut r = Drip { input: &buf[..4], interrupt: false };
+        assert!(matches!(decode_from(&mut r).map_err(Error::into_root), Err(Error::UnexpectedEof { needed: 6, got: 2 })));
+    }
+
+    #[test]
+    fn decodes_from_buffered_readers() {
+        let values = [msgpack!({"a": [1, 2, "x".repeat(100)]}), msgpack!(null), Value::Bytes(vec![9; 5000])];
+        let mut buf = Vec::new();
+        for v in &values {
+            assert!(encode_to(&mut buf, v).is_ok());
+        }
+        for capacity in [1, 3, 64, 8192] {
+            let mut r = std::io::BufReader::with_capacity(capacity, Drip { input: &buf, interrupt: false });
+            for v in &values {
+                assert_eq!(decode_from_buf(&mut r).ok().as_ref(), Some(v));
+            }
+            assert!(matches!(decode_from_buf(&mut r).map_err(Error::into_root), Err(Error::UnexpectedEof { needed: 1, got: 0 })));
+        }
+
+        let mut r: &[u8] = &[0xa5, b'a', b'b'];
+        match decode_from_buf(&mut r) {
+            Err(Error::Decode { offset, error, .. }) => {
+                assert_eq!(offset, 3);
+                assert!(matches!(*error, Error::UnexpectedEof { needed: 5, got: 2 }));
+            },
+            r => panic!("expected a truncated string, got {:?}", r)
+        }
+    }
+
+    #[test]
+    fn deep_nesting_does_not_recurse() {
+        let depth = 200_000;
+        let mut buf = vec![0x91; depth];
+        buf.push(0xc0);
+        let opts = DecodeOptions { max_depth: usize::MAX, ..DecodeOptions::default() };
+        let mut r: &[u8] = &buf;
+        let mut v = ok(decode_with(&mut r, &opts));
+        // Take the result apart by hand, as dropping it would recurse.
+        let mut levels = 0;
+        while let Value::Array(mut items) = v {
+            assert_eq!(items.len(), 1);
+            v = items.pop().unwrap_or_default();
+            levels += 1;
+        }
+        assert_eq!(levels, depth);
+        assert_eq!(v, Value::Null);
+    }
+
+    #[test]
+    fn deep_values_encode_without_recursion() {
+        let depth = 200_000;
+        let mut v = Value::Null;
+        for i in 0..depth {
+            v = if i % 2 == 0 {
+                Value::Array(vec![v])
+            } else {
+                Value::Map(std::iter::once((Value::Integer(1), v)).collect())
+            };
+        }
+        let opts = EncodeOptions { max_depth: usize::MAX, ..EncodeOptions::default() };
+        let mut buf = Vec::new();
+        assert!(encode_with(&mut buf, &v, &opts).is_ok());
+        assert_eq!(buf.len(), depth + depth / 2 + 1);
+        assert_eq!(&buf[..4], [0x81, 0x01, 0x91, 0x81]);
+        assert!(matches!(encode_to_vec(&v), Err(Error::DepthLimitExceeded)));
+
+        // Take the value apart by hand, as dropping it would recurse.
+        loop {
+            v = match v {
+                Value::Array(mut items) => items.pop().unwrap_or_default(),
+                Value::Map(mut m) => map_remove(&mut m, &Value::Integer(1)).unwrap_or_default(),
+                _ => break
+            };
+        }
+    }
+
+    #[test]
+    fn decode_limits() {
+        let decode_opts = |buf: &[u8], opts: &DecodeOptions| {
+            let mut r = buf;
+            decode_with(&mut r, opts).map_err(Error::into_root)
+        };
+        let buf = enc(&msgpack!({"k": [1, 2, 3], "s": "abcdef", "b": Value::Bytes(vec![0; 4])}));
+        assert!(decode_opts(&buf, &DecodeOptions::default()).is_ok());
+
+        let opts = DecodeOptions { max_container_len: 2, ..DecodeOptions::default() };
+        assert!(matches!(decode_opts(&buf, &opts), Err(Error::ContainerTooLong { len: 3, limit: 2 })));
+        let opts = DecodeOptions { max_payload_len: 5, ..DecodeOptions::default() };
+        assert!(matches!(decode_opts(&buf, &opts), Err(Error::PayloadTooLong { len: 6, limit: 5 })));
+        let opts = DecodeOptions { max_alloc: 64, ..DecodeOptions::default() };
+        assert!(matches!(decode_opts(&buf, &opts), Err(Error::AllocLimitExceeded { limit: 64 })));
+        let opts = DecodeOptions { max_container_len: 3, max_payload_len: 6, max_alloc: 1 << 20, ..DecodeOptions::default() };
+        assert!(decode_opts(&buf, &opts).is_ok());
+
+        // Declared lengths are checked before any payload is read.
+        let opts = DecodeOptions { max_payload_len: 1 << 20, ..DecodeOptions::default() };
+        let forged = [0xdb, 0xff, 0xff, 0xff, 0xff];
+        assert!(matches!(decode_opts(&forged, &opts), Err(Error::PayloadTooLong { .. })));
+        let opts = DecodeOptions { max_alloc: 1 << 20, ..DecodeOptions::default() };
+        let forged = [0xdd, 0x00, 0x10, 0x00, 0x00];
+        assert!(matches!(decode_opts(&forged, &opts), Err(Error::AllocLimitExceeded { .. })));
+
+        // Values kept raw are held to the same limits as they are read.
+        let raw = |opts: DecodeOptions| DecodeOptions { raw_depth: Some(1), ..opts };
+        let opts = raw(DecodeOptions { max_payload_len: 1 << 20, ..DecodeOptions::default() });
+        assert!(matches!(decode_opts(&[0x91, 0x91, 0xdb, 0xff, 0xff, 0xff, 0xff], &opts), Err(Error::PayloadTooLong { .. })));
+        let opts = raw(DecodeOptions { max_container_len: 2, ..DecodeOptions::default() });
+        assert!(matches!(decode_opts(&[0x91, 0x91, 0x93], &opts), Err(Error::ContainerTooLong { len: 3, limit: 2 })));
+        let opts = raw(DecodeOptions { max_alloc: 100, ..DecodeOptions::default() });
+        let big = enc(&msgpack!([Value::Bytes(vec![0; 1000])]));
+        match decode_opts(&big, &opts) {
+            Err(Error::AllocLimitExceeded { limit: 100 }) => {},
+            other => panic!("expected the alloc limit, got {:?}", other)
+        }
+        assert!(decode_opts(&buf, &raw(DecodeOptions::default())).is_ok());
+    }
+
+    #[test]
+    fn value_clone_debug_default() {
+        let v = Value::Array(vec![Value::Integer(1), Value::String("a".into())]);
+        assert_eq!(v.clone(), v);
+        assert_eq!(format!("{:?}", v), "Array([Integer(1), String(\"a\")])");
+        assert_eq!(Value::default(), Value::Null);
+    }
+
+    #[test]
+    fn value_ordering() {
+        let mut values = vec![
+            Value::Ext(1, vec![]),
+            Value::Map(Map::default()),
+            Value::Array(vec![]),
+            Value::Bytes(vec![]),
+            Value::String("a".into()),
+            Value::Float(f64::NAN),
+            Value::Float(-0.0),
+            Value::Float(0.0),
+            Value::Float(f64::NEG_INFINITY),
+            Value::Integer(2),
+            Value::FixedInteger(1, IntFormat::U8),
+            Value::Boolean(true),
+            Value::Boolean(false),
+            Value::Null
+        ];
+        values.sort();
+        let rendered: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+        assert_eq!(rendered, vec![
+            "null", "false", "true", "1", "2", "-inf", "-0.0", "0.0", "NaN",
+            "\"a\"", "h\"\"", "[]", "{}", "{\"$ext\":1,\"data\":h\"\"}"
+        ]);
+
+        assert_eq!(Value::Float(f64::NAN), Value::Float(f64::NAN));
+        assert_ne!(Value::Float(0.0), Value::Float(-0.0));
+
+        let a = int_map(50);
+        let b = Value::Map((0..50).rev().map(|i| (Value::Integer(i), Value::Boolean(i % 2 == 0))).collect());
+        assert_eq!(a.cmp(&b), std::cmp::Ordering::Equal);
+        let hash = |v: &Value| {
+            let mut h = std::collections::hash_map::DefaultHasher::new();
+            std::hash::Hash::hash(v, &mut h);
+            std::hash::Hasher::finish(&h)
+        };
+        assert_eq!(hash(&a), hash(&b));
+
+        let mut sorted = std::collections::BTreeMap::new();
+        sorted.insert(Value::String("b".into()), 1);
+        sorted.insert(Value::Integer(7), 2);
+        assert_eq!(sorted.keys().next(), Some(&Value::Integer(7)));
+    }
+
+    #[test]
+    fn value_display() {
+        let v = Value::Array(vec![
+            Value::Null,
+            Value::Boolean(true),
+            Value::Integer(-3),
+            Value::Float(1.0),
+            Value::String("a\"b\\\n\u{1}".into()),
+            Value::Bytes(vec![0x00, 0xff]),
+            Value::Ext(5, vec![0x0a]),
+            Value::Map(vec![(Value::String("k".into()), Value::Array(vec![]))].into_iter().collect())
+        ]);
+        assert_eq!(
+            v.to_string(),
+            r#"[null,true,-3,1.0,"a\"b\\\n\u0001",h"00ff",{"$ext":5,"data":h"0a"},{"k":[]}]"#
+        );
+    }
+
+    #[test]
+    fn array16_and_array32() {
+        let buf = enc(&int_array(16));
+        assert_eq!(buf[0], 0xdc);
+        assert!(dec(&buf) == int_array(16));
+
+        let buf = enc(&int_array(65536));
+        assert_eq!(buf[0], 0xdd);
+        assert_eq!(&buf[1..5], &[0x00, 0x01, 0x00, 0x00]);
+        assert!(dec(&buf) == int_array(65536));
+    }
+
+    #[test]
+    fn map16_and_map32() {
+        let buf = enc(&int_map(16));
+        assert_eq!(buf[0], 0xde);
+        assert!(dec(&buf) == int_map(16));
+
+        let buf = enc(&int_map(65536));
+        assert_eq!(buf[0], 0xdf);
+        assert!(dec(&buf) == int_map(65536));
+    }
+
+    fn nested(depth: usize) -> Value {
+        let mut v = Value::Null;
+        for _i in 0..depth {
+            v = Value::Array(vec![v]);
+        }
+        v
+    }
+
+    #[test]
+    fn encode_depth_limit() {
+        let opts = EncodeOptions { max_depth: 3, ..Default::default() };
+        let mut buf = Vec::new();
+        assert!(encode_with(&mut buf, &nested(3), &opts).is_ok());
+        assert_eq!(buf, &[0x91, 0x91, 0x91, 0xc0]);
+        assert!(matches!(encode_with(&mut buf, &nested(4), &opts), Err(Error::DepthLimitExceeded)));
+
+        let mut buf = Vec::new();
+        assert!(encode_to(&mut buf, &nested(DEFAULT_MAX_DEPTH)).is_ok());
+        let deep = nested(DEFAULT_MAX_DEPTH + 1);
+        assert!(matches!(encode_to(&mut buf, &deep), Err(Error::DepthLimitExceeded)));
+    }
+
+    #[test]
+    fn decode_depth_limit() {
+        let buf = vec![0x91; 100_000];
+        let mut r = &buf[..];
+        assert!(matches!(decode_from(&mut r).map_err(Error::into_root), Err(Error::DepthLimitExceeded)));
+
+        let buf = enc(&nested(DEFAULT_MAX_DEPTH));
+        assert!(dec(&buf) == nested(DEFAULT_MAX_DEPTH));
+
+        let opts = DecodeOptions { max_depth: 2, ..Default::default() };
+        let mut r: &[u8] = &[0x91, 0x81, 0x01, 0x91, 0xc0];
+        assert!(matches!(decode_with(&mut r, &opts).map_err(Error::into_root), Err(Error::DepthLimitExceeded)));
+    }
+
+    #[test]
+    fn forged_lengths_do_not_allocate() {
+        let forged: &[&[u8]] = &[
+            &[0xdb, 0xff, 0xff, 0xff, 0xff, b'a'],
+            &[0xc6, 0xff, 0xff, 0xff, 0xff],
+            &[0xc9, 0xff, 0xff, 0xff, 0xff, 0x01],
+            &[0xdd, 0xff, 0xff, 0xff, 0xff],
+            &[0xdf, 0xff, 0xff, 0xff, 0xff, 0x01]
+        ];
+        for bytes in forged {
+            let mut r = *bytes;
+            assert!(matches!(decode_from(&mut r).map_err(Error::into_root), Err(Error::UnexpectedEof { .. })));
+        }
+    }
+
+    /// Inputs that have tripped decoders in the past: truncated headers,
+    /// forged lengths, reserved markers and invalid text in every position.
+    const CORPUS: &[&[u8]] = &[
+        &[],
+        &[0xc1],
+        &[0xd9],
+        &[0xda, 0x00],
+        &[0xd8, 0x01],
+        &[0xc7, 0x01],
+        &[0x81, 0xc1, 0x01],
+        &[0x81, 0x01],
+        &[0xdc, 0xff],
+        &[0xde, 0x00, 0x01, 0x92],
+        &[0xa2, 0xc3, 0x28],
+        &[0xd6, 0xff, 0x00, 0x00],
+        &[0xc8, 0xff, 0xff, 0xff, 0x00],
+        &[0xcf, 0xff, 0xff, 0xff],
+        &[0x9f, 0x9f, 0x9f, 0x9f, 0x9f]
+    ];
+
+    #[test]
+    fn no_panic_on_corpus_or_mutations() {
+        let strict = DecodeOptions { strict: true, duplicate_keys: DuplicateKeys::Error, ..Default::default() };
+        let check = |bytes: &[u8]| {
+            let mut r = bytes;
+            let _ = decode_from(&mut r);
+            let mut r = bytes;
+            let _ = decode_with(&mut r, &strict);
+            let _ = decode_partial(bytes, &DecodeOptions::default());
+            let _ = validate_all(bytes);
+        };
+        for bytes in CORPUS {
+            check(bytes);
+        }
+
+        let seed = enc(&Value::Map(vec![
+            (Value::String("a".into()), int_array(20)),
+            (Value::Integer(-1000), Value::Bytes(vec![1, 2, 3])),
+            (Value::Boolean(true), Value::Ext(-1, vec![0; 8]))
+        ].into_iter().collect()));
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        for _i in 0..20_000 {
+            let mut bytes = seed.clone();
+            for _j in 0..(next() % 4 + 1) {
+                let at = (next() as usize) % bytes.len();
+                bytes[at] = next() as u8;
+            }
+            let cut = (next() as usize) % (bytes.len() + 1);
+            check(&bytes[..cut]);
+        }
+    }
+
+    #[test]
+    fn length_limit() {
+        assert!(matches!(check_len(u32::MAX as usize), Ok(len) if len == u32::MAX as usize));
+        if usize::MAX as u64 > u32::MAX as u64 {
+            assert!(matches!(check_len(u32::MAX as usize + 1), Err(Error::TooLong)));
+            assert!(matches!(decode_len(u32::MAX), Ok(len) if len as u64 == u32::MAX as u64));
+        }
+    }
+
+    #[test]
+    fn error_kinds() {
+        let mut r: &[u8] = &[0x92, 0x01];
+        assert!(matches!(decode_from(&mut r).map_err(Error::into_root), Err(Error::UnexpectedEof { needed: 1, got: 0 })));
+        let mut r: &[u8] = &[0xa5, b'a', b'b'];
+        assert!(matches!(decode_from(&mut r).map_err(Error::into_root), Err(Error::UnexpectedEof { needed: 5, got: 2 })));
+        let mut r: &[u8] = &[0xcd, 0x01];
+        assert!(matches!(decode_from(&mut r).map_err(Error::into_root), Err(Error::UnexpectedEof { needed: 2, got: 1 })));
+        let mut r: &[u8] = &[0xa1, 0xff];
+        assert!(matches!(decode_from(&mut r).map_err(Error::into_root), Err(Error::InvalidUtf8(_))));
+        let mut w: &mut [u8] = &mut [0; 2];
+        assert!(matches!(encode_to(&mut w, &Value::String("abc".into())), Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn decode_error_context() {
+        let mut buf = enc(&Value::Map(vec![
+            (Value::String("items".into()), Value::Array(vec![Value::Null, Value::Null, Value::Null, Value::Map(vec![
+                (Value::String("name".into()), Value::String("x".into()))
+            ].into_iter().collect())]))
+        ].into_iter().collect()));
+        let last = buf.len() - 2;
+        buf[last] = 0xc1;
+        let mut r = &buf[..];
+        match decode_from(&mut r) {
+            Err(Error::Decode { offset, path, error }) => {
+                assert_eq!(offset, buf.len() as u64 - 1);
+                assert_eq!(path, "$.items[3].name");
+                assert!(matches!(*error, Error::ReservedMarker { .. }));
+            },
+            _ => panic!("expected decode error")
+        }
+    }
+
+    #[test]
+    fn slice_apis() {
+        let mut buf = enc(&Value::String("hi".into()));
+        buf.extend_from_slice(&[0x2a, 0xc0]);
+        assert!(matches!(decode_from_slice(&buf), Ok((Value::String(ref s), 3)) if s == "hi"));
+        assert!(matches!(decode_from_slice(&buf[3..]), Ok((Value::Integer(42), 1))));
+        assert!(matches!(decode_from_slice(&buf[..2]), Err(Error::Decode { offset: 2, .. })));
+    }
+
+    #[test]
+    fn skip_values() {
+        let first = msgpack!({"name": "a".repeat(1000), "n": [300, -70000, 1.5], "ext": Value::Ext(3, vec![1, 2, 3])});
+        let mut buf = enc(&first);
+        let end = buf.len();
+        buf.extend_from_slice(&[0x2a, 0xa2, 0xff, 0xfe, 0x91]);
+        assert_eq!(skip_value_at(&buf, 0).ok(), Some(0..end));
+        assert_eq!(skip_value_at(&buf, end).ok(), Some(end..end + 1));
+        // Strings are skipped without being checked.
+        assert_eq!(skip_value_at(&buf, end + 1).ok(), Some(end + 1..end + 4));
+        assert!(matches!(skip_value_at(&buf, end + 4), Err(Error::Decode { offset, .. }) if offset as usize == buf.len()));
+        assert!(skip_value_at(&buf, buf.len() + 1).is_err());
+        assert!(matches!(skip_value_at(&[0x91, 0xc1], 0).map_err(Error::into_root), Err(Error::ReservedMarker { offset: 1, .. })));
+
+        let mut r = Drip { input: &buf, interrupt: false };
+        assert!(skip_value(&mut r).is_ok());
+        assert_eq!(r.input, &buf[end..]);
+        assert!(skip_value(&mut r).is_ok());
+        assert!(skip_value(&mut r).is_ok());
+        assert_eq!(r.input, [0x91]);
+        assert!(matches!(skip_value(&mut r).map_err(Error::into_root), Err(Error::UnexpectedEof { needed: 1, got: 0 })));
+        // A forged length fails at the end of the input.
+        let mut r: &[u8] = &[0xc6, 0xff, 0xff, 0xff, 0xff, 0x00];
+        assert!(matches!(skip_value(&mut r).map_err(Error::into_root), Err(Error::UnexpectedEof { got: 1, .. })));
+    }
+
+    #[test]
+    fn encode_to_vec_allocates_once() {
+        let v = msgpack!({"name": "x".repeat(10_000), "items": [1, 2.5, Value::Bytes(vec![7; 5000])]});
+        let buf = enc(&v);
+        assert_eq!(Some(buf.len()), v.encoded_len().ok());
+        assert_eq!(buf.capacity(), buf.len());
+        assert_eq!(decode_from_slice(&buf).ok().map(|(d, _)| d), Some(v));
+    }
+
+    #[test]
+    fn encode_into_fixed_slices() {
+        let v = msgpack!({"name": "a".repeat(5000), "n": [1, 2, 3]});
+        let expected = enc(&v.clone());
+        let mut buf = vec![0u8; expected.len() + 10];
+        assert_eq!(encode_to_slice(&v, &mut buf).ok(), Some(expected.len()));
+        assert_eq!(&buf[..expected.len()], &expected[..]);
+        let mut exact = vec![0u8; expected.len()];
+        assert_eq!(encode_to_slice(&v, &mut exact).ok(), Some(expected.len()));
+        let mut small = vec![0u8; expected.len() - 1];
+        assert!(matches!(
+            encode_to_slice(&v, &mut small),
+            Err(Error::BufferTooSmall { needed, available }) if needed == expected.len() && available == needed - 1
+        ));
+        assert!(matches!(encode_to_slice(&msgpack!(1), &mut []), Err(Error::BufferTooSmall { needed: 1, available: 0 })));
+        let opts = EncodeOptions { max_depth: 0, ..EncodeOptions::default() };
+        assert!(matches!(encode_slice_with(&msgpack!([]), &mut [0; 4], &opts), Err(Error::DepthLimitExceeded)));
+    }
+
+    #[test]
+    fn partial_decode() {
+        let buf = [0x93, 0x01, 0x81, 0xa1, b'k', 0x92, 0x02, 0xc1, 0x03, 0xc0];
+        match decode_partial(&buf[..9], &DecodeOptions::default()) {
+            Err(p) => {
+                assert_eq!(p.consumed, 8);
+                assert!(matches!(p.error.into_root(), Error::ReservedMarker { offset: 7, expected: Expected::ArrayElement }));
+                let inner = Value::Map(vec![
+                    (Value::String("k".into()), Value::Array(vec![Value::Integer(2)]))
+                ].into_iter().collect());
+                assert!(p.value == Some(Value::Array(vec![Value::Integer(1), inner])));
+            },
+            Ok(_) => panic!("expected partial decode")
+        }
+
+        assert!(matches!(decode_partial(&buf[8..], &DecodeOptions::default()), Ok((Value::Integer(3), 1))));
+        assert!(matches!(decode_partial(&[0xa2, b'a'], &DecodeOptions::default()), Err(Partial { consumed: 2, value: None, .. })));
+    }
+
+    #[test]
+    fn error_display_and_source() {
+        use std::error::Error as _;
+
+        let mut r: &[u8] = &[0x91, 0xa1, 0xff];
+        let e = match decode_from(&mut r) {
+            Err(e) => e,
+            Ok(_) => panic!("expected decode error")
+        };
+        assert_eq!(e.to_string(), "invalid utf-8 in string: invalid utf-8 sequence of 1 bytes from index 0 at $[0] (byte 3)");
+        let cause = e.source().and_then(|e| e.source());
+        assert!(matches!(cause, Some(c) if c.is::<std::str::Utf8Error>()));
+
+        let mut r: &[u8] = &[0x81, 0xc1, 0x01];
+        let e = decode_from(&mut r).map_err(Error::into_root).err().map(|e| e.to_string());
+        assert_eq!(e.as_deref(), Some("expected map key, got reserved marker 0xc1 at byte 1"));
+
+        let boxed: Box<dyn std::error::Error> = Box::new(Error::DuplicateKey);
+        assert_eq!(boxed.to_string(), "duplicate map key");
+    }
+
+    #[test]
+    fn reserved_marker() {
+        let mut r: &[u8] = &[0xc1];
+        assert!(matches!(decode_from(&mut r).map_err(Error::into_root), Err(Error::ReservedMarker { offset: 0, expected: Expected::Value })));
+        let mut r: &[u8] = &[0x92, 0xa2, b'h', b'i', 0x81, 0x01, 0xc1];
+        assert!(matches!(decode_from(&mut r).map_err(Error::into_root), Err(Error::ReservedMarker { offset: 6, expected: Expected::MapValue })));
+    }
+
+    #[test]
+    fn integer_markers() {
+        let cases: &[(i128, &[u8])] = &[
+            (0, &[0x00]),
+            (127, &[0x7f]),
+            (128, &[0xcc, 0x80]),
+            (255, &[0xcc, 0xff]),
+            (256, &[0xcd, 0x01, 0x00]),
+            (65536, &[0xce, 0x00, 0x01, 0x00, 0x00]),
+            (u32::MAX as i128 + 1, &[0xcf, 0, 0, 0, 0x01, 0, 0, 0, 0]),
+            (u64::MAX as i128, &[0xcf, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]),
+            (-1, &[0xff]),
+            (-32, &[0xe0]),
+            (-33, &[0xd0, 0xdf]),
+            (-129, &[0xd1, 0xff, 0x7f]),
+            (-32769, &[0xd2, 0xff, 0xff, 0x7f, 0xff]),
+            (i64::MIN as i128, &[0xd3, 0x80, 0, 0, 0, 0, 0, 0, 0])
+        ];
+        for (i, bytes) in cases {
+            assert_eq!(enc(&Value::Integer(*i)), *bytes);
+            assert!(dec(bytes) == Value::Integer(*i));
+        }
+    }
+
+    #[test]
+    fn preserve_int_width() {
+        let opts = DecodeOptions { preserve_int_width: true, ..Default::default() };
+        let cases: &[&[u8]] = &[
+            &[0xcc, 0x05],
+            &[0xcd, 0x00, 0x05],
+            &[0xce, 0x00, 0x00, 0x00, 0x05],
+            &[0xcf, 0, 0, 0, 0, 0, 0, 0, 0x05],
+            &[0xd0, 0x05],
+            &[0xd1, 0xff, 0xfb],
+            &[0xd2, 0xff, 0xff, 0xff, 0xfb],
+            &[0xd3, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfb],
+            &[0x92, 0xcd, 0x00, 0x01, 0xd0, 0x7f]
+        ];
+        for bytes in cases {
+            let mut r = *bytes;
+            let value = ok(decode_with(&mut r, &opts));
+            assert!(value == dec(bytes));
+            assert_eq!(enc(&value), *bytes);
+        }
+
+        let mut r: &[u8] = &[0xcc, 0x80];
+        assert!(matches!(decode_with(&mut r, &opts), Ok(Value::Integer(128))));
+
+        let mut buf = Vec::new();
+        let value = Value::FixedInteger(256, IntFormat::U8);
+        assert!(matches!(encode_to(&mut buf, &value), Err(Error::IntegerOutOfRange)));
+    }
+
+    #[test]
+    fn int128_ext() {
+        let big = u64::MAX as i128 + 1;
+        let mut buf = Vec::new();
+        assert!(matches!(encode_to(&mut buf, &Value::Integer(big)), Err(Error::IntegerOutOfRange)));
+        assert!(matches!(encode_to(&mut buf, &Value::Integer(i128::MIN)), Err(Error::IntegerOutOfRange)));
+
+        let eopts = EncodeOptions { int128_ext: Some(42), ..Default::default() };
+        let dopts = DecodeOptions { int128_ext: Some(42), ..Default::default() };
+        for i in [big, i64::MIN as i128 - 1, i128::MAX, i128::MIN] {
+            let mut buf = Vec::new();
+            assert!(encode_with(&mut buf, &Value::Integer(i), &eopts).is_ok());
+            assert_eq!(&buf[..2], &[0xd8, 42]);
+            let mut r = &buf[..];
+            assert!(matches!(decode_with(&mut r, &dopts), Ok(Value::Integer(v)) if v == i));
+            assert!(matches!(dec(&buf), Value::Ext(42, _)));
+        }
+    }
+
+    #[test]
+    fn unknown_ext_policy() {
+        let bytes: &[u8] = &[0x93, 0xd4, 0x05, 0x00, 0xd6, 0xff, 0, 0, 0, 0, 0xd4, 0x07, 0x00];
+        let value = dec(bytes);
+        assert!(matches!(&value, Value::Array(a) if a[0] == Value::Ext(5, vec![0])));
+
+        let opts = DecodeOptions { unknown_ext: ExtPolicy::Reject, known_ext: vec![5], ..Default::default() };
+        let mut r = bytes;
+        assert!(matches!(decode_with(&mut r, &opts).map_err(Error::into_root), Err(Error::UnknownExt(7))));
+
+        let opts = DecodeOptions { known_ext: vec![5, 7], ..opts };
+        let mut r = bytes;
+        assert!(matches!(decode_with(&mut r, &opts), Ok(v) if v == value));
+    }
+
+    #[test]
+    fn float_markers() {
+        assert_eq!(enc(&Value::Float(1.5)), &[0xcb, 0x3f, 0xf8, 0, 0, 0, 0, 0, 0]);
+
+        let opts = EncodeOptions { prefer_f32: true, ..Default::default() };
+        let mut buf = Vec::new();
+        assert!(encode_with(&mut buf, &Value::Float(1.5), &opts).is_ok());
+        assert_eq!(buf, &[0xca, 0x3f, 0xc0, 0, 0]);
+        assert!(dec(&buf) == Value::Float(1.5));
+
+        let mut buf = Vec::new();
+        assert!(encode_with(&mut buf, &Value::Float(0.1), &opts).is_ok());
+        assert_eq!(buf[0], 0xcb);
+        assert!(dec(&buf) == Value::Float(0.1));
+    }
+
+    #[test]
+    fn strict_rejects_non_canonical() {
+        let strict = DecodeOptions { strict: true, ..Default::default() };
+        let non_canonical: &[&[u8]] = &[
+            &[0xcc, 0x05],
+            &[0xcd, 0x00, 0xff],
+            &[0xd0, 0xff],
+            &[0xd3, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00],
+            &[0xd9, 0x03, b'a', b'b', b'c'],
+            &[0xc5, 0x00, 0x01, 0x00],
+            &[0xc7, 0x04, 0x01, 0, 0, 0, 0],
+            &[0xdc, 0x00, 0x00],
+            &[0x91, 0xcc, 0x01]
+        ];
+        for bytes in non_canonical {
+            let mut r = *bytes;
+            assert!(matches!(decode_with(&mut r, &strict).map_err(Error::into_root), Err(Error::NonCanonical(_))));
+            let mut r = *bytes;
+            assert!(decode_from(&mut r).is_ok());
+        }
+
+        for value in [int_array(16), int_map(16), Value::Integer(-33), Value::Integer(200)] {
+            let buf = enc(&value);
+            let mut r = &buf[..];
+            assert!(decode_with(&mut r, &strict).is_ok());
+        }
+    }
+
+    #[test]
+    fn interns_repeated_keys() {
+        let long = "k".repeat(INTERN_MAX_LEN + 1);
+        let rows: Vec<Value> = (0..100).map(|i| msgpack!({"id": i, long.as_str(): "id"})).collect();
+        let buf = enc(&Value::Array(rows.clone()));
+        let opts = DecodeOptions { intern_keys: true, ..Default::default() };
+        let decoded = ok(decode_with(&mut &buf[..], &opts));
+        assert_eq!(decoded, Value::Array(rows));
+        let mut ids = Vec::new();
+        for row in decoded.members() {
+            for (k, v) in row.entries() {
+                match k {
+                    Value::SharedString(s) => ids.push(s.clone()),
+                    _ => assert_eq!(k.as_str(), Some(long.as_str()))
+                }
+                // Only keys are interned.
+                assert!(!matches!(v, Value::SharedString(_)));
+            }
+        }
+        assert_eq!(ids.len(), 100);
+        assert!(ids.iter().all(|s| Arc::ptr_eq(s, &ids[0])));
+
+        let bad = [0x81, 0xa1, 0xff, 0xc0];
+        let lossy = DecodeOptions { utf8: Utf8Policy::Lossy, ..opts.clone() };
+        assert_eq!(decode_with(&mut &bad[..], &lossy).ok(), Some(msgpack!({"\u{fffd}": null})));
+        assert!(decode_with(&mut &bad[..], &opts).is_err());
+    }
+
+    #[test]
+    fn charges_short_strings() {
+        // Keys that are new to the interner, or strings too long or invalid
+        // to store inline, each allocate and count towards `max_alloc` on
+        // top of the slots of their container.
+        let slot = std::mem::size_of::<Value>();
+        let keys: Map = (0..200).map(|i| (Value::from(format!("key{:05}", i)), Value::Null)).collect();
+        let buf = enc(&Value::Map(keys));
+        let limit = 400 * slot + 800;
+        let interned = DecodeOptions { intern_keys: true, max_alloc: limit, ..Default::default() };
+        let e = decode_with(&mut &buf[..], &interned).err().map(Error::into_root);
+        assert!(matches!(e, Some(Error::AllocLimitExceeded { .. })));
+        let interned = DecodeOptions { max_alloc: limit + 800, ..interned };
+        assert!(decode_with(&mut &buf[..], &interned).is_ok());
+
+        let mut bad = vec![0xdc, 0, 200];
+        bad.extend((0..200).flat_map(|_| vec![0xa1, 0xff]));
+        let limit = 200 * slot + 100;
+        let inline = DecodeOptions { inline_strings: true, utf8: Utf8Policy::Lossy, max_alloc: limit, ..Default::default() };
+        let e = decode_with(&mut &bad[..], &inline).err().map(Error::into_root);
+        assert!(matches!(e, Some(Error::AllocLimitExceeded { .. })));
+        let inline = DecodeOptions { max_alloc: limit + 100, ..inline };
+        assert!(decode_with(&mut &bad[..], &inline).is_ok());
+    }
+
+    #[test]
+    fn duplicate_keys() {
+        let bytes: &[u8] = &[0x82, 0x01, 0x0a, 0x01, 0x0b];
+        let first = |v: &Value| match v {
+            Value::Map(m) => m.len() == 1 && m.get(&Value::Integer(1)) == Some(&Value::Integer(10)),
+            _ => false
+        };
+        let last = |v: &Value| match v {
+            Value::Map(m) => m.len() == 1 && m.get(&Value::Integer(1)) == Some(&Value::Integer(11)),
+            _ => false
+        };
+        assert!(last(&dec(bytes)));
+
+        let opts = DecodeOptions { duplicate_keys: DuplicateKeys::KeepFirst, ..Default::default() };
+        let mut r = bytes;
+        assert!(matches!(decode_with(&mut r, &opts), Ok(v) if first(&v)));
+
+        let opts = DecodeOptions { duplicate_keys: DuplicateKeys::Error, ..Default::default() };
+        let mut r = bytes;
+        assert!(matches!(decode_with(&mut r, &opts).map_err(Error::into_root), Err(Error::DuplicateKey)));
+    }
+
+    #[test]
+    fn utf8_policy() {
+        let bytes: &[u8] = &[0xa3, b'a', 0xff, b'b'];
+        let mut r = bytes;
+        assert!(decode_from(&mut r).is_err());
+
+        let lossy = DecodeOptions { utf8: Utf8Policy::Lossy, ..Default::default() };
+        let mut r = bytes;
+        assert!(matches!(decode_with(&mut r, &lossy), Ok(Value::String(s)) if s == "a\u{fffd}b"));
+
+        let raw = DecodeOptions { utf8: Utf8Policy::Bytes, ..Default::default() };
+        let mut r = bytes;
+        assert!(matches!(decode_with(&mut r, &raw), Ok(Value::Bytes(b)) if b == [b'a', 0xff, b'b']));
+    }
+
+    #[test]
+    fn float_canonicalization() {
+        let odd_nan = f64::from_bits(0xfff0_0000_dead_beef);
+        assert!(odd_nan.is_nan());
+        assert_eq!(&enc(&Value::Float(odd_nan))[1..], &0xfff0_0000_dead_beefu64.to_be_bytes());
+        assert_eq!(&enc(&Value::Float(-0.0))[1..], &(-0.0f64).to_bits().to_be_bytes());
+
+        let opts = EncodeOptions { canonical_nan: true, normalize_negative_zero: true, ..Default::default() };
+        let mut buf = Vec::new();
+        assert!(encode_with(&mut buf, &Value::Float(odd_nan), &opts).is_ok());
+        assert_eq!(&buf[1..], &f64::NAN.to_bits().to_be_bytes());
+
+        let mut buf = Vec::new();
+        assert!(encode_with(&mut buf, &Value::Float(-0.0), &opts).is_ok());
+        assert_eq!(&buf[1..], &[0; 8]);
+    }
+
+    #[test]
+    fn legacy_raw() {
+        let opts = EncodeOptions { legacy_raw: true, ..Default::default() };
+        let cases: &[(usize, &[u8])] = &[
+            (3, &[0xa3]),
+            (32, &[0xda, 0x00, 32]),
+            (255, &[0xda, 0x00, 0xff]),
+            (65536, &[0xdb, 0x00, 0x01, 0x00, 0x00])
+        ];
+        for (len, header) in cases {
+            let mut buf = Vec::new();
+            assert!(encode_with(&mut buf, &Value::String("x".repeat(*len)), &opts).is_ok());
+            assert_eq!(&buf[..header.len()], *header);
+
+            let mut raw = Vec::new();
+            assert!(encode_with(&mut raw, &Value::Bytes(vec![b'x'; *len]), &opts).is_ok());
+            assert_eq!(raw, buf);
+        }
+    }
+
+    #[test]
+    fn string_markers() {
+        let cases: &[(usize, &[u8])] = &[
+            (0, &[0xa0]),
+            (31, &[0xbf]),
+            (32, &[0xd9, 32]),
+            (255, &[0xd9, 0xff]),
+            (256, &[0xda, 0x01, 0x00]),
+            (65535, &[0xda, 0xff, 0xff]),
+            (65536, &[0xdb, 0x00, 0x01, 0x00, 0x00])
+        ];
+        for (len, header) in cases {
+            let s = "x".repeat(*len);
+            let buf = enc(&Value::String(s.clone()));
+            assert_eq!(&buf[..header.len()], *header);
+            assert_eq!(buf.len(), header.len() + len);
+            assert!(dec(&buf) == Value::String(s));
+        }
+    }
+
+    #[test]
+    fn ext_markers() {
+        let cases: &[(usize, &[u8])] = &[
+            (1, &[0xd4]),
+            (2, &[0xd5]),
+            (4, &[0xd6]),
+            (8, &[0xd7]),
+            (16, &[0xd8]),
+            (3, &[0xc7, 3]),
+            (256, &[0xc8, 0x01, 0x00]),
+            (65536, &[0xc9, 0x00, 0x01, 0x00, 0x00])
+        ];
+        for (len, header) in cases {
+            let buf = enc(&Value::Ext(5, vec![0xaa; *len]));
+            assert_eq!(&buf[..header.len()], *header);
+            assert_eq!(buf[header.len()], 5);
+            assert_eq!(buf.len(), header.len() + 1 + len);
+            assert!(dec(&buf) == Value::Ext(5, vec![0xaa; *len]));
+        }
+        assert!(dec(&[0xd4, 0xff, 0x01]) == Value::Ext(-1, vec![0x01]));
+    }
+
+    #[test]
+    fn take_and_replace() {
+        let mut doc = Value::Array(vec![Value::String("big".into()), Value::Integer(1)]);
+        if let Value::Array(items) = &mut doc {
+            assert_eq!(items[0].take(), Value::String("big".into()));
+            assert_eq!(items[1].replace(Value::Boolean(true)), Value::Integer(1));
+        }
+        assert_eq!(doc, Value::Array(vec![Value::Null, Value::Boolean(true)]));
+    }
+
+    #[test]
+    fn members_and_entries() {
+        let mut arr = int_array(3);
+        assert_eq!(arr.members().count(), 3);
+        for v in arr.members_mut() {
+            *v = Value::Null;
+        }
+        assert_eq!(arr, Value::Array(vec![Value::Null; 3]));
+
+        let mut map = int_map(4);
+        assert_eq!(map.entries().filter(|(_, v)| **v == Value::Boolean(true)).count(), 2);
+        for (_, v) in map.entries_mut() {
+            *v = Value::Boolean(false);
+        }
+        assert!(map.entries().all(|(_, v)| *v == Value::Boolean(false)));
+
+        let mut scalar = Value::Integer(1);
+        assert_eq!(scalar.members().count(), 0);
+        assert_eq!(scalar.members_mut().count(), 0);
+        assert_eq!(scalar.entries().count(), 0);
+        assert_eq!(arr.entries_mut().count(), 0);
+        assert_eq!(map.members().count(), 0);
+    }
+
+    #[test]
+    fn encoded_len_matches_output() {
+        let values = [
+            Value::Null,
+            Value::Integer(-33),
+            Value::Integer(u64::MAX as i128),
+            Value::Float(1.5),
+            Value::String("x".repeat(300)),
+            Value::Bytes(vec![0; 70000]),
+            int_array(20),
+            int_map(16),
+            Value::Ext(3, vec![1; 16]),
+            msgpack!({"a": [1, {"b": null}], "c": "d"})
+        ];
+        for v in values.iter() {
+            assert_eq!(v.encoded_len().ok(), Some(enc(&v.clone()).len()));
+        }
+        let opts = EncodeOptions { prefer_f32: true, ..Default::default() };
+        assert_eq!(Value::Float(1.5).encoded_len_with(&opts).ok(), Some(5));
+        assert!(matches!(Value::Integer(i128::MAX).encoded_len(), Err(Error::IntegerOutOfRange)));
+    }
+
+    #[test]
+    fn deep_size_counts_heap() {
+        let base = std::mem::size_of::<Value>();
+        assert_eq!(Value::Integer(1).deep_size_of(), base);
+        assert_eq!(Value::String(String::with_capacity(100)).deep_size_of(), base + 100);
+
+        let arr = Value::Array(vec![Value::Bytes(vec![0; 10]), Value::Null]);
+        assert_eq!(arr.deep_size_of(), base + 2 * base + 10);
+
+        let small = msgpack!({"k": "v"});
+        let big = msgpack!({"k": "v".repeat(1000)});
+        assert!(small.deep_size_of() > base + 2);
+        assert!(big.deep_size_of() >= small.deep_size_of() + 999);
+    }
+
+    #[cfg(all(feature = "btree_map", not(feature = "preserve_order")))]
+    #[test]
+    fn btree_maps_iterate_sorted() {
+        let v = msgpack!({"b": 1, "a": 2, 3: null});
+        let buf = enc(&v.clone());
+        assert_eq!(buf, [0x83, 0x03, 0xc0, 0xa1, b'a', 0x02, 0xa1, b'b', 0x01]);
+        assert_eq!(dec(&buf), v);
+    }
+
+    #[cfg(feature = "preserve_order")]
+    #[test]
+    fn preserves_key_order() {
+        let buf = [0x83, 0xa1, b'b', 0x01, 0xa1, b'a', 0x02, 0xa1, b'c', 0x03];
+        let mut v = dec(&buf);
+        let keys: Vec<String> = v.entries().map(|(k, _)| k.to_string()).collect();
+        assert_eq!(keys, ["\"b\"", "\"a\"", "\"c\""]);
+        assert_eq!(enc(&v.clone()), buf);
+
+        assert_eq!(v.remove_at("/b"), Some(Value::Integer(1)));
+        assert_eq!(v.to_string(), "{\"a\":2,\"c\":3}");
     }
 }
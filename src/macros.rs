@@ -0,0 +1,185 @@
+/// Builds a `Value` from a JSON-like literal:
+///
+/// ```
+/// use stuff::{msgpack, Value};
+///
+/// let port = 8080;
+/// let v = msgpack!({"key": [1, 2, null], "flag": true, "port": port, 7: "int key"});
+/// assert_eq!(v["key"][1], Value::Integer(2));
+/// assert_eq!(v["port"], Value::Integer(8080));
+/// ```
+///
+/// Any expression with a `From` conversion into `Value` can be spliced in
+/// as an element, a map value or a map key.
+#[macro_export]
+macro_rules! msgpack {
+    ($($tt:tt)+) => {
+        $crate::msgpack_internal!($($tt)+)
+    };
+}
+
+// Munches array elements and map entries one token tree at a time, the
+// same way `serde_json::json!` does, so that `null`, nested literals and
+// arbitrary expressions can be mixed freely.
+#[macro_export]
+#[doc(hidden)]
+macro_rules! msgpack_internal {
+    (@array [$($elems:expr,)*]) => {
+        vec![$($elems,)*]
+    };
+    (@array [$($elems:expr),*]) => {
+        vec![$($elems),*]
+    };
+    (@array [$($elems:expr,)*] null $($rest:tt)*) => {
+        $crate::msgpack_internal!(@array [$($elems,)* $crate::msgpack_internal!(null)] $($rest)*)
+    };
+    (@array [$($elems:expr,)*] true $($rest:tt)*) => {
+        $crate::msgpack_internal!(@array [$($elems,)* $crate::msgpack_internal!(true)] $($rest)*)
+    };
+    (@array [$($elems:expr,)*] false $($rest:tt)*) => {
+        $crate::msgpack_internal!(@array [$($elems,)* $crate::msgpack_internal!(false)] $($rest)*)
+    };
+    (@array [$($elems:expr,)*] [$($array:tt)*] $($rest:tt)*) => {
+        $crate::msgpack_internal!(@array [$($elems,)* $crate::msgpack_internal!([$($array)*])] $($rest)*)
+    };
+    (@array [$($elems:expr,)*] {$($map:tt)*} $($rest:tt)*) => {
+        $crate::msgpack_internal!(@array [$($elems,)* $crate::msgpack_internal!({$($map)*})] $($rest)*)
+    };
+    (@array [$($elems:expr,)*] $next:expr, $($rest:tt)*) => {
+        $crate::msgpack_internal!(@array [$($elems,)* $crate::msgpack_internal!($next),] $($rest)*)
+    };
+    (@array [$($elems:expr,)*] $last:expr) => {
+        $crate::msgpack_internal!(@array [$($elems,)* $crate::msgpack_internal!($last)])
+    };
+    (@array [$($elems:expr),*] , $($rest:tt)*) => {
+        $crate::msgpack_internal!(@array [$($elems,)*] $($rest)*)
+    };
+    (@array [$($elems:expr),*] $unexpected:tt $($rest:tt)*) => {
+        $crate::msgpack_unexpected!($unexpected)
+    };
+
+    // Map entries: (key tokens so far) (remaining tokens) (copy of them,
+    // used to point errors at the offending token).
+    (@map $map:ident () () ()) => {};
+    (@map $map:ident [$($key:tt)+] ($value:expr) , $($rest:tt)*) => {
+        let _ = $map.insert($crate::Value::from($($key)+), $value);
+        $crate::msgpack_internal!(@map $map () ($($rest)*) ($($rest)*));
+    };
+    (@map $map:ident [$($key:tt)+] ($value:expr) $unexpected:tt $($rest:tt)*) => {
+        $crate::msgpack_unexpected!($unexpected);
+    };
+    (@map $map:ident [$($key:tt)+] ($value:expr)) => {
+        let _ = $map.insert($crate::Value::from($($key)+), $value);
+    };
+    (@map $map:ident ($($key:tt)+) (: null $($rest:tt)*) $copy:tt) => {
+        $crate::msgpack_internal!(@map $map [$($key)+] ($crate::msgpack_internal!(null)) $($rest)*);
+    };
+    (@map $map:ident ($($key:tt)+) (: true $($rest:tt)*) $copy:tt) => {
+        $crate::msgpack_internal!(@map $map [$($key)+] ($crate::msgpack_internal!(true)) $($rest)*);
+    };
+    (@map $map:ident ($($key:tt)+) (: false $($rest:tt)*) $copy:tt) => {
+        $crate::msgpack_internal!(@map $map [$($key)+] ($crate::msgpack_internal!(false)) $($rest)*);
+    };
+    (@map $map:ident ($($key:tt)+) (: [$($array:tt)*] $($rest:tt)*) $copy:tt) => {
+        $crate::msgpack_internal!(@map $map [$($key)+] ($crate::msgpack_internal!([$($array)*])) $($rest)*);
+    };
+    (@map $map:ident ($($key:tt)+) (: {$($inner:tt)*} $($rest:tt)*) $copy:tt) => {
+        $crate::msgpack_internal!(@map $map [$($key)+] ($crate::msgpack_internal!({$($inner)*})) $($rest)*);
+    };
+    (@map $map:ident ($($key:tt)+) (: $value:expr , $($rest:tt)*) $copy:tt) => {
+        $crate::msgpack_internal!(@map $map [$($key)+] ($crate::msgpack_internal!($value)) , $($rest)*);
+    };
+    (@map $map:ident ($($key:tt)+) (: $value:expr) $copy:tt) => {
+        $crate::msgpack_internal!(@map $map [$($key)+] ($crate::msgpack_internal!($value)));
+    };
+    (@map $map:ident ($($key:tt)+) (:) $copy:tt) => {
+        $crate::msgpack_internal!();
+    };
+    (@map $map:ident ($($key:tt)+) () $copy:tt) => {
+        $crate::msgpack_internal!();
+    };
+    (@map $map:ident () (: $($rest:tt)*) ($colon:tt $($copy:tt)*)) => {
+        $crate::msgpack_unexpected!($colon);
+    };
+    (@map $map:ident ($($key:tt)*) (, $($rest:tt)*) ($comma:tt $($copy:tt)*)) => {
+        $crate::msgpack_unexpected!($comma);
+    };
+    (@map $map:ident () (($key:expr) : $($rest:tt)*) $copy:tt) => {
+        $crate::msgpack_internal!(@map $map ($key) (: $($rest)*) (: $($rest)*));
+    };
+    (@map $map:ident ($($key:tt)*) ($tt:tt $($rest:tt)*) $copy:tt) => {
+        $crate::msgpack_internal!(@map $map ($($key)* $tt) ($($rest)*) ($($rest)*));
+    };
+
+    (null) => {
+        $crate::Value::Null
+    };
+    (true) => {
+        $crate::Value::Boolean(true)
+    };
+    (false) => {
+        $crate::Value::Boolean(false)
+    };
+    ([]) => {
+        $crate::Value::Array(vec![])
+    };
+    ([ $($tt:tt)+ ]) => {
+        $crate::Value::Array($crate::msgpack_internal!(@array [] $($tt)+))
+    };
+    ({}) => {
+        $crate::Value::Map($crate::Map::default())
+    };
+    ({ $($tt:tt)+ }) => {
+        $crate::Value::Map({
+            let mut map = $crate::Map::default();
+            $crate::msgpack_internal!(@map map () ($($tt)+) ($($tt)+));
+            map
+        })
+    };
+    ($other:expr) => {
+        $crate::Value::from($other)
+    };
+}
+
+#[macro_export]
+#[doc(hidden)]
+macro_rules! msgpack_unexpected {
+    () => {};
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Value;
+
+    #[test]
+    fn literals() {
+        assert_eq!(msgpack!(null), Value::Null);
+        assert_eq!(msgpack!([]), Value::Array(Vec::new()));
+        assert_eq!(msgpack!([1, "a", null, true, [false]]), Value::Array(vec![
+            Value::Integer(1),
+            Value::String("a".into()),
+            Value::Null,
+            Value::Boolean(true),
+            Value::Array(vec![Value::Boolean(false)])
+        ]));
+
+        let v = msgpack!({"key": [1, 2, null], "flag": true, "nested": {"x": -1.5,}, 3: {}});
+        assert_eq!(v["key"][2], Value::Null);
+        assert_eq!(v["flag"], Value::Boolean(true));
+        assert_eq!(v["nested"]["x"], Value::Float(-1.5));
+        assert_eq!(v.pointer("/3"), Some(&Value::Map(Default::default())));
+    }
+
+    #[test]
+    fn splices_expressions() {
+        let name = String::from("kim");
+        let key = "computed";
+        let roles = vec!["admin", "ops"];
+        let v = msgpack!({"name": name, (key): 1 + 2, "roles": roles, "bytes": vec![0u8, 1]});
+        assert_eq!(v["name"], Value::String("kim".into()));
+        assert_eq!(v["computed"], Value::Integer(3));
+        assert_eq!(v["roles"][1], Value::String("ops".into()));
+        assert_eq!(v["bytes"], Value::Bytes(vec![0, 1]));
+        assert_eq!(msgpack!([v["name"].clone(), 2 * 2]), Value::Array(vec![Value::String("kim".into()), Value::Integer(4)]));
+    }
+}
@@ -0,0 +1,85 @@
+use crate::Value;
+
+/// How `Value::merge` combines two arrays found at the same place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MergeStrategy {
+    /// The incoming array replaces the existing one.
+    #[default]
+    Replace,
+    /// Incoming elements are appended.
+    Concat,
+    /// Incoming elements are appended unless an equal element is already
+    /// present.
+    Union
+}
+
+impl Value {
+    /// Merges `other` into this value. Maps are merged key by key,
+    /// recursively; arrays are combined according to `strategy`; in every
+    /// other case `other` replaces this value.
+    pub fn merge(&mut self, other: Value, strategy: MergeStrategy) {
+        match (self, other) {
+            (Value::Map(m), Value::Map(other)) => {
+                for (k, v) in other {
+                    match m.get_mut(&k) {
+                        Some(existing) => existing.merge(v, strategy),
+                        None => {
+                            m.insert(k, v);
+                        }
+                    }
+                }
+            },
+            (Value::Array(a), Value::Array(other)) => match strategy {
+                MergeStrategy::Replace => *a = other,
+                MergeStrategy::Concat => a.extend(other),
+                MergeStrategy::Union => {
+                    for v in other {
+                        if !a.contains(&v) {
+                            a.push(v);
+                        }
+                    }
+                }
+            },
+            (target, other) => *target = other
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base() -> Value {
+        msgpack!({"name": "svc", "ports": [80, 443], "log": {"level": "info", "file": "a.log"}})
+    }
+
+    #[test]
+    fn merges_maps_recursively() {
+        let mut v = base();
+        v.merge(msgpack!({"log": {"level": "debug"}, "replicas": 3, "name": null}), MergeStrategy::Replace);
+        assert_eq!(v, msgpack!({
+            "name": null,
+            "ports": [80, 443],
+            "log": {"level": "debug", "file": "a.log"},
+            "replicas": 3
+        }));
+
+        v.merge(msgpack!({"log": "off"}), MergeStrategy::Replace);
+        assert_eq!(v["log"], Value::from("off"));
+    }
+
+    #[test]
+    fn array_strategies() {
+        let overlay = msgpack!({"ports": [443, 8080]});
+        let cases = [
+            (MergeStrategy::Replace, msgpack!([443, 8080])),
+            (MergeStrategy::Concat, msgpack!([80, 443, 443, 8080])),
+            (MergeStrategy::Union, msgpack!([80, 443, 8080]))
+        ];
+        for (strategy, ports) in cases.iter() {
+            let mut v = base();
+            v.merge(overlay.clone(), *strategy);
+            assert_eq!(&v["ports"], ports);
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use std::fs::File;
+use std::path::Path;
+
+use memmap2::Mmap;
+
+use crate::{decode_from_slice, ArrayIndex, Document, Result, Value};
+
+/// A file mapped into memory, so its contents can be decoded or browsed
+/// lazily through `Document` and `ArrayIndex` without first reading it onto
+/// the heap. Pages are loaded by the OS as they are touched.
+///
+/// The file must not be truncated or modified while it is mapped; doing so
+/// can crash the process or change bytes underneath borrowed strings.
+#[derive(Debug)]
+pub struct MappedFile {
+    map: Mmap
+}
+
+impl MappedFile {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<MappedFile> {
+        let file = File::open(path)?;
+        // SAFETY: the caller keeps the file unchanged while it is mapped,
+        // as the type's documentation requires.
+        let map = unsafe { Mmap::map(&file)? };
+        Ok(MappedFile { map })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.map
+    }
+
+    /// A lazy view of the first value in the file.
+    pub fn document(&self) -> Document<'_> {
+        Document::new(&self.map)
+    }
+
+    /// Indexes the array at the start of the file, see `ArrayIndex::build`.
+    pub fn array_index(&self) -> Result<ArrayIndex<'_>> {
+        ArrayIndex::build(&self.map)
+    }
+
+    /// Decodes the first value in the file.
+    pub fn decode(&self) -> Result<Value> {
+        decode_from_slice(&self.map).map(|(v, _)| v)
+    }
+}
+
+/// Maps the file at `path` and decodes the value at its start, sparing the
+/// copy of the whole file that reading it into a buffer would take.
+pub fn decode_from_path<P: AsRef<Path>>(path: P) -> Result<Value> {
+    MappedFile::open(path)?.decode()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{enc, ok};
+    use crate::Error;
+
+    fn temp_file(name: &str, contents: &[u8]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("stuff-{}-{}", std::process::id(), name));
+        ok(std::fs::write(&path, contents));
+        path
+    }
+
+    #[test]
+    fn decodes_and_browses_files() {
+        let v = msgpack!([{"id": 1, "name": "a"}, {"id": 2, "name": "b"}]);
+        let path = temp_file("browse", &enc(&v));
+        assert_eq!(decode_from_path(&path).ok(), Some(v.clone()));
+        let file = ok(MappedFile::open(&path));
+        assert_eq!(file.document().index(1).and_then(|d| d.get("name")).and_then(|d| d.as_str()), Some("b"));
+        assert_eq!(file.array_index().map(|i| i.len()).ok(), Some(2));
+        assert_eq!(file.as_bytes().len(), enc(&v).len());
+        drop(file);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn reports_missing_and_bad_files() {
+        let missing = std::env::temp_dir().join("stuff-no-such-file");
+        assert!(matches!(decode_from_path(&missing), Err(Error::Io(_))));
+        let path = temp_file("empty", &[]);
+        assert!(decode_from_path(&path).is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,201 @@
+use std::io::Write;
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+
+use rayon::prelude::*;
+
+use crate::validate::{read_header, Header};
+use crate::{
+    check_depth, decode_partial, decode_value, encode_value_in, encode_with, write_array_header, ArrayIndex, Buffered,
+    DecodeOptions, EncodeOptions, Reader, Result, Value
+};
+
+/// Top-level arrays shorter than this are encoded and decoded on the
+/// calling thread.
+const PARALLEL_MIN_LEN: usize = 4096;
+
+/// Elements each task encodes into a buffer of its own.
+const CHUNK_LEN: usize = 1024;
+
+/// Chunks encoded before their buffers are written out, bounding how much
+/// encoded output is held at once.
+const BATCH_CHUNKS: usize = 256;
+
+/// Like `encode_with`, but encodes the elements of a large top-level array
+/// on rayon's thread pool, in chunks that are written out in order. The
+/// output is byte for byte what `encode_with` produces. Other values, and
+/// short arrays, are encoded on the calling thread.
+pub fn par_encode_with<W: Write + ?Sized>(w: &mut W, value: &Value, opts: &EncodeOptions) -> Result<()> {
+    let items = match value {
+        Value::Array(items) if items.len() >= PARALLEL_MIN_LEN => items,
+        _ => return encode_with(w, value, opts)
+    };
+    check_depth(opts, 0)?;
+    // Elements sit one level inside the array.
+    let inner = EncodeOptions { max_depth: opts.max_depth - 1, ..opts.clone() };
+    write_array_header(w, items.len())?;
+    for batch in items.chunks(CHUNK_LEN * BATCH_CHUNKS) {
+        let chunks = batch.par_chunks(CHUNK_LEN).map(|chunk| {
+            let mut buf = Vec::new();
+            let mut stack = Vec::new();
+            for v in chunk {
+                encode_value_in(&mut buf, v, &inner, &mut stack)?;
+            }
+            Ok(buf)
+        }).collect::<Result<Vec<Vec<u8>>>>()?;
+        for chunk in chunks {
+            w.write_all(&chunk)?;
+        }
+    }
+    Ok(())
+}
+
+/// Encodes `value` to a new buffer with `par_encode_with`.
+pub fn par_encode_to_vec(value: &Value) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    par_encode_with(&mut buf, value, &EncodeOptions::default())?;
+    Ok(buf)
+}
+
+/// Like `decode_partial`, but a large top-level array is first indexed
+/// with `ArrayIndex` and its elements are then decoded on rayon's thread
+/// pool. Returns the value with the number of bytes it occupied. The
+/// result, errors included, is what a sequential decode gives; `max_alloc`
+/// limits what all the elements allocate together.
+pub fn par_decode_with(buf: &[u8], opts: &DecodeOptions) -> Result<(Value, usize)> {
+    let sequential = || decode_partial(buf, opts).map_err(|p| p.error);
+    let parallel = matches!(read_header(buf), Ok((Header::Array(len), _)) if len >= PARALLEL_MIN_LEN && len <= opts.max_container_len)
+        && opts.max_depth > 0
+        && opts.raw_depth != Some(0);
+    if !parallel {
+        return sequential();
+    }
+    // Malformed input is left to the sequential decoder to report.
+    let index = match ArrayIndex::build(buf) {
+        Ok(index) => index,
+        Err(_) => return sequential()
+    };
+    let slots = index.len().saturating_mul(std::mem::size_of::<Value>());
+    if slots > opts.max_alloc {
+        return sequential();
+    }
+    // Elements sit one level inside the array.
+    let inner = DecodeOptions {
+        max_depth: opts.max_depth - 1,
+        raw_depth: opts.raw_depth.map(|d| d - 1),
+        ..opts.clone()
+    };
+    // The elements draw on one budget, which starts with the array's own
+    // slots charged. Without a limit there is nothing to share.
+    let shared = if opts.max_alloc == usize::MAX { None } else { Some(Arc::new(AtomicUsize::new(slots))) };
+    let items = (0..index.len()).into_par_iter().map(|i| {
+        let range = index.range(i).unwrap_or_default();
+        let mut r = &buf[range];
+        let mut reader = Reader::new(Buffered(&mut r));
+        reader.shared_alloc = shared.clone();
+        decode_value(&mut reader, &inner)
+    }).collect::<Result<Vec<Value>>>();
+    match items {
+        Ok(items) => Ok((Value::Array(items), index.encoded_len())),
+        // Whichever element failed first in time need not be the first in
+        // the array, so the sequential decoder finds and reports that one.
+        Err(_) => sequential()
+    }
+}
+
+/// Decodes the value at the front of `buf` with `par_decode_with`.
+pub fn par_decode_from_slice(buf: &[u8]) -> Result<(Value, usize)> {
+    par_decode_with(buf, &DecodeOptions::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{enc, ok};
+    use crate::{decode_from_slice, Error};
+
+    #[test]
+    fn matches_sequential_encoding() {
+        let items: Vec<Value> = (0..50_000).map(|i| msgpack!([i, format!("row{}", i), {"f": (i as f64) / 3.0}])).collect();
+        let v = Value::Array(items);
+        let parallel = ok(par_encode_to_vec(&v));
+        assert!(!parallel.is_empty());
+        assert_eq!(parallel, enc(&v));
+        assert_eq!(par_encode_to_vec(&msgpack!([1, 2])).ok(), Some(vec![0x92, 0x01, 0x02]));
+    }
+
+    #[test]
+    fn reports_element_errors() {
+        let mut items = vec![Value::Null; PARALLEL_MIN_LEN * 2];
+        items[PARALLEL_MIN_LEN + 7] = msgpack!([[1]]);
+        let v = Value::Array(items);
+        let opts = EncodeOptions { max_depth: 2, ..EncodeOptions::default() };
+        let mut buf = Vec::new();
+        assert!(matches!(par_encode_with(&mut buf, &v, &opts), Err(Error::DepthLimitExceeded)));
+        let opts = EncodeOptions { max_depth: 3, ..EncodeOptions::default() };
+        let mut buf = Vec::new();
+        assert!(par_encode_with(&mut buf, &v, &opts).is_ok());
+        let opts = EncodeOptions { max_depth: 0, ..EncodeOptions::default() };
+        assert!(matches!(par_encode_with(&mut Vec::new(), &v, &opts), Err(Error::DepthLimitExceeded)));
+    }
+
+    #[test]
+    fn decodes_like_the_sequential_decoder() {
+        let items: Vec<Value> = (0..20_000).map(|i| msgpack!({"id": i, "name": format!("row{}", i)})).collect();
+        let v = Value::Array(items);
+        let mut buf = enc(&v);
+        let len = buf.len();
+        buf.push(0xc0);
+        assert_eq!(par_decode_from_slice(&buf).ok(), Some((v, len)));
+        assert_eq!(ok(par_decode_from_slice(&[0x91, 0x01])), ok(decode_from_slice(&[0x91, 0x01])));
+    }
+
+    #[test]
+    fn reports_errors_with_their_place() {
+        let items = vec![msgpack!("ok"); PARALLEL_MIN_LEN];
+        let mut buf = enc(&Value::Array(items));
+        // Corrupt the last element's string with invalid UTF-8.
+        let last = buf.len() - 1;
+        buf[last] = 0xff;
+        match par_decode_from_slice(&buf) {
+            Err(Error::Decode { offset, path, error }) => {
+                assert_eq!(offset as usize, buf.len());
+                assert_eq!(path, format!("$[{}]", PARALLEL_MIN_LEN - 1));
+                assert!(matches!(*error, Error::InvalidUtf8(_)));
+            },
+            other => panic!("unexpected {:?}", other.map(|(v, _)| v.to_string()))
+        }
+        let sequential = decode_from_slice(&buf).map_err(|e| e.to_string());
+        assert_eq!(par_decode_from_slice(&buf).map_err(|e| e.to_string()), sequential);
+        // Truncation is found while indexing and reported the same way too.
+        let truncated = &buf[..buf.len() - 1];
+        let sequential = decode_from_slice(truncated).map_err(|e| e.to_string());
+        assert_eq!(par_decode_from_slice(truncated).map_err(|e| e.to_string()), sequential);
+
+        // With several bad elements, the first one is reported.
+        let mut buf = enc(&Value::Array(vec![msgpack!("ok"); PARALLEL_MIN_LEN * 4]));
+        for i in [PARALLEL_MIN_LEN * 4 - 1, PARALLEL_MIN_LEN, PARALLEL_MIN_LEN * 2] {
+            buf[3 + i * 3 + 2] = 0xff;
+        }
+        let sequential = decode_from_slice(&buf).map_err(|e| e.to_string());
+        assert!(sequential.as_ref().err().map(|e| e.contains(&format!("$[{}]", PARALLEL_MIN_LEN))).unwrap_or_default());
+        for _ in 0..10 {
+            assert_eq!(par_decode_from_slice(&buf).map_err(|e| e.to_string()), sequential);
+        }
+    }
+
+    #[test]
+    fn shares_max_alloc_between_elements() {
+        let name = "x".repeat(100);
+        let buf = enc(&Value::Array(vec![msgpack!(name.as_str()); PARALLEL_MIN_LEN]));
+        let slots = PARALLEL_MIN_LEN * std::mem::size_of::<Value>();
+        // Room for any one element, but not for all of them.
+        let opts = DecodeOptions { max_alloc: slots + 100 * 10, ..DecodeOptions::default() };
+        let sequential = decode_partial(&buf, &opts).map_err(|p| p.error.to_string());
+        assert!(sequential.as_ref().err().map(|e| e.contains("allocate")).unwrap_or_default());
+        assert_eq!(par_decode_with(&buf, &opts).map_err(|e| e.to_string()), sequential);
+
+        let opts = DecodeOptions { max_alloc: slots + 200 * PARALLEL_MIN_LEN, ..DecodeOptions::default() };
+        assert_eq!(ok(par_decode_with(&buf, &opts)), ok(decode_partial(&buf, &opts).map_err(|p| p.error)));
+    }
+}
@@ -0,0 +1,237 @@
+use crate::pointer::escape;
+use crate::{Error, Result, Value};
+
+/// One edit of a `Patch`. Paths are JSON Pointers as accepted by
+/// `Value::pointer`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatchOp {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: Value }
+}
+
+/// An ordered list of edits taking one document to another, in the manner
+/// of RFC 6902 JSON Patch.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Patch {
+    pub ops: Vec<PatchOp>
+}
+
+impl Patch {
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
+
+/// Reference token for a map key. Only string keys get one: an integer
+/// key's token would be read back as a string key when it is added.
+fn key_token(key: &Value) -> Option<String> {
+    match key {
+        Value::String(s) => Some(escape(s)),
+        Value::SharedString(s) => Some(escape(s)),
+        Value::InlineString(s) => Some(escape(s)),
+        _ => None
+    }
+}
+
+fn diff_into(ops: &mut Vec<PatchOp>, path: &str, a: &Value, b: &Value) {
+    if a == b {
+        return;
+    }
+    match (a, b) {
+        (Value::Map(m), Value::Map(m2)) if m.keys().chain(m2.keys()).all(|k| key_token(k).is_some()) => {
+            let mut keys: Vec<&Value> = m.keys().chain(m2.keys().filter(|k| !m.contains_key(*k))).collect();
+            keys.sort();
+            for key in keys {
+                let child = format!("{}/{}", path, key_token(key).unwrap_or_default());
+                match (m.get(key), m2.get(key)) {
+                    (Some(v), Some(v2)) => diff_into(ops, &child, v, v2),
+                    (Some(_), None) => ops.push(PatchOp::Remove { path: child }),
+                    (None, Some(v2)) => ops.push(PatchOp::Add { path: child, value: v2.clone() }),
+                    (None, None) => {}
+                }
+            }
+        },
+        (Value::Array(a), Value::Array(a2)) => {
+            let common = a.len().min(a2.len());
+            for i in 0..common {
+                diff_into(ops, &format!("{}/{}", path, i), &a[i], &a2[i]);
+            }
+            for (i, v) in a2.iter().enumerate().skip(common) {
+                ops.push(PatchOp::Add { path: format!("{}/{}", path, i), value: v.clone() });
+            }
+            for i in (common..a.len()).rev() {
+                ops.push(PatchOp::Remove { path: format!("{}/{}", path, i) });
+            }
+        },
+        _ => ops.push(PatchOp::Replace { path: path.to_string(), value: b.clone() })
+    }
+}
+
+/// Computes the edits that turn `a` into `b`. Maps and arrays are compared
+/// member by member; a map with any key that isn't a string is replaced
+/// as a whole.
+pub fn diff(a: &Value, b: &Value) -> Patch {
+    let mut ops = Vec::new();
+    diff_into(&mut ops, "", a, b);
+    Patch { ops }
+}
+
+/// Adds `value` at `path`, whose parent must already be a map or array.
+fn add(doc: &mut Value, path: &str, value: Value) -> std::result::Result<(), String> {
+    if let Some(slash) = path.rfind('/') {
+        match doc.pointer(&path[..slash]) {
+            Some(Value::Map(_)) | Some(Value::Array(_)) => {},
+            Some(_) => return Err(format!("parent of {} is not a container", path)),
+            None => return Err(format!("parent of {} does not exist", path))
+        }
+    }
+    doc.insert_at(path, value).map_err(|e| e.to_string())
+}
+
+fn apply_op(doc: &mut Value, op: &PatchOp) -> std::result::Result<(), String> {
+    let missing = |path: &str| format!("no value at {}", path);
+    match op {
+        PatchOp::Add { path, value } => add(doc, path, value.clone()),
+        PatchOp::Remove { path } => {
+            doc.remove_at(path).map(drop).ok_or_else(|| missing(path))
+        },
+        PatchOp::Replace { path, value } => {
+            let target = doc.pointer_mut(path).ok_or_else(|| missing(path))?;
+            *target = value.clone();
+            Ok(())
+        },
+        PatchOp::Move { from, path } => {
+            if path.starts_with(from.as_str()) && path[from.len()..].starts_with('/') {
+                return Err(format!("cannot move {} into itself", from));
+            }
+            if from == path {
+                return doc.pointer(from).map(drop).ok_or_else(|| missing(from));
+            }
+            let value = doc.remove_at(from).ok_or_else(|| missing(from))?;
+            add(doc, path, value)
+        },
+        PatchOp::Copy { from, path } => {
+            let value = doc.pointer(from).ok_or_else(|| missing(from))?.clone();
+            add(doc, path, value)
+        },
+        PatchOp::Test { path, value } => match doc.pointer(path) {
+            Some(v) if v == value => Ok(()),
+            Some(v) => Err(format!("test at {} expected {}, found {}", path, value, v)),
+            None => Err(missing(path))
+        }
+    }
+}
+
+impl Value {
+    /// Applies every operation of `patch` in order. Either all of them
+    /// succeed or the value is left unchanged and the failing operation is
+    /// reported as `Error::PatchFailed`.
+    ///
+    /// As in RFC 6902, `add` into an array shifts later elements up and
+    /// accepts `-` for the end, while `replace` and `remove` need an
+    /// existing target. `add` and `move` need the target's parent to exist.
+    pub fn apply_patch(&mut self, patch: &Patch) -> Result<()> {
+        let mut doc = self.clone();
+        for (index, op) in patch.ops.iter().enumerate() {
+            apply_op(&mut doc, op).map_err(|reason| Error::PatchFailed { index, reason })?;
+        }
+        *self = doc;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add(path: &str, value: Value) -> PatchOp {
+        PatchOp::Add { path: path.into(), value }
+    }
+
+    fn remove(path: &str) -> PatchOp {
+        PatchOp::Remove { path: path.into() }
+    }
+
+    fn replace(path: &str, value: Value) -> PatchOp {
+        PatchOp::Replace { path: path.into(), value }
+    }
+
+    #[test]
+    fn diffs_nested_documents() {
+        let a = msgpack!({"name": "a", "tags": [1, 2, 3], "meta": {"x": 1, "a/b": 2}, "gone": true});
+        let b = msgpack!({"name": "b", "tags": [1, 5], "meta": {"x": 1, "a/b": 3, "y": null}, "new": 4});
+        assert_eq!(diff(&a, &b).ops, vec![
+            remove("/gone"),
+            replace("/meta/a~1b", Value::Integer(3)),
+            add("/meta/y", Value::Null),
+            replace("/name", Value::from("b")),
+            add("/new", Value::Integer(4)),
+            replace("/tags/1", Value::Integer(5)),
+            remove("/tags/2")
+        ]);
+        assert!(diff(&a, &a.clone()).is_empty());
+    }
+
+    #[test]
+    fn replaces_what_it_cannot_address() {
+        assert_eq!(diff(&msgpack!(1), &msgpack!("1")).ops, vec![replace("", Value::from("1"))]);
+        let a = msgpack!({"k": {true: null}});
+        let b = msgpack!({"k": {}});
+        assert_eq!(diff(&a, &b).ops, vec![replace("/k", msgpack!({}))]);
+        assert_eq!(diff(&msgpack!({}), &msgpack!({4: 1})).ops, vec![replace("", msgpack!({4: 1}))]);
+        assert_eq!(diff(&msgpack!([1]), &msgpack!([1, [2], 3])).ops, vec![
+            add("/1", msgpack!([2])),
+            add("/2", Value::Integer(3))
+        ]);
+    }
+
+    #[test]
+    fn diff_then_apply_round_trips() {
+        let a = msgpack!({"name": "a", "tags": [1, 2, 3], "meta": {"x": 1, "a/b": 2}, "gone": true});
+        let b = msgpack!({"name": "b", "tags": [1, 5], "meta": {"x": [1], "a/b": 3, "y": null}, "new": 4});
+        let mut doc = a.clone();
+        assert!(doc.apply_patch(&diff(&a, &b)).is_ok());
+        assert_eq!(doc, b);
+    }
+
+    #[test]
+    fn applies_each_operation() {
+        let mut doc = msgpack!({"a": [1, 2], "b": {"c": "x"}});
+        let patch = Patch {
+            ops: vec![
+                PatchOp::Test { path: "/b/c".into(), value: Value::from("x") },
+                add("/a/-", Value::Integer(3)),
+                add("/a/0", Value::Integer(0)),
+                PatchOp::Move { from: "/b/c".into(), path: "/d".into() },
+                PatchOp::Copy { from: "/a".into(), path: "/b/a".into() },
+                replace("/a/1", Value::Null),
+                remove("/a/3")
+            ]
+        };
+        assert!(doc.apply_patch(&patch).is_ok());
+        assert_eq!(doc, msgpack!({"a": [0, null, 2], "b": {"a": [0, 1, 2, 3]}, "d": "x"}));
+    }
+
+    #[test]
+    fn failures_leave_document_unchanged() {
+        let original = msgpack!({"a": [1], "b": 2});
+        let failing = [
+            PatchOp::Test { path: "/b".into(), value: Value::Integer(3) },
+            remove("/missing"),
+            replace("/a/1", Value::Null),
+            add("/x/y", Value::Null),
+            add("/b/y", Value::Null),
+            PatchOp::Move { from: "/a".into(), path: "/a/0".into() }
+        ];
+        for op in failing.iter() {
+            let mut doc = original.clone();
+            let patch = Patch { ops: vec![add("/c", Value::Null), op.clone()] };
+            assert!(matches!(doc.apply_patch(&patch), Err(Error::PatchFailed { index: 1, .. })));
+            assert_eq!(doc, original);
+        }
+    }
+}
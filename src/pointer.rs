@@ -0,0 +1,320 @@
+use crate::{map_remove, Error, Map, Result, Value};
+
+/// Splits an RFC 6901 pointer into unescaped reference tokens, or `None`
+/// if it is neither empty nor starts with `/`.
+pub(crate) fn tokens(pointer: &str) -> Option<Vec<String>> {
+    if pointer.is_empty() {
+        return Some(Vec::new());
+    }
+    if !pointer.starts_with('/') {
+        return None;
+    }
+    Some(pointer[1..].split('/').map(|t| t.replace("~1", "/").replace("~0", "~")).collect())
+}
+
+/// Escapes a key for use as a reference token, the inverse of `tokens`.
+pub(crate) fn escape(token: &str) -> String {
+    token.replace('~', "~0").replace('/', "~1")
+}
+
+/// Array positions are plain decimal numbers without leading zeros.
+pub(crate) fn array_index(token: &str) -> Option<usize> {
+    if token.is_empty() || (token.len() > 1 && token.starts_with('0')) || !token.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    token.parse().ok()
+}
+
+/// Map keys are matched as strings first, then as integers so that
+/// integer-keyed maps can be addressed too.
+pub(crate) fn map_key(m: &Map, token: &str) -> Value {
+    let key = Value::String(token.to_string());
+    if !m.contains_key(&key) {
+        if let Ok(i) = token.parse::<i128>() {
+            if m.contains_key(&Value::Integer(i)) {
+                return Value::Integer(i);
+            }
+        }
+    }
+    key
+}
+
+impl Value {
+    /// Looks up a value by JSON Pointer, e.g. `/data/items/3/id`, with `~1`
+    /// standing for `/` and `~0` for `~` inside keys. The empty pointer
+    /// refers to the whole value.
+    pub fn pointer(&self, pointer: &str) -> Option<&Value> {
+        let mut target = self;
+        for token in tokens(pointer)? {
+            target = match target {
+                Value::Array(a) => a.get(array_index(&token)?)?,
+                Value::Map(m) => m.get(&map_key(m, &token))?,
+                _ => return None
+            };
+        }
+        Some(target)
+    }
+
+    pub fn pointer_mut(&mut self, pointer: &str) -> Option<&mut Value> {
+        let mut target = self;
+        for token in tokens(pointer)? {
+            target = match target {
+                Value::Array(a) => a.get_mut(array_index(&token)?)?,
+                Value::Map(m) => {
+                    let key = map_key(m, &token);
+                    m.get_mut(&key)?
+                },
+                _ => return None
+            };
+        }
+        Some(target)
+    }
+}
+
+pub(crate) fn path_error(pointer: &str, reason: &str) -> Error {
+    Error::InvalidPath(format!("{}: {}", pointer, reason))
+}
+
+/// A missing member becomes an array when the token addressing into it
+/// is `-` or a position, and a map otherwise.
+fn container_for(token: &str) -> Value {
+    if token == "-" || array_index(token).is_some() {
+        Value::Array(Vec::new())
+    } else {
+        Value::Map(Map::default())
+    }
+}
+
+/// Checks every token of a `set_at` or `insert_at` path against `target`
+/// before anything is created, so an edit that fails leaves it unchanged.
+fn check_path(target: &Value, pointer: &str, tokens: &[String]) -> Result<()> {
+    // `None` once the walk reaches a member that doesn't exist yet.
+    let mut target = Some(target);
+    for token in tokens {
+        target = match target {
+            None | Some(Value::Null) => match container_for(token) {
+                Value::Array(_) if token != "-" && token != "0" => {
+                    return Err(path_error(pointer, "array position out of range"));
+                },
+                _ => None
+            },
+            Some(Value::Array(a)) => {
+                let idx = if token == "-" { a.len() } else {
+                    array_index(token).ok_or_else(|| path_error(pointer, "bad array position"))?
+                };
+                if idx > a.len() {
+                    return Err(path_error(pointer, "array position out of range"));
+                }
+                a.get(idx)
+            },
+            Some(Value::Map(m)) => m.get(&map_key(m, token)),
+            Some(_) => return Err(path_error(pointer, "not a container"))
+        };
+    }
+    Ok(())
+}
+
+/// Walks to the parent of the last token, creating containers in place of
+/// missing or null members along the way.
+fn walk_create<'a>(mut target: &'a mut Value, pointer: &str, tokens: &[String]) -> Result<&'a mut Value> {
+    let (last, parents) = match tokens.split_last() {
+        Some(split) => split,
+        None => return Ok(target)
+    };
+    for token in parents {
+        if let Value::Null = target {
+            *target = container_for(token);
+        }
+        target = match target {
+            Value::Array(a) => {
+                let idx = if token == "-" { a.len() } else {
+                    array_index(token).ok_or_else(|| path_error(pointer, "bad array position"))?
+                };
+                if idx == a.len() {
+                    a.push(Value::Null);
+                }
+                a.get_mut(idx).ok_or_else(|| path_error(pointer, "array position out of range"))?
+            },
+            Value::Map(m) => {
+                let key = map_key(m, token);
+                m.entry(key).or_insert(Value::Null)
+            },
+            _ => return Err(path_error(pointer, "not a container"))
+        };
+    }
+    if let Value::Null = target {
+        *target = container_for(last);
+    }
+    Ok(target)
+}
+
+impl Value {
+    /// Replaces the value at `pointer`, creating intermediate containers as
+    /// needed, and returns the previous value if there was one. The token
+    /// `-` appends to an array.
+    pub fn set_at(&mut self, pointer: &str, value: Value) -> Result<Option<Value>> {
+        let tokens = tokens(pointer).ok_or_else(|| path_error(pointer, "must start with /"))?;
+        let last = match tokens.last() {
+            Some(last) => last,
+            None => return Ok(Some(std::mem::replace(self, value)))
+        };
+        check_path(self, pointer, &tokens)?;
+        match walk_create(self, pointer, &tokens)? {
+            Value::Array(a) => {
+                let idx = if last == "-" { a.len() } else {
+                    array_index(last).ok_or_else(|| path_error(pointer, "bad array position"))?
+                };
+                if idx == a.len() {
+                    a.push(value);
+                    Ok(None)
+                } else {
+                    let slot = a.get_mut(idx).ok_or_else(|| path_error(pointer, "array position out of range"))?;
+                    Ok(Some(std::mem::replace(slot, value)))
+                }
+            },
+            Value::Map(m) => {
+                let key = map_key(m, last);
+                Ok(m.insert(key, value))
+            },
+            _ => Err(path_error(pointer, "not a container"))
+        }
+    }
+
+    /// Inserts at `pointer`, creating intermediate containers as needed.
+    /// Unlike `set_at`, inserting into an array shifts later elements up.
+    pub fn insert_at(&mut self, pointer: &str, value: Value) -> Result<()> {
+        let tokens = tokens(pointer).ok_or_else(|| path_error(pointer, "must start with /"))?;
+        let last = match tokens.last() {
+            Some(last) => last,
+            None => {
+                *self = value;
+                return Ok(());
+            }
+        };
+        check_path(self, pointer, &tokens)?;
+        match walk_create(self, pointer, &tokens)? {
+            Value::Array(a) => {
+                let idx = if last == "-" { a.len() } else {
+                    array_index(last).ok_or_else(|| path_error(pointer, "bad array position"))?
+                };
+                if idx > a.len() {
+                    return Err(path_error(pointer, "array position out of range"));
+                }
+                a.insert(idx, value);
+                Ok(())
+            },
+            Value::Map(m) => {
+                let key = map_key(m, last);
+                m.insert(key, value);
+                Ok(())
+            },
+            _ => Err(path_error(pointer, "not a container"))
+        }
+    }
+
+    /// Removes and returns the value at `pointer`, shifting later array
+    /// elements down. The whole document can't be removed.
+    pub fn remove_at(&mut self, pointer: &str) -> Option<Value> {
+        let mut tokens = tokens(pointer)?;
+        let last = tokens.pop()?;
+        let mut parent = String::new();
+        for token in &tokens {
+            parent.push('/');
+            parent.push_str(&escape(token));
+        }
+        match self.pointer_mut(&parent)? {
+            Value::Array(a) => {
+                let idx = array_index(&last)?;
+                if idx < a.len() {
+                    Some(a.remove(idx))
+                } else {
+                    None
+                }
+            },
+            Value::Map(m) => {
+                let key = map_key(m, &last);
+                map_remove(m, &key)
+            },
+            _ => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Value;
+
+    fn doc() -> Value {
+        let items = Value::Array((0..5).map(|i| {
+            Value::Map(vec![(Value::String("id".into()), Value::Integer(i * 10))].into_iter().collect())
+        }).collect());
+        Value::Map(vec![
+            (Value::String("data".into()), Value::Map(vec![(Value::String("items".into()), items)].into_iter().collect())),
+            (Value::String("a/b".into()), Value::Integer(1)),
+            (Value::String("m~n".into()), Value::Integer(2)),
+            (Value::Integer(7), Value::Boolean(true))
+        ].into_iter().collect())
+    }
+
+    #[test]
+    fn pointer_lookup() {
+        let doc = doc();
+        assert_eq!(doc.pointer("/data/items/3/id"), Some(&Value::Integer(30)));
+        assert_eq!(doc.pointer(""), Some(&doc));
+        assert_eq!(doc.pointer("/a~1b"), Some(&Value::Integer(1)));
+        assert_eq!(doc.pointer("/m~0n"), Some(&Value::Integer(2)));
+        assert_eq!(doc.pointer("/7"), Some(&Value::Boolean(true)));
+        assert_eq!(doc.pointer("/data/items/03/id"), None);
+        assert_eq!(doc.pointer("/data/items/9"), None);
+        assert_eq!(doc.pointer("data"), None);
+    }
+
+    #[test]
+    fn set_at_creates_intermediates() {
+        let mut v = Value::Null;
+        assert!(matches!(v.set_at("/server/ports/-", Value::Integer(80)), Ok(None)));
+        assert!(matches!(v.set_at("/server/ports/1", Value::Integer(443)), Ok(None)));
+        assert!(matches!(v.set_at("/server/ports/0", Value::Integer(8080)), Ok(Some(Value::Integer(80)))));
+        assert!(matches!(v.set_at("/server/name", Value::String("x".into())), Ok(None)));
+        assert_eq!(v, msgpack!({"server": {"ports": [8080, 443], "name": "x"}}));
+        assert_eq!(v.pointer("/server/ports/1"), Some(&Value::Integer(443)));
+
+        // Failed edits leave the value as it was.
+        let before = v.clone();
+        assert!(v.set_at("/server/ports/5", Value::Null).is_err());
+        assert!(v.set_at("/server/name/first", Value::Null).is_err());
+        assert!(v.set_at("server", Value::Null).is_err());
+        assert!(v.set_at("/b/3/c", Value::Integer(2)).is_err());
+        assert!(v.set_at("/server/ports/-/3", Value::Null).is_err());
+        assert!(v.insert_at("/x/y/5", Value::Null).is_err());
+        assert!(v.insert_at("/server/ports/3", Value::Null).is_err());
+        assert_eq!(v, before);
+    }
+
+    #[test]
+    fn insert_and_remove_at() {
+        let mut doc = doc();
+        assert!(doc.insert_at("/data/items/1", Value::Integer(-1)).is_ok());
+        assert_eq!(doc.pointer("/data/items/1"), Some(&Value::Integer(-1)));
+        assert_eq!(doc.pointer("/data/items/2/id"), Some(&Value::Integer(10)));
+        let items = msgpack!([{"id": 0}, -1, {"id": 10}, {"id": 20}, {"id": 30}, {"id": 40}]);
+        assert_eq!(doc.pointer("/data/items"), Some(&items));
+        assert!(doc.insert_at("/data/items/9", Value::Null).is_err());
+
+        assert_eq!(doc.remove_at("/data/items/1"), Some(Value::Integer(-1)));
+        assert_eq!(doc.pointer("/data/items/1/id"), Some(&Value::Integer(10)));
+        assert_eq!(doc.remove_at("/a~1b"), Some(Value::Integer(1)));
+        assert_eq!(doc.remove_at("/a~1b"), None);
+        assert_eq!(doc.remove_at("/7"), Some(Value::Boolean(true)));
+        assert_eq!(doc.remove_at(""), None);
+    }
+
+    #[test]
+    fn pointer_mut_updates() {
+        let mut doc = doc();
+        if let Some(v) = doc.pointer_mut("/data/items/0/id") {
+            *v = Value::String("first".into());
+        }
+        assert_eq!(doc.pointer("/data/items/0/id"), Some(&Value::String("first".into())));
+    }
+}
@@ -0,0 +1,210 @@
+use crate::validate::{read_header, Header};
+use crate::{DecodeOptions, Decoder, Error, Expected, Result, Value};
+
+/// Decodes back-to-back values from bytes handed over as they arrive, in
+/// chunks of any size, without owning a reader: for event loops, hosts
+/// without blocking IO and custom transports. Input is scanned once as it
+/// comes in, and each value is decoded as soon as its last byte is fed.
+///
+/// Bytes of an unfinished value are buffered until the rest arrives, so
+/// set `max_payload_len` and `max_container_len` when the peer isn't
+/// trusted: headers exceeding them fail as soon as they are seen. A value
+/// that fails to decode is not skipped, and every later call reports the
+/// same error until `reset`.
+#[derive(Debug, Default)]
+pub struct PushParser {
+    buf: Vec<u8>,
+    /// Bytes at the front of `buf` already decoded.
+    start: usize,
+    /// Bytes of the value after `start` known to be complete items.
+    scanned: usize,
+    /// Items the value after `start` still needs, once it has begun.
+    pending: usize,
+    /// Stream offset of `buf[start]`.
+    offset: u64,
+    decoder: Decoder
+}
+
+impl PushParser {
+    pub fn new() -> PushParser {
+        PushParser::default()
+    }
+
+    pub fn with_options(opts: DecodeOptions) -> PushParser {
+        PushParser { decoder: Decoder::with_options(opts), ..PushParser::default() }
+    }
+
+    /// Buffers `chunk` and returns every value it completes, which is none
+    /// while more input is needed. If a value fails, those completed
+    /// before it are returned first and the error by the next call.
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<Vec<Value>> {
+        self.push(chunk);
+        let mut values = Vec::new();
+        loop {
+            match self.next_value() {
+                Ok(Some(v)) => values.push(v),
+                Ok(None) => return Ok(values),
+                Err(e) if values.is_empty() => return Err(e),
+                Err(_) => return Ok(values)
+            }
+        }
+    }
+
+    /// Buffers `chunk` without decoding anything; see `next_value`.
+    pub fn push(&mut self, chunk: &[u8]) {
+        if self.start > 0 {
+            self.buf.drain(..self.start);
+            self.start = 0;
+        }
+        self.buf.extend_from_slice(chunk);
+    }
+
+    /// Decodes the next value from the input buffered so far, or returns
+    /// `None` if it hasn't all arrived yet.
+    pub fn next_value(&mut self) -> Result<Option<Value>> {
+        let len = match self.scan() {
+            Ok(Some(len)) => len,
+            Ok(None) => return Ok(None),
+            // Decoding what arrived fails at the same place, and says where.
+            Err(e) => {
+                return match self.decoder.decode_buf_at(&mut &self.buf[self.start..], self.offset) {
+                    Err(e) => Err(e),
+                    Ok(_) => Err(Error::Decode { offset: self.offset + self.scanned as u64, path: "$".to_string(), error: Box::new(e) })
+                };
+            }
+        };
+        // Scanned again next time, whether this value was taken or not.
+        self.scanned = 0;
+        self.pending = 0;
+        let (v, offset) = self.decoder.decode_buf_at(&mut &self.buf[self.start..self.start + len], self.offset)?;
+        self.start += len;
+        self.offset = offset;
+        Ok(Some(v))
+    }
+
+    /// Advances over the items of the next value that have fully arrived,
+    /// returning its length once all of them have.
+    fn scan(&mut self) -> Result<Option<usize>> {
+        let opts = self.decoder.options();
+        loop {
+            let rest = &self.buf[self.start + self.scanned..];
+            if rest.is_empty() {
+                return Ok(None);
+            }
+            let (header, n) = match read_header(rest) {
+                Ok(header) => header,
+                Err(Error::UnexpectedEof { .. }) => return Ok(None),
+                Err(e) => return Err(e)
+            };
+            let (payload, items) = match header {
+                Header::Scalar(len) => (len, 0),
+                Header::Str(len) | Header::Bin(len) | Header::Ext(len) => {
+                    if len > opts.max_payload_len {
+                        return Err(Error::PayloadTooLong { len, limit: opts.max_payload_len });
+                    }
+                    (len, 0)
+                },
+                Header::Array(len) | Header::Map(len) if len > opts.max_container_len => {
+                    return Err(Error::ContainerTooLong { len, limit: opts.max_container_len });
+                },
+                Header::Array(len) => (0, len),
+                Header::Map(len) => (0, len.saturating_mul(2)),
+                Header::Reserved => return Err(Error::ReservedMarker { offset: self.offset + self.scanned as u64, expected: Expected::Value })
+            };
+            if rest.len() - n < payload {
+                return Ok(None);
+            }
+            self.scanned += n + payload;
+            self.pending = self.pending.max(1) - 1;
+            self.pending = self.pending.saturating_add(items);
+            if self.pending == 0 {
+                return Ok(Some(self.scanned));
+            }
+        }
+    }
+
+    /// Bytes buffered but not yet decoded.
+    pub fn buffered(&self) -> usize {
+        self.buf.len() - self.start
+    }
+
+    /// Bytes decoded so far, which is where the next value starts.
+    pub fn position(&self) -> u64 {
+        self.offset
+    }
+
+    /// Drops everything buffered, to start over on a fresh stream.
+    pub fn reset(&mut self) {
+        self.buf.clear();
+        self.start = 0;
+        self.scanned = 0;
+        self.pending = 0;
+        self.offset = 0;
+    }
+
+    /// See `Decoder::reclaim`.
+    pub fn reclaim(&mut self, value: Value) {
+        self.decoder.reclaim(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{enc, ok};
+
+    #[test]
+    fn decodes_values_split_across_chunks() {
+        let values: Vec<Value> = (0..300).map(|i| msgpack!({"seq": i, "data": Value::Bytes(vec![7; i % 70]), "tags": ["a", [i]]})).collect();
+        let buf: Vec<u8> = values.iter().flat_map(|v| enc(v)).collect();
+        for size in [1, 2, 3, 7, 64, 1000, buf.len()] {
+            let mut parser = PushParser::new();
+            let mut decoded = Vec::new();
+            for chunk in buf.chunks(size) {
+                decoded.append(&mut ok(parser.feed(chunk)));
+            }
+            assert_eq!(decoded, values);
+            assert_eq!(parser.buffered(), 0);
+            assert_eq!(parser.position(), buf.len() as u64);
+        }
+    }
+
+    #[test]
+    fn waits_for_the_rest_of_a_value() {
+        let mut parser = PushParser::new();
+        assert_eq!(parser.feed(&[0x92, 0x01]).ok(), Some(Vec::new()));
+        assert_eq!(parser.feed(&[0xa2, b'h']).ok(), Some(Vec::new()));
+        assert_eq!(parser.buffered(), 4);
+        assert_eq!(parser.feed(&[b'i', 0xc3]).ok(), Some(vec![msgpack!([1, "hi"]), msgpack!(true)]));
+        assert_eq!(parser.next_value().ok(), Some(None));
+    }
+
+    #[test]
+    fn reports_errors_in_order() {
+        let mut parser = PushParser::new();
+        // A complete value, then a map key that is the reserved marker.
+        assert_eq!(parser.feed(&[0x01, 0x81, 0xc1]).ok(), Some(vec![msgpack!(1)]));
+        match parser.feed(&[]) {
+            Err(Error::Decode { offset, path, error }) => {
+                assert_eq!((offset, path.as_str()), (3, "$.<key>"));
+                assert!(matches!(*error, Error::ReservedMarker { offset: 2, .. }));
+            },
+            other => panic!("expected an error, got {:?}", other)
+        }
+        assert!(parser.next_value().is_err());
+        parser.reset();
+        assert_eq!(parser.feed(&[0xc0]).ok(), Some(vec![Value::Null]));
+
+        // Oversized headers fail before their payload is buffered.
+        let opts = DecodeOptions { max_payload_len: 16, ..DecodeOptions::default() };
+        let mut parser = PushParser::with_options(opts);
+        let e = parser.feed(&[0x91, 0xdb, 0x7f, 0xff, 0xff, 0xff]).err().map(Error::into_root);
+        assert!(matches!(e, Some(Error::PayloadTooLong { len: 0x7fff_ffff, limit: 16 })));
+
+        // Values that are well formed but fail to decode are retried too.
+        let mut parser = PushParser::new();
+        assert!(parser.feed(&[0xa1, 0xff]).is_err());
+        assert!(parser.feed(&[]).is_err());
+        assert_eq!(parser.buffered(), 2);
+    }
+}
@@ -0,0 +1,89 @@
+use crate::{decode_from_slice, encode_to_vec, validate, Result, Value};
+
+/// The encoded bytes of exactly one well-formed value. Stored in a document
+/// as `Value::Raw`, it is written out verbatim, so a proxy can forward a
+/// nested payload without decoding and re-encoding it; see
+/// `DecodeOptions::raw_depth`. Raw values only compare equal to other raw
+/// values with the same bytes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RawValue {
+    bytes: Vec<u8>
+}
+
+impl RawValue {
+    /// Takes the value at the front of `buf`, checking that it is complete
+    /// and well formed, and returns it with the number of bytes it spans.
+    pub fn from_slice(buf: &[u8]) -> Result<(RawValue, usize)> {
+        let len = validate::value_len(buf)?;
+        Ok((RawValue { bytes: buf[..len].to_vec() }, len))
+    }
+
+    pub fn from_value(value: &Value) -> Result<RawValue> {
+        Ok(RawValue { bytes: encode_to_vec(value)? })
+    }
+
+    /// For bytes already known to hold exactly one well-formed value.
+    pub(crate) fn from_vec_unchecked(bytes: Vec<u8>) -> RawValue {
+        RawValue { bytes }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    /// Decodes the held value.
+    pub fn decode(&self) -> Result<Value> {
+        decode_from_slice(&self.bytes).map(|(v, _)| v)
+    }
+}
+
+impl From<RawValue> for Value {
+    fn from(raw: RawValue) -> Value {
+        Value::Raw(raw)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{dec, enc, ok};
+    use crate::{decode_with, DecodeOptions, Error};
+
+    #[test]
+    fn from_slice_takes_one_value() {
+        let buf = [0x92, 0xa1, b'a', 0xc0, 0x01];
+        let (raw, len) = ok(RawValue::from_slice(&buf));
+        assert_eq!((raw.as_bytes(), len), (&buf[..4], 4));
+        assert_eq!(raw.decode().ok(), Some(msgpack!(["a", null])));
+
+        assert!(matches!(RawValue::from_slice(&[0x92, 0x01]), Err(Error::UnexpectedEof { .. })));
+        assert!(matches!(RawValue::from_slice(&[0x91, 0xc1]), Err(Error::ReservedMarker { .. })));
+        assert!(matches!(RawValue::from_slice(&[0xa1, 0xff]), Err(Error::InvalidUtf8(_))));
+    }
+
+    #[test]
+    fn proxies_nested_payloads() {
+        let payload = msgpack!({"deep": [1, 2, {"x": "y"}]});
+        let envelope = msgpack!({"to": "svc", "body": payload.clone()});
+        let buf = enc(&envelope);
+
+        let opts = DecodeOptions { raw_depth: Some(1), ..Default::default() };
+        let decoded = ok(decode_with(&mut &buf[..], &opts));
+        let body = match &decoded["body"] {
+            Value::Raw(raw) => raw.clone(),
+            v => panic!("body decoded as {}", v)
+        };
+        assert_eq!(body.decode().ok(), Some(payload));
+        assert!(matches!(decoded["to"], Value::Raw(_)));
+
+        let forwarded = enc(&decoded);
+        assert_eq!(dec(&forwarded), envelope);
+
+        let mut truncated = &buf[..buf.len() - 1];
+        assert!(decode_with(&mut truncated, &opts).is_err());
+    }
+}
@@ -0,0 +1,615 @@
+use std::convert::TryFrom;
+use std::fmt::Display;
+use std::io::Write;
+
+use serde::ser::{self, Serialize};
+
+use crate::validate::{read_header, Header};
+use crate::{
+    check_depth, decode_from_slice, encode_bin, encode_ext, encode_float, encode_int, encode_str,
+    write_array_header, write_map_header, EncodeOptions, Error, Result, Value, EXT_STRUCT_NAME
+};
+
+impl ser::Error for Error {
+    fn custom<T: Display>(msg: T) -> Error {
+        Error::Custom(msg.to_string())
+    }
+}
+
+/// How the serde layer lays out enum variants, to match the wire format of
+/// peers that don't follow serde's default. The same setting has to be
+/// given to the `Deserializer` reading the data back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EnumRepr {
+    /// `"Variant"` for unit variants, otherwise `{"Variant": content}`.
+    #[default]
+    External,
+    /// Like `External`, but naming variants by their index: `2` or `{2: content}`.
+    Index,
+    /// `{tag: "Variant", field: value, ..}`. Only unit variants, struct
+    /// variants and newtype variants holding a struct or map fit this form,
+    /// and the tag has to be the first key when reading.
+    Internal { tag: &'static str },
+    /// `{tag: "Variant", content: content}`, or just `{tag: "Variant"}`
+    /// for unit variants.
+    Adjacent { tag: &'static str, content: &'static str }
+}
+
+/// Writes any `Serialize` type straight to MessagePack, producing the same
+/// bytes as rmp-serde: structs become positional arrays (or maps, see
+/// `with_struct_map`), unit variants their name, and other variants a
+/// single-entry map from name to content (see `with_enum_repr`).
+pub struct Serializer<W> {
+    writer: W,
+    opts: EncodeOptions,
+    depth: usize,
+    struct_map: bool,
+    enum_repr: EnumRepr
+}
+
+impl<W: Write> Serializer<W> {
+    pub fn new(writer: W) -> Serializer<W> {
+        Serializer::with_options(writer, EncodeOptions::default())
+    }
+
+    pub fn with_options(writer: W, opts: EncodeOptions) -> Serializer<W> {
+        Serializer { writer, opts, depth: 0, struct_map: false, enum_repr: EnumRepr::External }
+    }
+
+    /// Writes structs and struct variants as maps keyed by field name,
+    /// which is larger but self-describing and tolerates reordered fields.
+    pub fn with_struct_map(mut self) -> Serializer<W> {
+        self.struct_map = true;
+        self
+    }
+
+    /// Writes structs as positional arrays, the default.
+    pub fn with_struct_tuple(mut self) -> Serializer<W> {
+        self.struct_map = false;
+        self
+    }
+
+    pub fn with_enum_repr(mut self, repr: EnumRepr) -> Serializer<W> {
+        self.enum_repr = repr;
+        self
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn enter(&mut self) -> Result<()> {
+        check_depth(&self.opts, self.depth)?;
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// Starts an array or map. Without a length the elements are buffered
+    /// until `end`, when the header can finally be written.
+    fn compound(&mut self, len: Option<usize>, map: bool) -> Result<Compound<'_, W>> {
+        self.enter()?;
+        match len {
+            Some(len) => {
+                if map {
+                    write_map_header(&mut self.writer, len)?;
+                } else {
+                    write_array_header(&mut self.writer, len)?;
+                }
+                Ok(Compound { ser: self, buffer: None, count: 0, map })
+            },
+            None => Ok(Compound { ser: self, buffer: Some(Vec::new()), count: 0, map })
+        }
+    }
+
+    /// A serializer with the same settings for a nested value written out
+    /// of line.
+    fn nested<'b>(&self, buf: &'b mut Vec<u8>, struct_map: bool) -> Serializer<&'b mut Vec<u8>> {
+        Serializer {
+            writer: buf,
+            opts: self.opts.clone(),
+            depth: self.depth,
+            struct_map,
+            enum_repr: self.enum_repr
+        }
+    }
+
+    /// Writes everything that precedes a variant's content, or the whole
+    /// variant if it is a unit variant.
+    fn variant(&mut self, index: u32, variant: &str, unit: bool) -> Result<()> {
+        match self.enum_repr {
+            EnumRepr::External => {
+                if !unit {
+                    write_map_header(&mut self.writer, 1)?;
+                }
+                encode_str(&mut self.writer, variant, &self.opts)
+            },
+            EnumRepr::Index => {
+                if !unit {
+                    write_map_header(&mut self.writer, 1)?;
+                }
+                encode_int(&mut self.writer, index as i128, &self.opts)
+            },
+            EnumRepr::Internal { tag } | EnumRepr::Adjacent { tag, .. } if unit => {
+                write_map_header(&mut self.writer, 1)?;
+                encode_str(&mut self.writer, tag, &self.opts)?;
+                encode_str(&mut self.writer, variant, &self.opts)
+            },
+            EnumRepr::Adjacent { tag, content } => {
+                write_map_header(&mut self.writer, 2)?;
+                encode_str(&mut self.writer, tag, &self.opts)?;
+                encode_str(&mut self.writer, variant, &self.opts)?;
+                encode_str(&mut self.writer, content, &self.opts)
+            },
+            EnumRepr::Internal { .. } => Err(Error::Custom(format!(
+                "variant {} can't be internally tagged; only unit, struct and newtype variants can",
+                variant
+            )))
+        }
+    }
+
+    /// Writes an internally tagged newtype variant by splicing the tag into
+    /// the map its content serializes to.
+    fn internal_newtype<T: Serialize + ?Sized>(&mut self, tag: &str, variant: &str, value: &T) -> Result<()> {
+        let mut buf = Vec::new();
+        value.serialize(&mut self.nested(&mut buf, true))?;
+        match read_header(&buf)? {
+            (Header::Map(len), n) => {
+                write_map_header(&mut self.writer, len + 1)?;
+                encode_str(&mut self.writer, tag, &self.opts)?;
+                encode_str(&mut self.writer, variant, &self.opts)?;
+                self.writer.write_all(&buf[n..])?;
+                Ok(())
+            },
+            _ => Err(Error::Custom(format!(
+                "variant {} can't be internally tagged; its content is not a struct or map",
+                variant
+            )))
+        }
+    }
+}
+
+/// Encodes `value` into `w`.
+pub fn to_writer<W: Write, T: Serialize + ?Sized>(w: W, value: &T) -> Result<()> {
+    value.serialize(&mut Serializer::new(w))
+}
+
+/// Encodes `value` into a new buffer.
+pub fn to_vec<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    to_writer(&mut buf, value)?;
+    Ok(buf)
+}
+
+/// Like `to_vec`, but writes structs as maps keyed by field name.
+pub fn to_vec_named<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    value.serialize(&mut Serializer::new(&mut buf).with_struct_map())?;
+    Ok(buf)
+}
+
+impl<'a, W: Write> ser::Serializer for &'a mut Serializer<W> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Compound<'a, W>;
+    type SerializeTuple = Compound<'a, W>;
+    type SerializeTupleStruct = Compound<'a, W>;
+    type SerializeTupleVariant = Compound<'a, W>;
+    type SerializeMap = Compound<'a, W>;
+    type SerializeStruct = Compound<'a, W>;
+    type SerializeStructVariant = Compound<'a, W>;
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    fn serialize_bool(self, v: bool) -> Result<()> {
+        self.writer.write_all(&[if v { 0xc3 } else { 0xc2 }])?;
+        Ok(())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<()> {
+        encode_int(&mut self.writer, v as i128, &self.opts)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<()> {
+        encode_int(&mut self.writer, v as i128, &self.opts)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<()> {
+        encode_int(&mut self.writer, v as i128, &self.opts)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<()> {
+        encode_int(&mut self.writer, v as i128, &self.opts)
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<()> {
+        encode_int(&mut self.writer, v, &self.opts)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<()> {
+        encode_int(&mut self.writer, v as i128, &self.opts)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<()> {
+        encode_int(&mut self.writer, v as i128, &self.opts)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<()> {
+        encode_int(&mut self.writer, v as i128, &self.opts)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<()> {
+        encode_int(&mut self.writer, v as i128, &self.opts)
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<()> {
+        let v = i128::try_from(v).map_err(|_| Error::IntegerOutOfRange)?;
+        encode_int(&mut self.writer, v, &self.opts)
+    }
+
+    fn serialize_f32(self, mut v: f32) -> Result<()> {
+        if self.opts.canonical_nan && v.is_nan() {
+            v = f32::NAN;
+        }
+        if self.opts.normalize_negative_zero && v == 0.0 {
+            v = 0.0;
+        }
+        self.writer.write_all(&[0xca])?;
+        self.writer.write_all(&v.to_bits().to_be_bytes())?;
+        Ok(())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<()> {
+        encode_float(&mut self.writer, v, &self.opts)
+    }
+
+    fn serialize_char(self, v: char) -> Result<()> {
+        encode_str(&mut self.writer, v.encode_utf8(&mut [0; 4]), &self.opts)
+    }
+
+    fn serialize_str(self, v: &str) -> Result<()> {
+        encode_str(&mut self.writer, v, &self.opts)
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<()> {
+        encode_bin(&mut self.writer, v, &self.opts)
+    }
+
+    fn serialize_none(self) -> Result<()> {
+        self.serialize_unit()
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<()> {
+        self.writer.write_all(&[0xc0])?;
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, index: u32, variant: &'static str) -> Result<()> {
+        self.variant(index, variant, true)
+    }
+
+    /// `EXT_STRUCT_NAME` wraps an `(i8, bytes)` pair to be written as ext.
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, name: &'static str, value: &T) -> Result<()> {
+        if name != EXT_STRUCT_NAME {
+            return value.serialize(self);
+        }
+        let mut buf = Vec::new();
+        value.serialize(&mut Serializer::with_options(&mut buf, self.opts.clone()))?;
+        match decode_from_slice(&buf)?.0 {
+            Value::Array(a) => match a.as_slice() {
+                [Value::Integer(t), Value::Bytes(d)] if i8::try_from(*t).is_ok() => {
+                    encode_ext(&mut self.writer, *t as i8, d)
+                },
+                _ => Err(Error::Custom("ext must hold an i8 type and bytes".to_string()))
+            },
+            _ => Err(Error::Custom("ext must hold an i8 type and bytes".to_string()))
+        }
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        index: u32,
+        variant: &'static str,
+        value: &T
+    ) -> Result<()> {
+        if let EnumRepr::Internal { tag } = self.enum_repr {
+            return self.internal_newtype(tag, variant, value);
+        }
+        self.variant(index, variant, false)?;
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Compound<'a, W>> {
+        self.compound(len, false)
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Compound<'a, W>> {
+        self.compound(Some(len), false)
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<Compound<'a, W>> {
+        self.compound(Some(len), false)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        index: u32,
+        variant: &'static str,
+        len: usize
+    ) -> Result<Compound<'a, W>> {
+        self.variant(index, variant, false)?;
+        self.compound(Some(len), false)
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Compound<'a, W>> {
+        self.compound(len, true)
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Compound<'a, W>> {
+        let map = self.struct_map;
+        self.compound(Some(len), map)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        index: u32,
+        variant: &'static str,
+        len: usize
+    ) -> Result<Compound<'a, W>> {
+        if let EnumRepr::Internal { tag } = self.enum_repr {
+            self.enter()?;
+            write_map_header(&mut self.writer, len + 1)?;
+            encode_str(&mut self.writer, tag, &self.opts)?;
+            encode_str(&mut self.writer, variant, &self.opts)?;
+            return Ok(Compound { ser: self, buffer: None, count: 0, map: true });
+        }
+        self.variant(index, variant, false)?;
+        let map = self.struct_map;
+        self.compound(Some(len), map)
+    }
+}
+
+/// An array or map being written by `Serializer`.
+pub struct Compound<'a, W> {
+    ser: &'a mut Serializer<W>,
+    /// Encoded elements, when the length wasn't known up front.
+    buffer: Option<Vec<u8>>,
+    /// Elements, or for maps entries, written so far.
+    count: usize,
+    map: bool
+}
+
+impl<W: Write> Compound<'_, W> {
+    fn element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        match self.buffer {
+            Some(ref mut buf) => {
+                value.serialize(&mut self.ser.nested(buf, self.ser.struct_map))
+            },
+            None => value.serialize(&mut *self.ser)
+        }
+    }
+
+    /// A struct field, preceded by its name when writing structs as maps.
+    fn field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<()> {
+        if self.map {
+            self.element(key)?;
+        }
+        self.element(value)
+    }
+
+    fn finish(self) -> Result<()> {
+        if let Some(buf) = self.buffer {
+            if self.map {
+                write_map_header(&mut self.ser.writer, self.count)?;
+            } else {
+                write_array_header(&mut self.ser.writer, self.count)?;
+            }
+            self.ser.writer.write_all(&buf)?;
+        }
+        self.ser.depth -= 1;
+        Ok(())
+    }
+}
+
+impl<W: Write> ser::SerializeSeq for Compound<'_, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.count += 1;
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish()
+    }
+}
+
+impl<W: Write> ser::SerializeTuple for Compound<'_, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish()
+    }
+}
+
+impl<W: Write> ser::SerializeTupleStruct for Compound<'_, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish()
+    }
+}
+
+impl<W: Write> ser::SerializeTupleVariant for Compound<'_, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish()
+    }
+}
+
+impl<W: Write> ser::SerializeMap for Compound<'_, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        self.count += 1;
+        self.element(key)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish()
+    }
+}
+
+impl<W: Write> ser::SerializeStruct for Compound<'_, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<()> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish()
+    }
+}
+
+impl<W: Write> ser::SerializeStructVariant for Compound<'_, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<()> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde::Serialize;
+
+    use super::*;
+    use crate::test_util::{dec, enc, ok, ser};
+    use crate::{Error, Value};
+
+    #[derive(Serialize)]
+    struct Point {
+        x: i32,
+        y: i32
+    }
+
+    #[derive(Serialize)]
+    enum Shape {
+        Empty,
+        Dot(Point),
+        Line(Point, Point),
+        Circle { r: f64 }
+    }
+
+    #[test]
+    fn matches_rmp_serde_layout() {
+        assert_eq!(ser(&Point { x: 1, y: -2 }), vec![0x92, 0x01, 0xfe]);
+        assert_eq!(ser(&Shape::Empty), enc(&msgpack!("Empty")));
+        assert_eq!(ser(&Shape::Dot(Point { x: 1, y: 2 })), enc(&msgpack!({"Dot": [1, 2]})));
+        assert_eq!(
+            ser(&Shape::Line(Point { x: 1, y: 2 }, Point { x: 3, y: 4 })),
+            enc(&msgpack!({"Line": [[1, 2], [3, 4]]}))
+        );
+        assert_eq!(ser(&Shape::Circle { r: 0.5 }), enc(&msgpack!({"Circle": [0.5]})));
+        assert_eq!(ser(&(Some(3u64), None::<u8>, 1.5f32)), vec![0x93, 0x03, 0xc0, 0xca, 0x3f, 0xc0, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn agrees_with_value_encoding() {
+        let v = msgpack!({"a": [1, null, true, -2.5, "s"], "b": (vec![1u8, 2])});
+        let mut sorted = BTreeMap::new();
+        sorted.insert("k", vec!["x", "y"]);
+        assert_eq!(ser(&sorted), enc(&msgpack!({"k": ["x", "y"]})));
+        assert_eq!(dec(&ser(&v)), v);
+        assert_eq!(ser(&Value::Ext(5, vec![1, 2])), enc(&Value::Ext(5, vec![1, 2])));
+    }
+
+    #[test]
+    fn structs_as_maps() {
+        let named = ok(to_vec_named(&(Point { x: 1, y: 2 }, Shape::Circle { r: 0.5 })));
+        assert_eq!(dec(&named), msgpack!([{"x": 1, "y": 2}, {"Circle": {"r": 0.5}}]));
+        assert_eq!(&named[..4], &[0x92, 0x82, 0xa1, b'x']);
+        let mut buf = Vec::new();
+        let mut ser = Serializer::new(&mut buf).with_struct_map().with_struct_tuple();
+        assert!(Point { x: 1, y: 2 }.serialize(&mut ser).is_ok());
+        assert_eq!(buf, vec![0x92, 0x01, 0x02]);
+    }
+
+    #[test]
+    fn unknown_lengths_are_buffered() {
+        struct Odd;
+        impl Serialize for Odd {
+            fn serialize<S: serde::Serializer>(&self, s: S) -> std::result::Result<S::Ok, S::Error> {
+                s.collect_seq((1..=20).filter(|i| i % 2 == 1))
+            }
+        }
+        assert_eq!(ser(&Odd), enc(&msgpack!([1, 3, 5, 7, 9, 11, 13, 15, 17, 19])));
+    }
+
+    #[test]
+    fn depth_limit_applies() {
+        let nested = vec![vec![vec![1]]];
+        let opts = EncodeOptions { max_depth: 2, ..EncodeOptions::default() };
+        let mut buf = Vec::new();
+        let r = nested.serialize(&mut Serializer::with_options(&mut buf, opts));
+        assert!(matches!(r, Err(Error::DepthLimitExceeded)));
+    }
+
+    #[test]
+    fn normalizes_f32_like_f64() {
+        let odd_nan = f32::from_bits(0x7fc0_0001);
+        let opts = EncodeOptions { canonical_nan: true, normalize_negative_zero: true, ..EncodeOptions::default() };
+        let mut buf = Vec::new();
+        assert!((odd_nan, -0.0f32).serialize(&mut Serializer::with_options(&mut buf, opts)).is_ok());
+        let mut expected = vec![0x92, 0xca];
+        expected.extend_from_slice(&f32::NAN.to_bits().to_be_bytes());
+        expected.extend_from_slice(&[0xca, 0, 0, 0, 0]);
+        assert_eq!(buf, expected);
+        let plain = ser(&(odd_nan, -0.0f32));
+        assert_eq!(plain[2..6], odd_nan.to_bits().to_be_bytes());
+        assert_eq!(plain[7..], (-0.0f32).to_bits().to_be_bytes());
+    }
+}
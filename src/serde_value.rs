@@ -0,0 +1,691 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+use serde::de::value::{MapDeserializer, SeqDeserializer};
+use serde::de::{self, Deserialize, DeserializeOwned, Deserializer, IntoDeserializer, MapAccess, SeqAccess, Visitor};
+use serde::forward_to_deserialize_any;
+use serde::ser::{self, Serialize, SerializeMap, SerializeSeq, Serializer};
+
+use crate::{Error, Map, Result, Value};
+
+/// Newtype struct name under which ext values travel through serde, the
+/// same one rmp-serde uses, holding an `(i8, bytes)` tuple.
+pub const EXT_STRUCT_NAME: &str = "_ExtStruct";
+
+struct Bytes<'a>(&'a [u8]);
+
+impl Serialize for Bytes<'_> {
+    fn serialize<S: Serializer>(&self, s: S) -> std::result::Result<S::Ok, S::Error> {
+        s.serialize_bytes(self.0)
+    }
+}
+
+/// Integers use the narrowest serde integer type that holds them, so
+/// formats without 128-bit support still accept everything in i64/u64.
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, s: S) -> std::result::Result<S::Ok, S::Error> {
+        match self {
+            Value::Null => s.serialize_unit(),
+            Value::Boolean(b) => s.serialize_bool(*b),
+            Value::Integer(i) | Value::FixedInteger(i, _) => {
+                if let Ok(i) = i64::try_from(*i) {
+                    s.serialize_i64(i)
+                } else if let Ok(u) = u64::try_from(*i) {
+                    s.serialize_u64(u)
+                } else {
+                    s.serialize_i128(*i)
+                }
+            },
+            Value::Float(f) => s.serialize_f64(*f),
+            Value::String(v) => s.serialize_str(v),
+            Value::SharedString(v) => s.serialize_str(v),
+            Value::InlineString(v) => s.serialize_str(v),
+            Value::Bytes(b) => s.serialize_bytes(b),
+            Value::Array(a) => {
+                let mut seq = s.serialize_seq(Some(a.len()))?;
+                for v in a {
+                    seq.serialize_element(v)?;
+                }
+                seq.end()
+            },
+            Value::Map(m) => {
+                let mut map = s.serialize_map(Some(m.len()))?;
+                for (k, v) in m {
+                    map.serialize_entry(k, v)?;
+                }
+                map.end()
+            },
+            Value::Ext(t, d) => s.serialize_newtype_struct(EXT_STRUCT_NAME, &(t, Bytes(d))),
+            Value::Raw(raw) => raw.decode().map_err(ser::Error::custom)?.serialize(s)
+        }
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "any MessagePack value")
+    }
+
+    fn visit_bool<E>(self, b: bool) -> std::result::Result<Value, E> {
+        Ok(Value::Boolean(b))
+    }
+
+    fn visit_i64<E>(self, i: i64) -> std::result::Result<Value, E> {
+        Ok(Value::Integer(i as i128))
+    }
+
+    fn visit_u64<E>(self, u: u64) -> std::result::Result<Value, E> {
+        Ok(Value::Integer(u as i128))
+    }
+
+    fn visit_i128<E>(self, i: i128) -> std::result::Result<Value, E> {
+        Ok(Value::Integer(i))
+    }
+
+    fn visit_u128<E: de::Error>(self, u: u128) -> std::result::Result<Value, E> {
+        i128::try_from(u).map(Value::Integer).map_err(|_| E::custom("integer out of range"))
+    }
+
+    fn visit_f64<E>(self, f: f64) -> std::result::Result<Value, E> {
+        Ok(Value::Float(f))
+    }
+
+    fn visit_str<E>(self, s: &str) -> std::result::Result<Value, E> {
+        Ok(Value::String(s.to_string()))
+    }
+
+    fn visit_string<E>(self, s: String) -> std::result::Result<Value, E> {
+        Ok(Value::String(s))
+    }
+
+    fn visit_bytes<E>(self, b: &[u8]) -> std::result::Result<Value, E> {
+        Ok(Value::Bytes(b.to_vec()))
+    }
+
+    fn visit_byte_buf<E>(self, b: Vec<u8>) -> std::result::Result<Value, E> {
+        Ok(Value::Bytes(b))
+    }
+
+    fn visit_unit<E>(self) -> std::result::Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_none<E>(self) -> std::result::Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, d: D) -> std::result::Result<Value, D::Error> {
+        Value::deserialize(d)
+    }
+
+    /// Only ext values arrive as newtype structs.
+    fn visit_newtype_struct<D: Deserializer<'de>>(self, d: D) -> std::result::Result<Value, D::Error> {
+        let (t, data): (i8, Value) = Deserialize::deserialize(d)?;
+        match data {
+            Value::Bytes(b) => Ok(Value::Ext(t, b)),
+            v => Err(de::Error::invalid_type(de::Unexpected::Other(v.kind()), &"ext payload bytes"))
+        }
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> std::result::Result<Value, A::Error> {
+        let mut a = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+        while let Some(v) = seq.next_element()? {
+            a.push(v);
+        }
+        Ok(Value::Array(a))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> std::result::Result<Value, A::Error> {
+        let mut m = Map::default();
+        while let Some((k, v)) = access.next_entry()? {
+            m.insert(k, v);
+        }
+        Ok(Value::Map(m))
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> std::result::Result<Value, D::Error> {
+        d.deserialize_any(ValueVisitor)
+    }
+}
+
+/// Converts `value` into a `Value`, laid out as `to_vec` would encode it:
+/// structs become arrays and enums are externally tagged.
+pub fn to_value<T: Serialize + ?Sized>(value: &T) -> Result<Value> {
+    value.serialize(ValueSerializer)
+}
+
+/// Builds a `T` from `value`, accepting whatever `from_slice` would accept
+/// from its encoding.
+pub fn from_value<T: DeserializeOwned>(value: Value) -> Result<T> {
+    T::deserialize(value)
+}
+
+struct ValueSerializer;
+
+fn int<T: Into<i128>>(i: T) -> Result<Value> {
+    Ok(Value::Integer(i.into()))
+}
+
+impl Serializer for ValueSerializer {
+    type Ok = Value;
+    type Error = Error;
+    type SerializeSeq = SerializeArray;
+    type SerializeTuple = SerializeArray;
+    type SerializeTupleStruct = SerializeArray;
+    type SerializeTupleVariant = SerializeArray;
+    type SerializeMap = SerializeObject;
+    type SerializeStruct = SerializeArray;
+    type SerializeStructVariant = SerializeArray;
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    fn serialize_bool(self, v: bool) -> Result<Value> {
+        Ok(Value::Boolean(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Value> {
+        int(v)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Value> {
+        int(v)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Value> {
+        int(v)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Value> {
+        int(v)
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<Value> {
+        int(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Value> {
+        int(v)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Value> {
+        int(v)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Value> {
+        int(v)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Value> {
+        int(v)
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<Value> {
+        i128::try_from(v).map(Value::Integer).map_err(|_| Error::IntegerOutOfRange)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Value> {
+        Ok(Value::Float(v as f64))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Value> {
+        Ok(Value::Float(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Value> {
+        Ok(Value::String(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Value> {
+        Ok(Value::String(v.to_string()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Value> {
+        Ok(Value::Bytes(v.to_vec()))
+    }
+
+    fn serialize_none(self) -> Result<Value> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Value> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Value> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Value> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, variant: &'static str) -> Result<Value> {
+        Ok(Value::String(variant.to_string()))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, name: &'static str, value: &T) -> Result<Value> {
+        let inner = value.serialize(self)?;
+        if name != EXT_STRUCT_NAME {
+            return Ok(inner);
+        }
+        match inner {
+            Value::Array(mut a) if a.len() == 2 => match (a.pop(), a.pop()) {
+                (Some(Value::Bytes(d)), Some(Value::Integer(t))) if i8::try_from(t).is_ok() => Ok(Value::Ext(t as i8, d)),
+                _ => Err(Error::Custom("ext must hold an i8 type and bytes".to_string()))
+            },
+            _ => Err(Error::Custom("ext must hold an i8 type and bytes".to_string()))
+        }
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T
+    ) -> Result<Value> {
+        let mut m = Map::default();
+        m.insert(Value::String(variant.to_string()), value.serialize(self)?);
+        Ok(Value::Map(m))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeArray> {
+        Ok(SerializeArray { variant: None, items: Vec::with_capacity(len.unwrap_or(0).min(4096)) })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeArray> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<SerializeArray> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize
+    ) -> Result<SerializeArray> {
+        Ok(SerializeArray { variant: Some(variant), items: Vec::with_capacity(len.min(4096)) })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<SerializeObject> {
+        Ok(SerializeObject { map: Map::default(), key: None })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<SerializeArray> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        len: usize
+    ) -> Result<SerializeArray> {
+        self.serialize_tuple_variant(name, index, variant, len)
+    }
+}
+
+/// An array being built by `to_value`, wrapped in a single-entry map from
+/// `variant` when it is the content of an enum variant.
+struct SerializeArray {
+    variant: Option<&'static str>,
+    items: Vec<Value>
+}
+
+impl SerializeArray {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.items.push(value.serialize(ValueSerializer)?);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Value> {
+        let array = Value::Array(self.items);
+        match self.variant {
+            Some(variant) => {
+                let mut m = Map::default();
+                m.insert(Value::String(variant.to_string()), array);
+                Ok(Value::Map(m))
+            },
+            None => Ok(array)
+        }
+    }
+}
+
+impl SerializeSeq for SerializeArray {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for SerializeArray {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeArray {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeArray {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStruct for SerializeArray {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, _key: &'static str, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeArray {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, _key: &'static str, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value> {
+        self.finish()
+    }
+}
+
+struct SerializeObject {
+    map: Map,
+    key: Option<Value>
+}
+
+impl SerializeMap for SerializeObject {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        self.key = Some(key.serialize(ValueSerializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        let key = match self.key.take() {
+            Some(key) => key,
+            None => return Err(Error::Custom("serialize_value called before serialize_key".into()))
+        };
+        self.map.insert(key, value.serialize(ValueSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value> {
+        Ok(Value::Map(self.map))
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for Value {
+    type Deserializer = Value;
+
+    fn into_deserializer(self) -> Value {
+        self
+    }
+}
+
+/// Reads a `Value` as `Deserializer` reads its encoding.
+impl<'de> Deserializer<'de> for Value {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self {
+            Value::Null => visitor.visit_unit(),
+            Value::Boolean(b) => visitor.visit_bool(b),
+            Value::Integer(i) | Value::FixedInteger(i, _) => {
+                if let Ok(i) = i64::try_from(i) {
+                    visitor.visit_i64(i)
+                } else if let Ok(u) = u64::try_from(i) {
+                    visitor.visit_u64(u)
+                } else {
+                    visitor.visit_i128(i)
+                }
+            },
+            Value::Float(f) => visitor.visit_f64(f),
+            Value::String(s) => visitor.visit_string(s),
+            Value::SharedString(s) => visitor.visit_str(&s),
+            Value::InlineString(s) => visitor.visit_str(&s),
+            Value::Bytes(b) => visitor.visit_byte_buf(b),
+            Value::Array(a) => {
+                let mut seq = SeqDeserializer::new(a.into_iter());
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(value)
+            },
+            Value::Map(m) => {
+                let mut map = MapDeserializer::new(m.into_iter());
+                let value = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(value)
+            },
+            Value::Ext(t, d) => {
+                let parts = vec![Value::Integer(t as i128), Value::Bytes(d)];
+                visitor.visit_newtype_struct(SeqDeserializer::<_, Error>::new(parts.into_iter()))
+            },
+            Value::Raw(raw) => raw.decode()?.deserialize_any(visitor)
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self {
+            Value::Null => visitor.visit_none(),
+            v => visitor.visit_some(v)
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, name: &'static str, visitor: V) -> Result<V::Value> {
+        if name == EXT_STRUCT_NAME {
+            self.deserialize_any(visitor)
+        } else {
+            visitor.visit_newtype_struct(self)
+        }
+    }
+
+    /// Bytes are read as a sequence, as `Deserializer` does.
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self {
+            Value::Bytes(b) => {
+                let mut seq = SeqDeserializer::<_, Error>::new(b.into_iter());
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(value)
+            },
+            v => v.deserialize_any(visitor)
+        }
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V
+    ) -> Result<V::Value> {
+        match self {
+            Value::Map(m) => {
+                let mut entries = m.into_iter();
+                if let (Some((variant, content)), None) = (entries.next(), entries.next()) {
+                    return visitor.visit_enum(VariantValue { variant, content: Some(content) });
+                }
+                Err(de::Error::invalid_type(de::Unexpected::Other("container"), &"enum"))
+            },
+            Value::Array(_) => Err(de::Error::invalid_type(de::Unexpected::Other("container"), &"enum")),
+            Value::Raw(raw) => raw.decode()?.deserialize_enum(name, variants, visitor),
+            variant => visitor.visit_enum(VariantValue { variant, content: None })
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct tuple_struct map struct identifier ignored_any
+    }
+}
+
+/// An enum read from a `Value`: its variant name or index, and the content
+/// unless it is a unit variant.
+struct VariantValue {
+    variant: Value,
+    content: Option<Value>
+}
+
+impl<'de> de::EnumAccess<'de> for VariantValue {
+    type Error = Error;
+    type Variant = Content;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Content)> {
+        Ok((seed.deserialize(self.variant)?, Content(self.content)))
+    }
+}
+
+struct Content(Option<Value>);
+
+impl<'de> de::VariantAccess<'de> for Content {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        match self.0 {
+            Some(v) => seed.deserialize(v),
+            None => Err(de::Error::invalid_type(de::Unexpected::UnitVariant, &"newtype variant"))
+        }
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        match self.0 {
+            Some(v) => v.deserialize_any(visitor),
+            None => Err(de::Error::invalid_type(de::Unexpected::UnitVariant, &"tuple variant"))
+        }
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value> {
+        match self.0 {
+            Some(v) => v.deserialize_any(visitor),
+            None => Err(de::Error::invalid_type(de::Unexpected::UnitVariant, &"struct variant"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{dec, ok, ser};
+
+    #[test]
+    fn round_trips_through_json() {
+        let v = msgpack!({"a": [1, null, true, -2.5, "s"], "big": (u64::MAX), "neg": (i64::MIN)});
+        let json = ok(serde_json::to_string(&v));
+        let back: Value = ok(serde_json::from_str(&json));
+        assert_eq!(back, v);
+    }
+
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct Item {
+        id: u32,
+        tags: Vec<String>,
+        kind: Kind,
+        parent: Option<Box<Item>>
+    }
+
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    enum Kind {
+        Plain,
+        Sized(u8, u8),
+        Named { name: String }
+    }
+
+    #[test]
+    fn typed_conversions() {
+        let parent = Item { id: 1, tags: vec![], kind: Kind::Plain, parent: None };
+        let item = Item {
+            id: 2,
+            tags: vec!["a".into()],
+            kind: Kind::Named { name: "n".into() },
+            parent: Some(Box::new(parent))
+        };
+        let v = ok(to_value(&item));
+        assert_eq!(v, msgpack!([2, ["a"], {"Named": ["n"]}, [1, [], "Plain", null]]));
+        assert_eq!(dec(&ser(&item)), v);
+        assert_eq!(from_value::<Item>(v).ok(), Some(item));
+        assert_eq!(from_value::<Kind>(msgpack!({"Sized": [1, 2]})).ok(), Some(Kind::Sized(1, 2)));
+        assert!(from_value::<Kind>(msgpack!({"Sized": [1, 2, 3]})).is_err());
+        assert!(from_value::<Kind>(msgpack!({"Plain": null, "Sized": [1, 2]})).is_err());
+        assert!(from_value::<Kind>(Value::Map(Map::default())).is_err());
+        assert_eq!(from_value::<Value>(Value::Ext(2, vec![1])).ok(), Some(Value::Ext(2, vec![1])));
+        assert_eq!(to_value(&Value::Ext(2, vec![1])).ok(), Some(Value::Ext(2, vec![1])));
+    }
+
+    #[test]
+    fn bytes_and_ext_serialize() {
+        let json = serde_json::to_string(&msgpack!([(vec![1u8, 2]), (Value::Ext(5, vec![9]))]));
+        assert_eq!(json.ok().as_deref(), Some("[[1,2],[5,[9]]]"));
+    }
+
+    #[test]
+    fn rejects_a_value_without_a_key() {
+        let mut map = ok(ValueSerializer.serialize_map(None));
+        match map.serialize_value(&1) {
+            Err(Error::Custom(msg)) => assert_eq!(msg, "serialize_value called before serialize_key"),
+            other => panic!("unexpected {:?}", other)
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use std::ops::Deref;
+use std::sync::Arc;
+
+use crate::Value;
+
+/// An immutable document behind an `Arc`: cloning it is a reference count
+/// increment, so one decoded tree can be handed to many threads. It
+/// dereferences to `Value` for reading; `make_mut` copies the tree only if
+/// other handles still share it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct SharedValue(Arc<Value>);
+
+impl SharedValue {
+    pub fn new(value: Value) -> SharedValue {
+        SharedValue(Arc::new(value))
+    }
+
+    /// Returns a mutable reference, first cloning the tree if it is shared.
+    pub fn make_mut(&mut self) -> &mut Value {
+        Arc::make_mut(&mut self.0)
+    }
+
+    /// Unwraps the value, cloning it if other handles remain.
+    pub fn into_value(self) -> Value {
+        Arc::try_unwrap(self.0).unwrap_or_else(|shared| (*shared).clone())
+    }
+
+    /// Whether both handles point at the same tree.
+    pub fn ptr_eq(&self, that: &SharedValue) -> bool {
+        Arc::ptr_eq(&self.0, &that.0)
+    }
+}
+
+impl Deref for SharedValue {
+    type Target = Value;
+
+    fn deref(&self) -> &Value {
+        &self.0
+    }
+}
+
+impl From<Value> for SharedValue {
+    fn from(value: Value) -> SharedValue {
+        SharedValue::new(value)
+    }
+}
+
+impl From<Arc<Value>> for SharedValue {
+    fn from(value: Arc<Value>) -> SharedValue {
+        SharedValue(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shares_across_threads() {
+        let doc = SharedValue::new(msgpack!({"items": [1, 2, 3]}));
+        let handles: Vec<_> = (0..4).map(|_| {
+            let doc = doc.clone();
+            std::thread::spawn(move || doc["items"].members().count())
+        }).collect();
+        for h in handles {
+            assert_eq!(h.join().ok(), Some(3));
+        }
+    }
+
+    #[test]
+    fn copies_on_write() {
+        let a = SharedValue::new(msgpack!([1]));
+        let mut b = a.clone();
+        assert!(a.ptr_eq(&b));
+        b.make_mut().set_at("/-", Value::Integer(2)).ok();
+        assert!(!a.ptr_eq(&b));
+        assert_eq!(*a, msgpack!([1]));
+        assert_eq!(b.into_value(), msgpack!([1, 2]));
+        assert_eq!(a.into_value(), msgpack!([1]));
+    }
+}
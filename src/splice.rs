@@ -0,0 +1,164 @@
+use crate::pointer::{array_index, path_error, tokens};
+use crate::validate::{read_header, skip_len, Header};
+use crate::{decode_from_slice, encode_to_vec, write_array_header, write_map_header, Result, Value};
+
+/// Where the last token of a pointer leads inside an encoded document.
+enum Target {
+    /// An existing value occupying these bytes.
+    Existing(usize, usize),
+    /// A new member to be written at `at`, growing the container whose
+    /// header of `n` bytes starts at `header` from `len` members.
+    New { header: usize, n: usize, len: usize, is_map: bool, at: usize }
+}
+
+/// Finds the member addressed by `token` in the container at `buf[pos..]`.
+fn member(buf: &[u8], pos: usize, token: &str, pointer: &str) -> Result<Target> {
+    let (header, n) = read_header(&buf[pos..])?;
+    let mut at = pos + n;
+    match header {
+        Header::Array(len) => {
+            let idx = if token == "-" { len } else {
+                array_index(token).ok_or_else(|| path_error(pointer, "bad array position"))?
+            };
+            if idx > len {
+                return Err(path_error(pointer, "array position out of range"));
+            }
+            for _ in 0..idx {
+                at += skip_len(&buf[at..])?;
+            }
+            if idx == len {
+                return Ok(Target::New { header: pos, n, len, is_map: false, at });
+            }
+            Ok(Target::Existing(at, at + skip_len(&buf[at..])?))
+        },
+        Header::Map(len) => {
+            // String keys win over integer keys, as for `Value::pointer`.
+            let int_key = token.parse::<i128>().ok();
+            let mut int_match = None;
+            for _ in 0..len {
+                let key_len = skip_len(&buf[at..])?;
+                let value = at + key_len;
+                let value_end = value + skip_len(&buf[value..])?;
+                match read_header(&buf[at..])? {
+                    (Header::Str(len), n) if &buf[at + n..at + n + len] == token.as_bytes() => {
+                        return Ok(Target::Existing(value, value_end));
+                    },
+                    (Header::Scalar(_), _) if int_key.is_some() && int_match.is_none() => {
+                        match decode_from_slice(&buf[at..value]) {
+                            Ok((Value::Integer(i), _)) | Ok((Value::FixedInteger(i, _), _)) if Some(i) == int_key => {
+                                int_match = Some((value, value_end));
+                            },
+                            _ => {}
+                        }
+                    },
+                    _ => {}
+                }
+                at = value_end;
+            }
+            match int_match {
+                Some((start, end)) => Ok(Target::Existing(start, end)),
+                None => Ok(Target::New { header: pos, n, len, is_map: true, at })
+            }
+        },
+        _ => Err(path_error(pointer, "not a container"))
+    }
+}
+
+/// Replaces the value at `pointer` inside the encoded document `buf` with
+/// `value`, rewriting only its bytes rather than re-encoding the document.
+/// A missing map key is added, and the token `-` appends to an array; the
+/// only other bytes touched are then the length header of the container
+/// that grew. Intermediate containers must already exist. Strings are not
+/// checked on the way, so `buf` should come from a trusted encoder.
+pub fn splice_at(buf: &mut Vec<u8>, pointer: &str, value: &Value) -> Result<()> {
+    let tokens = tokens(pointer).ok_or_else(|| path_error(pointer, "must start with /"))?;
+    let mut target = Target::Existing(0, skip_len(buf)?);
+    for token in &tokens {
+        let pos = match target {
+            Target::Existing(start, _) => start,
+            Target::New { .. } => return Err(path_error(pointer, "missing parent"))
+        };
+        target = member(buf, pos, token, pointer)?;
+    }
+    let encoded = encode_to_vec(value)?;
+    match target {
+        Target::Existing(start, end) => {
+            buf.splice(start..end, encoded);
+        },
+        Target::New { header, n, len, is_map, at } => {
+            let mut grown = Vec::with_capacity(5);
+            let mut member = Vec::with_capacity(encoded.len() + 8);
+            if is_map {
+                write_map_header(&mut grown, len + 1)?;
+                let key = tokens.last().map(String::as_str).unwrap_or_default();
+                member.extend(encode_to_vec(&Value::from(key))?);
+            } else {
+                write_array_header(&mut grown, len + 1)?;
+            }
+            member.extend(encoded);
+            buf.splice(at..at, member);
+            buf.splice(header..header + n, grown);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::enc;
+    use crate::Error;
+
+    fn doc() -> Vec<u8> {
+        let v = msgpack!({"user": {"id": 1, "tags": ["a", "b"]}, "items": [1, 2, 3], 7: "seven"});
+        enc(&v)
+    }
+
+    fn decode(buf: &[u8]) -> Option<Value> {
+        decode_from_slice(buf).ok().filter(|(_, len)| *len == buf.len()).map(|(v, _)| v)
+    }
+
+    #[test]
+    fn replaces_nested_values() {
+        let mut buf = doc();
+        assert!(splice_at(&mut buf, "/user/id", &msgpack!("a much longer replacement")).is_ok());
+        assert!(splice_at(&mut buf, "/items/1", &msgpack!({"nested": [true]})).is_ok());
+        assert!(splice_at(&mut buf, "/7", &msgpack!(null)).is_ok());
+        let expected = msgpack!({
+            "user": {"id": "a much longer replacement", "tags": ["a", "b"]},
+            "items": [1, {"nested": [true]}, 3],
+            7: null
+        });
+        assert_eq!(decode(&buf), Some(expected));
+        assert!(splice_at(&mut buf, "", &msgpack!(5)).is_ok());
+        assert_eq!(buf, [0x05]);
+    }
+
+    #[test]
+    fn grows_the_parent_header() {
+        let mut buf = doc();
+        assert!(splice_at(&mut buf, "/user/name", &msgpack!("ann")).is_ok());
+        assert!(splice_at(&mut buf, "/user/tags/-", &msgpack!("c")).is_ok());
+        assert!(splice_at(&mut buf, "/user/tags/3", &msgpack!("d")).is_ok());
+        let expected = msgpack!({"user": {"id": 1, "tags": ["a", "b", "c", "d"], "name": "ann"}, "items": [1, 2, 3], 7: "seven"});
+        assert_eq!(decode(&buf), Some(expected));
+
+        // Going from 15 to 16 members switches to a wider header.
+        let mut buf = enc(&Value::Array(vec![Value::Null; 15]));
+        assert!(splice_at(&mut buf, "/-", &msgpack!(1)).is_ok());
+        assert_eq!(&buf[..3], [0xdc, 0x00, 0x10]);
+        assert_eq!(decode(&buf).and_then(|v| v.pointer("/15").cloned()), Some(msgpack!(1)));
+    }
+
+    #[test]
+    fn bad_paths_leave_the_buffer_alone() {
+        let mut buf = doc();
+        let original = buf.clone();
+        for pointer in ["user", "/items/4", "/items/x", "/user/id/0", "/missing/id"] {
+            assert!(matches!(splice_at(&mut buf, pointer, &msgpack!(0)), Err(Error::InvalidPath(_))), "{}", pointer);
+        }
+        assert_eq!(buf, original);
+        let mut truncated = original[..original.len() - 1].to_vec();
+        assert!(splice_at(&mut truncated, "/user/id", &msgpack!(0)).is_err());
+    }
+}
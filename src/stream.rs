@@ -0,0 +1,173 @@
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+
+use crate::{encode_value_in, DecodeOptions, Decoder, EncodeOptions, Result, Value};
+
+/// Output `encode_all` gathers before handing it to the writer.
+const BATCH_LEN: usize = 64 * 1024;
+
+/// Writes `values` back-to-back to `w`, the counterpart of
+/// `StreamDecoder`. They are encoded into one shared buffer that goes out
+/// in large writes, and `w` is flushed once at the end. On error, values
+/// before the failing one may already have been written.
+pub fn encode_all<'a, W, I>(w: &mut W, values: I) -> Result<()>
+where
+    W: Write + ?Sized,
+    I: IntoIterator<Item = &'a Value>
+{
+    encode_all_with(w, values, &EncodeOptions::default())
+}
+
+pub fn encode_all_with<'a, W, I>(w: &mut W, values: I, opts: &EncodeOptions) -> Result<()>
+where
+    W: Write + ?Sized,
+    I: IntoIterator<Item = &'a Value>
+{
+    let mut buf = Vec::new();
+    let mut stack = Vec::new();
+    for value in values {
+        encode_value_in(&mut buf, value, opts, &mut stack)?;
+        if buf.len() >= BATCH_LEN {
+            w.write_all(&buf)?;
+            buf.clear();
+        }
+    }
+    w.write_all(&buf)?;
+    w.flush()?;
+    Ok(())
+}
+
+/// Decodes back-to-back values from a reader, as written to log files and
+/// by transports that frame nothing but the values themselves. The stream
+/// may end cleanly only between values; ending inside one yields
+/// `Error::UnexpectedEof`. Iteration stops after the first error, since
+/// where the next value starts is no longer known.
+///
+/// Buffers are reused across values as by `Decoder`; hand values back
+/// through `reclaim` once done with them.
+#[derive(Debug)]
+pub struct StreamDecoder<R> {
+    inner: BufReader<R>,
+    decoder: Decoder,
+    /// Bytes consumed so far.
+    offset: u64,
+    failed: bool
+}
+
+impl<R: Read> StreamDecoder<R> {
+    pub fn new(r: R) -> StreamDecoder<R> {
+        StreamDecoder::with_options(r, DecodeOptions::default())
+    }
+
+    pub fn with_options(r: R, opts: DecodeOptions) -> StreamDecoder<R> {
+        StreamDecoder { inner: BufReader::new(r), decoder: Decoder::with_options(opts), offset: 0, failed: false }
+    }
+
+    /// Bytes consumed from the stream so far, which after a value is where
+    /// the next one starts.
+    pub fn position(&self) -> u64 {
+        self.offset
+    }
+
+    /// See `Decoder::reclaim`.
+    pub fn reclaim(&mut self, value: Value) {
+        self.decoder.reclaim(value);
+    }
+
+    /// Gives back the reader along with whatever was read ahead of the last
+    /// value returned.
+    pub fn into_inner(self) -> BufReader<R> {
+        self.inner
+    }
+
+    /// Whether the stream ended before another value began.
+    fn at_end(&mut self) -> Result<bool> {
+        loop {
+            match self.inner.fill_buf() {
+                Ok(buf) => return Ok(buf.is_empty()),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {},
+                Err(e) => return Err(e.into())
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for StreamDecoder<R> {
+    type Item = Result<Value>;
+
+    fn next(&mut self) -> Option<Result<Value>> {
+        if self.failed {
+            return None;
+        }
+        let result = match self.at_end() {
+            Ok(true) => return None,
+            Ok(false) => self.decoder.decode_buf_at(&mut self.inner, self.offset),
+            Err(e) => Err(e)
+        };
+        match result {
+            Ok((v, offset)) => {
+                self.offset = offset;
+                Some(Ok(v))
+            },
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{enc, ok};
+    use crate::Error;
+
+    fn stream(values: &[Value]) -> Vec<u8> {
+        values.iter().flat_map(|v| enc(v)).collect()
+    }
+
+    #[test]
+    fn yields_each_value_in_turn() {
+        let values: Vec<Value> = (0..5000).map(|i| msgpack!({"seq": i, "msg": "x".repeat(i % 50)})).collect();
+        let buf = stream(&values);
+        let mut stream = StreamDecoder::new(&buf[..]);
+        let mut decoded = Vec::new();
+        for v in &mut stream {
+            decoded.push(ok(v));
+        }
+        assert_eq!(decoded, values);
+        assert_eq!(stream.position(), buf.len() as u64);
+        assert!(StreamDecoder::new(&[][..]).next().is_none());
+    }
+
+    #[test]
+    fn encodes_values_back_to_back() {
+        let values: Vec<Value> = (0..5000).map(|i| msgpack!([i, "y".repeat(i % 40)])).collect();
+        let mut out = Vec::new();
+        assert!(encode_all(&mut out, &values).is_ok());
+        assert_eq!(out, stream(&values));
+        let decoded: Result<Vec<Value>> = StreamDecoder::new(&out[..]).collect();
+        assert_eq!(decoded.ok(), Some(values));
+
+        let opts = EncodeOptions { max_depth: 1, ..EncodeOptions::default() };
+        let mut out = Vec::new();
+        let values = [msgpack!([1]), msgpack!([[2]])];
+        assert!(matches!(encode_all_with(&mut out, &values, &opts), Err(Error::DepthLimitExceeded)));
+    }
+
+    #[test]
+    fn stops_at_the_first_error() {
+        let mut buf = stream(&[msgpack!([1, 2]), msgpack!("abc")]);
+        buf.truncate(buf.len() - 1);
+        let mut stream = StreamDecoder::new(&buf[..]);
+        assert_eq!(stream.next().and_then(Result::ok), Some(msgpack!([1, 2])));
+        match stream.next() {
+            Some(Err(Error::Decode { offset, error, .. })) => {
+                assert_eq!(offset, buf.len() as u64);
+                assert!(matches!(*error, Error::UnexpectedEof { .. }));
+            },
+            other => panic!("expected an error, got {:?}", other)
+        }
+        assert!(stream.next().is_none());
+    }
+}
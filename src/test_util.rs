@@ -0,0 +1,59 @@
+//! Helpers for the unit tests, failing the test on error.
+
+use crate::{decode_from_slice, encode_to_vec, encode_with, EncodeOptions, Value};
+
+/// The value of `result`, failing the test with its error otherwise.
+#[track_caller]
+pub(crate) fn ok<T, E: std::fmt::Display>(result: std::result::Result<T, E>) -> T {
+    match result {
+        Ok(v) => v,
+        Err(e) => panic!("{}", e)
+    }
+}
+
+#[track_caller]
+pub(crate) fn enc(value: &Value) -> Vec<u8> {
+    match encode_to_vec(value) {
+        Ok(buf) => buf,
+        Err(e) => panic!("encode failed: {}", e)
+    }
+}
+
+#[track_caller]
+pub(crate) fn enc_with(value: &Value, opts: &EncodeOptions) -> Vec<u8> {
+    let mut buf = Vec::new();
+    match encode_with(&mut buf, value, opts) {
+        Ok(()) => buf,
+        Err(e) => panic!("encode failed: {}", e)
+    }
+}
+
+/// Decodes a value that fills all of `buf`.
+#[track_caller]
+pub(crate) fn dec(buf: &[u8]) -> Value {
+    match decode_from_slice(buf) {
+        Ok((v, len)) => {
+            assert_eq!(len, buf.len());
+            v
+        },
+        Err(e) => panic!("decode failed: {}", e)
+    }
+}
+
+#[cfg(feature = "serde")]
+#[track_caller]
+pub(crate) fn ser<T: serde::Serialize + ?Sized>(value: &T) -> Vec<u8> {
+    match crate::to_vec(value) {
+        Ok(buf) => buf,
+        Err(e) => panic!("serialize failed: {}", e)
+    }
+}
+
+#[cfg(feature = "serde")]
+#[track_caller]
+pub(crate) fn de<'a, T: serde::Deserialize<'a>>(buf: &'a [u8]) -> T {
+    match crate::from_slice(buf) {
+        Ok(v) => v,
+        Err(e) => panic!("deserialize failed: {}", e)
+    }
+}
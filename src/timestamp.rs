@@ -0,0 +1,260 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{Error, Result, Value};
+
+/// The extension type code reserved by the MessagePack spec for timestamps.
+pub const TIMESTAMP_EXT: i8 = -1;
+
+/// A point in time as carried by the timestamp extension: seconds since
+/// the Unix epoch plus a nanosecond adjustment in `0..1_000_000_000`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Timestamp {
+    seconds: i64,
+    /// Always below `NANOS_PER_SEC`, which every format relies on.
+    nanoseconds: u32
+}
+
+const NANOS_PER_SEC: u32 = 1_000_000_000;
+
+impl Timestamp {
+    /// Whole seconds in `nanoseconds` are carried into `seconds`. Past the
+    /// last representable instant this saturates to it.
+    pub fn new(seconds: i64, nanoseconds: u32) -> Timestamp {
+        let carry = (nanoseconds / NANOS_PER_SEC) as i64;
+        match seconds.checked_add(carry) {
+            Some(seconds) => Timestamp { seconds, nanoseconds: nanoseconds % NANOS_PER_SEC },
+            None => Timestamp { seconds: i64::MAX, nanoseconds: NANOS_PER_SEC - 1 }
+        }
+    }
+
+    pub fn seconds(&self) -> i64 {
+        self.seconds
+    }
+
+    pub fn nanoseconds(&self) -> u32 {
+        self.nanoseconds
+    }
+
+    /// Parses the payload of a timestamp 32, 64 or 96 extension.
+    pub fn from_ext_data(data: &[u8]) -> Result<Timestamp> {
+        let (seconds, nanoseconds) = match data.len() {
+            4 => {
+                let mut buf = [0u8; 4];
+                buf.copy_from_slice(data);
+                (u32::from_be_bytes(buf) as i64, 0)
+            },
+            8 => {
+                let mut buf = [0u8; 8];
+                buf.copy_from_slice(data);
+                let v = u64::from_be_bytes(buf);
+                ((v & 0x3_ffff_ffff) as i64, (v >> 34) as u32)
+            },
+            12 => {
+                let mut nsec = [0u8; 4];
+                let mut sec = [0u8; 8];
+                nsec.copy_from_slice(&data[..4]);
+                sec.copy_from_slice(&data[4..]);
+                (i64::from_be_bytes(sec), u32::from_be_bytes(nsec))
+            },
+            _ => return Err(Error::InvalidTimestamp)
+        };
+        if nanoseconds >= NANOS_PER_SEC {
+            return Err(Error::InvalidTimestamp);
+        }
+        Ok(Timestamp { seconds, nanoseconds })
+    }
+
+    /// Encodes the extension payload, using the smallest of the three
+    /// timestamp formats that can represent this value.
+    pub fn to_ext_data(&self) -> Vec<u8> {
+        if self.seconds >> 34 == 0 {
+            let v = ((self.nanoseconds as u64) << 34) | self.seconds as u64;
+            if v >> 32 == 0 {
+                (v as u32).to_be_bytes().to_vec()
+            } else {
+                v.to_be_bytes().to_vec()
+            }
+        } else {
+            let mut data = Vec::with_capacity(12);
+            data.extend_from_slice(&self.nanoseconds.to_be_bytes());
+            data.extend_from_slice(&self.seconds.to_be_bytes());
+            data
+        }
+    }
+
+    /// Converts to a `SystemTime`, or `None` if the platform can't represent it.
+    pub fn to_system_time(&self) -> Option<SystemTime> {
+        let nanos = Duration::from_nanos(self.nanoseconds as u64);
+        if self.seconds >= 0 {
+            UNIX_EPOCH.checked_add(Duration::from_secs(self.seconds as u64))?.checked_add(nanos)
+        } else {
+            UNIX_EPOCH.checked_sub(Duration::from_secs(self.seconds.unsigned_abs()))?.checked_add(nanos)
+        }
+    }
+}
+
+impl From<SystemTime> for Timestamp {
+    fn from(t: SystemTime) -> Timestamp {
+        match t.duration_since(UNIX_EPOCH) {
+            Ok(d) => Timestamp::new(d.as_secs() as i64, d.subsec_nanos()),
+            Err(e) => {
+                let d = e.duration();
+                let seconds = -(d.as_secs() as i64);
+                if d.subsec_nanos() == 0 {
+                    Timestamp::new(seconds, 0)
+                } else {
+                    Timestamp::new(seconds - 1, NANOS_PER_SEC - d.subsec_nanos())
+                }
+            }
+        }
+    }
+}
+
+impl From<Timestamp> for Value {
+    fn from(ts: Timestamp) -> Value {
+        Value::Ext(TIMESTAMP_EXT, ts.to_ext_data())
+    }
+}
+
+impl From<SystemTime> for Value {
+    fn from(t: SystemTime) -> Value {
+        Timestamp::from(t).into()
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl From<chrono::DateTime<chrono::Utc>> for Timestamp {
+    fn from(t: chrono::DateTime<chrono::Utc>) -> Timestamp {
+        Timestamp::new(t.timestamp(), t.timestamp_subsec_nanos())
+    }
+}
+
+/// Fails with `Error::InvalidTimestamp` outside the range chrono supports.
+#[cfg(feature = "chrono")]
+impl std::convert::TryFrom<Timestamp> for chrono::DateTime<chrono::Utc> {
+    type Error = Error;
+
+    fn try_from(ts: Timestamp) -> Result<chrono::DateTime<chrono::Utc>> {
+        chrono::DateTime::from_timestamp(ts.seconds, ts.nanoseconds).ok_or(Error::InvalidTimestamp)
+    }
+}
+
+/// Written as the timestamp extension, through `EXT_STRUCT_NAME`.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Timestamp {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> std::result::Result<S::Ok, S::Error> {
+        Value::from(*self).serialize(s)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Timestamp {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> std::result::Result<Timestamp, D::Error> {
+        match Value::deserialize(d)? {
+            Value::Ext(TIMESTAMP_EXT, data) => Timestamp::from_ext_data(&data).map_err(serde::de::Error::custom),
+            v => Err(serde::de::Error::invalid_type(serde::de::Unexpected::Other(v.kind()), &"timestamp"))
+        }
+    }
+}
+
+impl Value {
+    /// Returns the timestamp if this is a well-formed timestamp extension.
+    pub fn as_timestamp(&self) -> Option<Timestamp> {
+        match self {
+            Value::Ext(TIMESTAMP_EXT, data) => Timestamp::from_ext_data(data).ok(),
+            _ => None
+        }
+    }
+
+    /// Returns the timestamp extension as a `SystemTime`.
+    pub fn as_system_time(&self) -> Option<SystemTime> {
+        self.as_timestamp().and_then(|ts| ts.to_system_time())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_smallest_format() {
+        assert_eq!(Timestamp::new(1, 0).to_ext_data().len(), 4);
+        assert_eq!(Timestamp::new(u32::MAX as i64, 0).to_ext_data().len(), 4);
+        assert_eq!(Timestamp::new(1, 1).to_ext_data().len(), 8);
+        assert_eq!(Timestamp::new(1 << 33, 0).to_ext_data().len(), 8);
+        assert_eq!(Timestamp::new(1 << 34, 0).to_ext_data().len(), 12);
+        assert_eq!(Timestamp::new(-1, 500).to_ext_data().len(), 12);
+    }
+
+    #[test]
+    fn round_trips_ext_data() {
+        let cases = [
+            Timestamp::new(0, 0),
+            Timestamp::new(1_600_000_000, 0),
+            Timestamp::new(1_600_000_000, 999_999_999),
+            Timestamp::new((1 << 34) - 1, 123),
+            Timestamp::new(1 << 40, 1),
+            Timestamp::new(-1, 999_999_999),
+            Timestamp::new(i64::MIN, 0)
+        ];
+        for ts in cases.iter() {
+            assert_eq!(Timestamp::from_ext_data(&ts.to_ext_data()).ok(), Some(*ts));
+        }
+    }
+
+    #[test]
+    fn carries_excess_nanoseconds() {
+        let ts = Timestamp::new(5, 2_500_000_000);
+        assert_eq!((ts.seconds(), ts.nanoseconds()), (7, 500_000_000));
+        assert_eq!(Timestamp::new(5, u32::MAX), Timestamp::new(9, 294_967_295));
+        assert_eq!(Timestamp::new(i64::MAX, NANOS_PER_SEC), Timestamp::new(i64::MAX, NANOS_PER_SEC - 1));
+        // What is written is always read back the same.
+        for ts in [ts, Timestamp::new(1 << 33, u32::MAX), Timestamp::new(-3, 1_000_000_001)] {
+            assert_eq!(Value::from(ts).as_timestamp(), Some(ts));
+        }
+    }
+
+    #[test]
+    fn rejects_bad_payloads() {
+        assert!(matches!(Timestamp::from_ext_data(&[0; 5]), Err(Error::InvalidTimestamp)));
+        let mut data = Vec::new();
+        data.extend_from_slice(&1_000_000_000u32.to_be_bytes());
+        data.extend_from_slice(&0i64.to_be_bytes());
+        assert!(Timestamp::from_ext_data(&data).is_err());
+    }
+
+    #[test]
+    fn system_time_conversion() {
+        let t = UNIX_EPOCH + Duration::new(1_600_000_000, 42);
+        assert_eq!(Value::from(t).as_system_time(), Some(t));
+
+        let before = UNIX_EPOCH - Duration::new(10, 250);
+        assert_eq!(Timestamp::from(before), Timestamp::new(-11, 999_999_750));
+        assert_eq!(Value::from(before).as_system_time(), Some(before));
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn chrono_conversion() {
+        use std::convert::TryFrom;
+
+        use crate::test_util::ok;
+
+        let t = ok(chrono::DateTime::from_timestamp(-5, 7).ok_or("out of range"));
+        assert_eq!(Timestamp::from(t), Timestamp::new(-5, 7));
+        assert_eq!(chrono::DateTime::try_from(Timestamp::new(-5, 7)).ok(), Some(t));
+        assert!(chrono::DateTime::<chrono::Utc>::try_from(Timestamp::new(i64::MAX, 0)).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_as_ext() {
+        use crate::test_util::{enc, ser};
+
+        let ts = Timestamp::new(1_600_000_000, 5);
+        let buf = ser(&ts);
+        assert_eq!(buf, enc(&Value::from(ts)));
+        assert_eq!(crate::from_slice::<Timestamp>(&buf).ok(), Some(ts));
+        assert!(crate::from_slice::<Timestamp>(&[0x01]).is_err());
+    }
+}
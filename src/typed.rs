@@ -0,0 +1,637 @@
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::hash::{BuildHasher, Hash};
+use std::io::Write;
+
+use crate::{
+    decode_from_slice, encode_float, encode_int, encode_str, encode_to, write_array_header,
+    write_map_header, EncodeOptions, Error, Result, Value
+};
+
+/// Typed conversion into MessagePack without serde; derive it with
+/// `#[derive(EncodeValue)]` (feature `derive`). Structs with named fields
+/// become maps keyed by field name, tuple structs arrays, and enums are
+/// written as `"Variant"` or `{"Variant": content}`.
+pub trait EncodeValue {
+    fn to_value(&self) -> Value;
+
+    /// Writes the encoding directly, without building a `Value` first.
+    fn encode<W: Write + ?Sized>(&self, w: &mut W) -> Result<()> {
+        encode_to(w, &self.to_value())
+    }
+
+    fn encode_to_vec(&self) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        self.encode(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+/// Typed conversion out of a `Value`, the counterpart of `EncodeValue`.
+pub trait DecodeValue: Sized {
+    fn from_value(value: Value) -> Result<Self>;
+
+    /// Decodes the value at the front of `buf`.
+    fn decode(buf: &[u8]) -> Result<Self> {
+        Self::from_value(decode_from_slice(buf)?.0)
+    }
+}
+
+impl EncodeValue for Value {
+    fn to_value(&self) -> Value {
+        self.clone()
+    }
+}
+
+impl DecodeValue for Value {
+    fn from_value(value: Value) -> Result<Value> {
+        Ok(value)
+    }
+}
+
+impl EncodeValue for bool {
+    fn to_value(&self) -> Value {
+        Value::Boolean(*self)
+    }
+
+    fn encode<W: Write + ?Sized>(&self, w: &mut W) -> Result<()> {
+        w.write_all(&[if *self { 0xc3 } else { 0xc2 }])?;
+        Ok(())
+    }
+}
+
+impl DecodeValue for bool {
+    fn from_value(value: Value) -> Result<bool> {
+        bool::try_from(value)
+    }
+}
+
+macro_rules! typed_integer {
+    ($($t:ty),*) => {
+        $(
+            impl EncodeValue for $t {
+                fn to_value(&self) -> Value {
+                    Value::Integer(*self as i128)
+                }
+
+                fn encode<W: Write + ?Sized>(&self, w: &mut W) -> Result<()> {
+                    encode_int(w, *self as i128, &EncodeOptions::default())
+                }
+            }
+
+            impl DecodeValue for $t {
+                fn from_value(value: Value) -> Result<$t> {
+                    <$t>::try_from(value)
+                }
+            }
+        )*
+    };
+}
+
+typed_integer!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl EncodeValue for f32 {
+    fn to_value(&self) -> Value {
+        Value::Float(*self as f64)
+    }
+}
+
+impl DecodeValue for f32 {
+    fn from_value(value: Value) -> Result<f32> {
+        f32::try_from(value)
+    }
+}
+
+impl EncodeValue for f64 {
+    fn to_value(&self) -> Value {
+        Value::Float(*self)
+    }
+
+    fn encode<W: Write + ?Sized>(&self, w: &mut W) -> Result<()> {
+        encode_float(w, *self, &EncodeOptions::default())
+    }
+}
+
+impl DecodeValue for f64 {
+    fn from_value(value: Value) -> Result<f64> {
+        f64::try_from(value)
+    }
+}
+
+impl EncodeValue for str {
+    fn to_value(&self) -> Value {
+        Value::String(self.to_string())
+    }
+
+    fn encode<W: Write + ?Sized>(&self, w: &mut W) -> Result<()> {
+        encode_str(w, self, &EncodeOptions::default())
+    }
+}
+
+impl EncodeValue for String {
+    fn to_value(&self) -> Value {
+        Value::String(self.clone())
+    }
+
+    fn encode<W: Write + ?Sized>(&self, w: &mut W) -> Result<()> {
+        encode_str(w, self, &EncodeOptions::default())
+    }
+}
+
+impl DecodeValue for String {
+    fn from_value(value: Value) -> Result<String> {
+        String::try_from(value)
+    }
+}
+
+impl<T: EncodeValue + ?Sized> EncodeValue for &T {
+    fn to_value(&self) -> Value {
+        (**self).to_value()
+    }
+
+    fn encode<W: Write + ?Sized>(&self, w: &mut W) -> Result<()> {
+        (**self).encode(w)
+    }
+}
+
+impl<T: EncodeValue + ?Sized> EncodeValue for Box<T> {
+    fn to_value(&self) -> Value {
+        (**self).to_value()
+    }
+
+    fn encode<W: Write + ?Sized>(&self, w: &mut W) -> Result<()> {
+        (**self).encode(w)
+    }
+}
+
+impl<T: DecodeValue> DecodeValue for Box<T> {
+    fn from_value(value: Value) -> Result<Box<T>> {
+        T::from_value(value).map(Box::new)
+    }
+}
+
+/// `None` is nil, so a missing field also decodes as `None`.
+impl<T: EncodeValue> EncodeValue for Option<T> {
+    fn to_value(&self) -> Value {
+        match self {
+            Some(v) => v.to_value(),
+            None => Value::Null
+        }
+    }
+
+    fn encode<W: Write + ?Sized>(&self, w: &mut W) -> Result<()> {
+        match self {
+            Some(v) => v.encode(w),
+            None => {
+                w.write_all(&[0xc0])?;
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<T: DecodeValue> DecodeValue for Option<T> {
+    fn from_value(value: Value) -> Result<Option<T>> {
+        match value {
+            Value::Null => Ok(None),
+            v => T::from_value(v).map(Some)
+        }
+    }
+}
+
+/// Written as an array, even for `u8`; wrap bytes in `Value::Bytes` or use
+/// `Vec<u8>`'s `From` conversion to write bin.
+impl<T: EncodeValue> EncodeValue for [T] {
+    fn to_value(&self) -> Value {
+        Value::Array(self.iter().map(EncodeValue::to_value).collect())
+    }
+
+    fn encode<W: Write + ?Sized>(&self, w: &mut W) -> Result<()> {
+        write_array_header(w, self.len())?;
+        for v in self {
+            v.encode(w)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: EncodeValue> EncodeValue for Vec<T> {
+    fn to_value(&self) -> Value {
+        self.as_slice().to_value()
+    }
+
+    fn encode<W: Write + ?Sized>(&self, w: &mut W) -> Result<()> {
+        self.as_slice().encode(w)
+    }
+}
+
+/// Accepts bin as well as arrays, so `Vec<u8>` reads either.
+impl<T: DecodeValue> DecodeValue for Vec<T> {
+    fn from_value(value: Value) -> Result<Vec<T>> {
+        match value {
+            Value::Array(a) => a.into_iter().map(T::from_value).collect(),
+            Value::Bytes(b) => b.into_iter().map(|b| T::from_value(Value::Integer(b as i128))).collect(),
+            v => Err(Error::TypeMismatch { expected: "array", found: v.kind() })
+        }
+    }
+}
+
+fn encode_entries<'a, W: Write + ?Sized, K: EncodeValue + 'a, V: EncodeValue + 'a>(
+    w: &mut W,
+    len: usize,
+    entries: impl Iterator<Item = (&'a K, &'a V)>
+) -> Result<()> {
+    write_map_header(w, len)?;
+    for (k, v) in entries {
+        k.encode(w)?;
+        v.encode(w)?;
+    }
+    Ok(())
+}
+
+fn decode_entries<K: DecodeValue, V: DecodeValue, M: Default + Extend<(K, V)>>(value: Value) -> Result<M> {
+    match value {
+        Value::Map(m) => {
+            let mut out = M::default();
+            for (k, v) in m {
+                out.extend(Some((K::from_value(k)?, V::from_value(v)?)));
+            }
+            Ok(out)
+        },
+        v => Err(Error::TypeMismatch { expected: "map", found: v.kind() })
+    }
+}
+
+impl<K: EncodeValue, V: EncodeValue, S> EncodeValue for HashMap<K, V, S> {
+    fn to_value(&self) -> Value {
+        Value::Map(self.iter().map(|(k, v)| (k.to_value(), v.to_value())).collect())
+    }
+
+    fn encode<W: Write + ?Sized>(&self, w: &mut W) -> Result<()> {
+        encode_entries(w, self.len(), self.iter())
+    }
+}
+
+impl<K: DecodeValue + Eq + Hash, V: DecodeValue, S: BuildHasher + Default> DecodeValue for HashMap<K, V, S> {
+    fn from_value(value: Value) -> Result<HashMap<K, V, S>> {
+        decode_entries(value)
+    }
+}
+
+impl<K: EncodeValue, V: EncodeValue> EncodeValue for BTreeMap<K, V> {
+    fn to_value(&self) -> Value {
+        Value::Map(self.iter().map(|(k, v)| (k.to_value(), v.to_value())).collect())
+    }
+
+    fn encode<W: Write + ?Sized>(&self, w: &mut W) -> Result<()> {
+        encode_entries(w, self.len(), self.iter())
+    }
+}
+
+impl<K: DecodeValue + Ord, V: DecodeValue> DecodeValue for BTreeMap<K, V> {
+    fn from_value(value: Value) -> Result<BTreeMap<K, V>> {
+        decode_entries(value)
+    }
+}
+
+/// Used by the code `#[derive(EncodeValue, DecodeValue)]` generates.
+#[doc(hidden)]
+pub mod __private {
+    use std::io::Write;
+
+    use super::{DecodeValue, EncodeValue};
+    use crate::{map_remove, Error, Map, Result, Value};
+
+    pub fn write_array_header<W: Write + ?Sized>(w: &mut W, len: usize) -> Result<()> {
+        crate::write_array_header(w, len)
+    }
+
+    pub fn write_map_header<W: Write + ?Sized>(w: &mut W, len: usize) -> Result<()> {
+        crate::write_map_header(w, len)
+    }
+
+    pub fn into_map(value: Value) -> Result<Map> {
+        match value {
+            Value::Map(m) => Ok(m),
+            v => Err(Error::TypeMismatch { expected: "map", found: v.kind() })
+        }
+    }
+
+    /// The elements of an array of exactly `len` values.
+    pub fn into_array(value: Value, len: usize) -> Result<std::vec::IntoIter<Value>> {
+        match value {
+            Value::Array(a) if a.len() == len => Ok(a.into_iter()),
+            Value::Array(_) => Err(Error::TypeMismatch { expected: "array of the declared length", found: "array" }),
+            v => Err(Error::TypeMismatch { expected: "array", found: v.kind() })
+        }
+    }
+
+    pub fn element<T: DecodeValue>(items: &mut std::vec::IntoIter<Value>) -> Result<T> {
+        T::from_value(items.next().unwrap_or_default())
+    }
+
+    /// Takes a named field out of `map`; missing fields decode from nil, so
+    /// `Option` fields may be absent.
+    pub fn field<T: DecodeValue, K: EncodeValue + ?Sized>(map: &mut Map, key: &K, name: &'static str) -> Result<T> {
+        match map_remove(map, &key.to_value()) {
+            Some(v) => T::from_value(v),
+            None => T::from_value(Value::Null).map_err(|_| Error::MissingField(name))
+        }
+    }
+
+    pub fn field_or_default<T: DecodeValue + Default, K: EncodeValue + ?Sized>(map: &mut Map, key: &K) -> Result<T> {
+        match map_remove(map, &key.to_value()) {
+            Some(v) => T::from_value(v),
+            None => Ok(T::default())
+        }
+    }
+
+    /// The entries of an `unknown` field whose keys no known field uses.
+    pub fn unknown_entries<'a>(map: &'a Map, known: &[Value]) -> Vec<(&'a Value, &'a Value)> {
+        map.iter().filter(|(k, _)| !known.contains(k)).collect()
+    }
+
+    /// Splits an enum into its variant name and content: a bare string is
+    /// a unit variant, a single-entry map any other.
+    pub fn variant(value: Value) -> Result<(String, Option<Value>)> {
+        match value {
+            Value::String(s) => Ok((s, None)),
+            Value::SharedString(s) => Ok((s.to_string(), None)),
+            Value::InlineString(s) => Ok((s.to_string(), None)),
+            Value::Map(m) if m.len() == 1 => match m.into_iter().next() {
+                Some((Value::String(k), v)) => Ok((k, Some(v))),
+                Some((Value::SharedString(k), v)) => Ok((k.to_string(), Some(v))),
+                Some((Value::InlineString(k), v)) => Ok((k.to_string(), Some(v))),
+                _ => Err(Error::TypeMismatch { expected: "variant name", found: "non-string key" })
+            },
+            v => Err(Error::TypeMismatch { expected: "enum", found: v.kind() })
+        }
+    }
+
+    /// Splits an adjacently tagged enum, `{tag: "Variant", content: ..}`.
+    pub fn adjacent(value: Value, tag: &str, content: &str) -> Result<(String, Option<Value>)> {
+        let mut map = into_map(value)?;
+        let variant = match map_remove(&mut map, &Value::from(tag)) {
+            Some(Value::String(s)) => s,
+            Some(Value::SharedString(s)) => s.to_string(),
+            Some(Value::InlineString(s)) => s.to_string(),
+            Some(v) => return Err(Error::TypeMismatch { expected: "variant name", found: v.kind() }),
+            None => return Err(Error::TypeMismatch { expected: "enum tag", found: "map without it" })
+        };
+        Ok((variant, map_remove(&mut map, &Value::from(content))))
+    }
+
+    pub fn adjacent_entries(tag: &str, variant: &str, content: &str, value: Value) -> Value {
+        let mut m = Map::default();
+        m.insert(Value::from(tag), Value::from(variant));
+        m.insert(Value::from(content), value);
+        Value::Map(m)
+    }
+
+    pub fn no_variant_matched(name: &str, value: &Value) -> Error {
+        Error::Custom(format!("{} matches no variant of untagged enum {}", value.kind(), name))
+    }
+
+    pub fn content(content: Option<Value>) -> Result<Value> {
+        content.ok_or(Error::TypeMismatch { expected: "variant content", found: "unit variant" })
+    }
+
+    pub fn single_entry(key: &str, value: Value) -> Value {
+        let mut m = Map::default();
+        m.insert(Value::from(key), value);
+        Value::Map(m)
+    }
+
+    pub fn unknown_variant(name: String) -> Error {
+        Error::UnknownVariant(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{enc, ok};
+    use crate::Map;
+
+    #[test]
+    fn std_types_round_trip() {
+        let mut m = HashMap::new();
+        m.insert("a".to_string(), vec![Some(1u32), None]);
+        let buf = ok(m.encode_to_vec());
+        assert_eq!(buf, enc(&m.to_value()));
+        assert_eq!(HashMap::<String, Vec<Option<u32>>>::decode(&buf).ok(), Some(m));
+        assert_eq!(Vec::<u8>::from_value(Value::Bytes(vec![1, 2])).ok(), Some(vec![1, 2]));
+        assert!(matches!(u8::from_value(Value::Integer(300)), Err(Error::IntegerOutOfRange)));
+    }
+
+    #[cfg(feature = "derive")]
+    mod derived {
+        use crate::test_util::{dec, ok};
+        use crate::{DecodeValue, EncodeValue, Value};
+
+        #[derive(Debug, PartialEq, EncodeValue, DecodeValue)]
+        struct User {
+            id: u64,
+            name: String,
+            email: Option<String>,
+            roles: Vec<Role>
+        }
+
+        #[derive(Debug, PartialEq, EncodeValue, DecodeValue)]
+        enum Role {
+            Guest,
+            Member(u32),
+            Admin { level: u8 },
+            Pair(i8, i8)
+        }
+
+        #[derive(Debug, PartialEq, EncodeValue, DecodeValue)]
+        struct Wrapper<T>(T);
+
+        #[derive(Debug, PartialEq, EncodeValue, DecodeValue)]
+        struct Marker;
+
+        #[test]
+        fn derived_round_trip() {
+            let user = User {
+                id: 7,
+                name: "ann".into(),
+                email: None,
+                roles: vec![Role::Guest, Role::Member(3), Role::Admin { level: 2 }, Role::Pair(-1, 1)]
+            };
+            let v = user.to_value();
+            let expected = msgpack!({
+                "id": 7,
+                "name": "ann",
+                "email": null,
+                "roles": ["Guest", {"Member": 3}, {"Admin": {"level": 2}}, {"Pair": [-1, 1]}]
+            });
+            assert_eq!(v, expected);
+            let buf = ok(user.encode_to_vec());
+            assert_eq!(dec(&buf), expected);
+            assert_eq!(User::decode(&buf).ok().as_ref(), Some(&user));
+            assert_eq!(Wrapper::<u8>::from_value(Wrapper(5u8).to_value()).ok(), Some(Wrapper(5)));
+            assert_eq!(Marker.to_value(), Value::Null);
+        }
+
+        #[derive(Debug, PartialEq, EncodeValue, DecodeValue)]
+        #[msgpack(rename_all = "camelCase")]
+        struct Session {
+            user_id: u64,
+            #[msgpack(rename = "ttl")]
+            expires_in: u32,
+            #[msgpack(skip)]
+            cached: Option<String>,
+            #[msgpack(default)]
+            retry_count: u8,
+            state: State
+        }
+
+        #[derive(Debug, PartialEq, EncodeValue, DecodeValue)]
+        #[msgpack(rename_all = "SCREAMING_SNAKE_CASE")]
+        enum State {
+            LoggedIn,
+            #[msgpack(rename = "gone")]
+            LoggedOut,
+            TimedOut(u32, #[msgpack(skip)] u8)
+        }
+
+        #[test]
+        fn derive_attributes() {
+            let s = Session {
+                user_id: 1,
+                expires_in: 60,
+                cached: Some("x".into()),
+                retry_count: 2,
+                state: State::LoggedIn
+            };
+            let expected = msgpack!({"userId": 1, "ttl": 60, "retryCount": 2, "state": "LOGGED_IN"});
+            assert_eq!(s.to_value(), expected);
+            let buf = ok(s.encode_to_vec());
+            assert_eq!(dec(&buf), expected);
+            let back = Session::from_value(msgpack!({"userId": 1, "ttl": 60, "state": "gone"}));
+            let want = Session { user_id: 1, expires_in: 60, cached: None, retry_count: 0, state: State::LoggedOut };
+            assert_eq!(back.ok(), Some(want));
+            assert_eq!(State::TimedOut(5, 9).to_value(), msgpack!({"TIMED_OUT": [5]}));
+            assert_eq!(State::from_value(msgpack!({"TIMED_OUT": [5]})).ok(), Some(State::TimedOut(5, 0)));
+        }
+
+        #[derive(Debug, PartialEq, EncodeValue, DecodeValue)]
+        #[msgpack(untagged)]
+        enum Message {
+            Empty,
+            Point { x: i32, y: i32 },
+            Text(String),
+            Pair(u8, u8)
+        }
+
+        #[derive(Debug, PartialEq, EncodeValue, DecodeValue)]
+        #[msgpack(tag = "type", content = "data", rename_all = "lowercase")]
+        enum Event {
+            Ping,
+            Click(u32),
+            Key { code: u8 }
+        }
+
+        #[test]
+        fn untagged_enums() {
+            let cases = [
+                (Message::Empty, msgpack!(null)),
+                (Message::Point { x: 1, y: 2 }, msgpack!({"x": 1, "y": 2})),
+                (Message::Text("hi".into()), msgpack!("hi")),
+                (Message::Pair(3, 4), msgpack!([3, 4]))
+            ];
+            for (message, value) in IntoIterator::into_iter(cases) {
+                assert_eq!(message.to_value(), value);
+                let buf = ok(message.encode_to_vec());
+                assert_eq!(dec(&buf), value);
+                assert_eq!(Message::from_value(value).ok(), Some(message));
+            }
+            assert!(matches!(Message::from_value(msgpack!(1.5)), Err(crate::Error::Custom(_))));
+        }
+
+        #[test]
+        fn adjacently_tagged_enums() {
+            let cases = [
+                (Event::Ping, msgpack!({"type": "ping"})),
+                (Event::Click(7), msgpack!({"type": "click", "data": 7})),
+                (Event::Key { code: 9 }, msgpack!({"type": "key", "data": {"code": 9}}))
+            ];
+            for (event, value) in IntoIterator::into_iter(cases) {
+                assert_eq!(event.to_value(), value);
+                let buf = ok(event.encode_to_vec());
+                assert_eq!(dec(&buf), value);
+                assert_eq!(Event::from_value(value).ok(), Some(event));
+            }
+            assert!(Event::from_value(msgpack!({"data": 1})).is_err());
+        }
+
+        #[derive(Debug, PartialEq, EncodeValue, DecodeValue)]
+        struct ProfileV1 {
+            #[msgpack(tag = 0)]
+            id: u32,
+            #[msgpack(tag = 1)]
+            name: String,
+            #[msgpack(unknown)]
+            rest: crate::Map
+        }
+
+        #[derive(Debug, PartialEq, EncodeValue, DecodeValue)]
+        #[msgpack(default)]
+        struct ProfileV2 {
+            #[msgpack(tag = 0)]
+            id: u32,
+            #[msgpack(tag = 1)]
+            name: String,
+            #[msgpack(tag = 2)]
+            email: String
+        }
+
+        #[test]
+        fn versioned_structs() {
+            let v2 = ProfileV2 { id: 1, name: "ann".into(), email: "a@b".into() };
+            assert_eq!(v2.to_value(), msgpack!({0: 1, 1: "ann", 2: "a@b"}));
+
+            // An old reader keeps the field it doesn't know and writes it back.
+            let buf = ok(v2.encode_to_vec());
+            let v1 = ok(ProfileV1::decode(&buf));
+            assert_eq!(v1.rest.len(), 1);
+            let buf = ok(v1.encode_to_vec());
+            assert_eq!(ProfileV2::decode(&buf).ok().as_ref(), Some(&v2));
+
+            // A new reader fills in what an old writer never had.
+            let v1 = ProfileV1 { id: 2, name: "bo".into(), rest: crate::Map::default() };
+            let buf = ok(v1.encode_to_vec());
+            let expected = ProfileV2 { id: 2, name: "bo".into(), email: String::new() };
+            assert_eq!(ProfileV2::decode(&buf).ok(), Some(expected));
+        }
+
+        #[test]
+        fn known_fields_win_over_unknown_entries() {
+            let mut rest = crate::Map::default();
+            rest.insert(Value::from(0u64), Value::from("stale"));
+            rest.insert(Value::from("note"), Value::from(true));
+            let v1 = ProfileV1 { id: 3, name: "cy".into(), rest };
+            let expected = msgpack!({0: 3, 1: "cy", "note": true});
+            assert_eq!(v1.to_value(), expected);
+            let buf = ok(v1.encode_to_vec());
+            assert_eq!(dec(&buf), expected);
+        }
+
+        #[test]
+        fn derived_errors() {
+            let r = User::from_value(msgpack!({"id": 1, "roles": []}));
+            assert!(matches!(r, Err(crate::Error::MissingField("name"))));
+            let r = Role::from_value(msgpack!("Owner"));
+            assert!(matches!(r, Err(crate::Error::UnknownVariant(ref v)) if v == "Owner"));
+            assert!(Role::from_value(msgpack!({"Pair": [1]})).is_err());
+        }
+    }
+
+    #[test]
+    fn missing_fields() {
+        let mut map = Map::default();
+        assert_eq!(__private::field::<Option<u8>, str>(&mut map, "a", "a").ok(), Some(None));
+        assert!(matches!(__private::field::<u8, str>(&mut map, "a", "a"), Err(Error::MissingField("a"))));
+    }
+}
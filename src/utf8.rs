@@ -0,0 +1,51 @@
+//! UTF-8 checks for decoded strings. With the `simd_utf8` feature valid
+//! input is checked with simdutf8; anything it rejects is checked again by
+//! std so that errors are the usual `Utf8Error`.
+
+use std::str::Utf8Error;
+use std::string::FromUtf8Error;
+
+#[cfg(not(feature = "simd_utf8"))]
+pub(crate) fn from_utf8(bytes: &[u8]) -> Result<&str, Utf8Error> {
+    std::str::from_utf8(bytes)
+}
+
+#[cfg(feature = "simd_utf8")]
+pub(crate) fn from_utf8(bytes: &[u8]) -> Result<&str, Utf8Error> {
+    match simdutf8::basic::from_utf8(bytes) {
+        Ok(s) => Ok(s),
+        Err(_) => std::str::from_utf8(bytes)
+    }
+}
+
+#[cfg(not(feature = "simd_utf8"))]
+pub(crate) fn into_string(bytes: Vec<u8>) -> Result<String, FromUtf8Error> {
+    String::from_utf8(bytes)
+}
+
+#[cfg(feature = "simd_utf8")]
+pub(crate) fn into_string(bytes: Vec<u8>) -> Result<String, FromUtf8Error> {
+    if simdutf8::basic::from_utf8(&bytes).is_ok() {
+        // SAFETY: simdutf8 just checked that the bytes are valid UTF-8.
+        return Ok(unsafe { String::from_utf8_unchecked(bytes) });
+    }
+    String::from_utf8(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn agrees_with_std() {
+        let long = "héllo wörld ✓ ".repeat(20);
+        let mut bad = long.clone().into_bytes();
+        bad[200] = 0xff;
+        for bytes in [&b""[..], b"plain", long.as_bytes(), &bad, b"\xe2\x9c", b"ok\xc0\x80"] {
+            assert_eq!(from_utf8(bytes), std::str::from_utf8(bytes));
+            let ours = into_string(bytes.to_vec()).map_err(|e| e.utf8_error());
+            let std = String::from_utf8(bytes.to_vec()).map_err(|e| e.utf8_error());
+            assert_eq!(ours, std);
+        }
+    }
+}
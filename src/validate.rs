@@ -0,0 +1,458 @@
+use std::convert::TryFrom;
+
+use crate::{decode_from, decode_from_slice, key_segment, DecodeOptions, Error, Expected, Utf8Policy, Value, DEFAULT_MAX_DEPTH};
+
+/// The structural shape of one encoded item, as read from its header.
+pub(crate) enum Header {
+    /// A nil, boolean, integer or float followed by this many payload bytes.
+    Scalar(usize),
+    Str(usize),
+    Bin(usize),
+    /// An ext payload of this many bytes; the type byte is part of the header.
+    Ext(usize),
+    Array(usize),
+    Map(usize),
+    /// The reserved 0xc1 marker.
+    Reserved
+}
+
+fn be_len(buf: &[u8], width: usize) -> std::result::Result<usize, Error> {
+    if buf.len() < width {
+        return Err(Error::UnexpectedEof { needed: width, got: buf.len() });
+    }
+    let mut len: u64 = 0;
+    for b in &buf[..width] {
+        len = (len << 8) | *b as u64;
+    }
+    usize::try_from(len).map_err(|_| Error::LengthOutOfRange)
+}
+
+/// Parses the header of the item starting at `buf[0]`, returning it along
+/// with the number of header bytes (marker, length and ext type).
+pub(crate) fn read_header(buf: &[u8]) -> std::result::Result<(Header, usize), Error> {
+    let marker = match buf.first() {
+        Some(b) => *b,
+        None => return Err(Error::UnexpectedEof { needed: 1, got: 0 })
+    };
+    let rest = &buf[1..];
+    let sized = |width: usize, extra: usize| -> std::result::Result<(usize, usize), Error> {
+        let len = be_len(rest, width)?;
+        if rest.len() < width + extra {
+            return Err(Error::UnexpectedEof { needed: width + extra, got: rest.len() });
+        }
+        Ok((len, 1 + width + extra))
+    };
+    let header = match marker {
+        0x00..=0x7f | 0xc0 | 0xc2 | 0xc3 | 0xe0..=0xff => (Header::Scalar(0), 1),
+        0x80..=0x8f => (Header::Map((marker & 0xf) as usize), 1),
+        0x90..=0x9f => (Header::Array((marker & 0xf) as usize), 1),
+        0xa0..=0xbf => (Header::Str((marker & 0x1f) as usize), 1),
+        0xc1 => (Header::Reserved, 1),
+        0xc4..=0xc6 => {
+            let (len, n) = sized(1 << (marker - 0xc4), 0)?;
+            (Header::Bin(len), n)
+        },
+        0xc7..=0xc9 => {
+            let (len, n) = sized(1 << (marker - 0xc7), 1)?;
+            (Header::Ext(len), n)
+        },
+        0xca => (Header::Scalar(4), 1),
+        0xcb => (Header::Scalar(8), 1),
+        0xcc..=0xcf => (Header::Scalar(1 << (marker - 0xcc)), 1),
+        0xd0..=0xd3 => (Header::Scalar(1 << (marker - 0xd0)), 1),
+        0xd4..=0xd8 => {
+            let (_, n) = sized(0, 1)?;
+            (Header::Ext(1 << (marker - 0xd4)), n)
+        },
+        0xd9..=0xdb => {
+            let (len, n) = sized(1 << (marker - 0xd9), 0)?;
+            (Header::Str(len), n)
+        },
+        0xdc | 0xdd => {
+            let (len, n) = sized(2 << (marker - 0xdc), 0)?;
+            (Header::Array(len), n)
+        },
+        0xde | 0xdf => {
+            let (len, n) = sized(2 << (marker - 0xde), 0)?;
+            (Header::Map(len), n)
+        }
+    };
+    Ok(header)
+}
+
+/// Names the kind of value `header` starts, as `Value::kind` would, for
+/// the item at the front of `buf`.
+pub(crate) fn header_kind(header: Header, buf: &[u8]) -> &'static str {
+    match header {
+        Header::Scalar(_) => decode_from_slice(buf).map(|(v, _)| v.kind()).unwrap_or("scalar"),
+        Header::Str(_) => "string",
+        Header::Bin(_) => "bytes",
+        Header::Ext(_) => "ext",
+        Header::Array(_) => "array",
+        Header::Map(_) => "map",
+        Header::Reserved => "reserved"
+    }
+}
+
+/// Number of header bytes following `marker`: the length field and, for
+/// ext formats, the type byte.
+pub(crate) fn header_extra(marker: u8) -> usize {
+    match marker {
+        0xc4..=0xc6 => 1 << (marker - 0xc4),
+        0xc7..=0xc9 => (1 << (marker - 0xc7)) + 1,
+        0xd4..=0xd8 => 1,
+        0xd9..=0xdb => 1 << (marker - 0xd9),
+        0xdc | 0xdd => 2 << (marker - 0xdc),
+        0xde | 0xdf => 2 << (marker - 0xde),
+        _ => 0
+    }
+}
+
+/// Checks the single value at the front of `buf` for reserved markers,
+/// truncation and invalid UTF-8, returning its encoded length.
+pub(crate) fn value_len(buf: &[u8]) -> std::result::Result<usize, Error> {
+    walk(buf, true)
+}
+
+/// Like `value_len`, without looking inside strings.
+pub(crate) fn skip_len(buf: &[u8]) -> std::result::Result<usize, Error> {
+    walk(buf, false)
+}
+
+fn walk(buf: &[u8], check_utf8: bool) -> std::result::Result<usize, Error> {
+    let mut pos = 0;
+    let mut pending: usize = 1;
+    while pending > 0 {
+        pending -= 1;
+        let (header, n) = read_header(&buf[pos..])?;
+        let start = pos;
+        pos += n;
+        let payload = match header {
+            Header::Scalar(len) | Header::Bin(len) | Header::Ext(len) | Header::Str(len) => len,
+            Header::Array(len) => {
+                pending = pending.saturating_add(len);
+                0
+            },
+            Header::Map(len) => {
+                pending = pending.saturating_add(len.saturating_mul(2));
+                0
+            },
+            Header::Reserved => {
+                return Err(Error::ReservedMarker { offset: start as u64, expected: Expected::Value });
+            }
+        };
+        if buf.len() - pos < payload {
+            return Err(Error::UnexpectedEof { needed: payload, got: buf.len() - pos });
+        }
+        match header {
+            Header::Str(len) if check_utf8 => {
+                crate::utf8::from_utf8(&buf[pos..pos + len]).map_err(Error::InvalidUtf8)?;
+            },
+            _ => {}
+        }
+        pos += payload;
+    }
+    Ok(pos)
+}
+
+struct Frame {
+    /// Items in the container; a map holds twice its entry count.
+    items: usize,
+    done: usize,
+    is_map: bool,
+    /// Path segment of the most recent map key.
+    key: String
+}
+
+fn path(stack: &[Frame]) -> String {
+    let mut path = String::from("$");
+    for frame in stack {
+        if !frame.is_map {
+            path.push_str(&format!("[{}]", frame.done));
+        } else if frame.done % 2 == 0 {
+            path.push_str(".<key>");
+        } else {
+            path.push_str(&frame.key);
+        }
+    }
+    path
+}
+
+/// Walks every value in `buf` without building any of them and reports
+/// each structural problem found (reserved markers, truncated items,
+/// invalid UTF-8), rather than stopping at the first one. Each problem is
+/// returned as an `Error::Decode` carrying its offset and path. Values
+/// following one another in `buf` are all checked.
+pub fn validate_all(buf: &[u8]) -> Vec<Error> {
+    let mut issues = Vec::new();
+    let mut stack: Vec<Frame> = Vec::new();
+    let mut pos = 0;
+    let issue = |issues: &mut Vec<Error>, stack: &[Frame], offset: usize, error: Error| {
+        issues.push(Error::Decode { offset: offset as u64, path: path(stack), error: Box::new(error) });
+    };
+    while pos < buf.len() {
+        let start = pos;
+        let (header, n) = match read_header(&buf[pos..]) {
+            Ok(h) => h,
+            Err(e) => {
+                issue(&mut issues, &stack, pos, e);
+                return issues;
+            }
+        };
+        pos += n;
+        let payload = match header {
+            Header::Scalar(len) | Header::Bin(len) | Header::Ext(len) | Header::Str(len) => len,
+            _ => 0
+        };
+        if buf.len() - pos < payload {
+            issue(&mut issues, &stack, pos, Error::UnexpectedEof { needed: payload, got: buf.len() - pos });
+            return issues;
+        }
+        match header {
+            Header::Reserved => {
+                let expected = match stack.last() {
+                    None => Expected::Value,
+                    Some(frame) if !frame.is_map => Expected::ArrayElement,
+                    Some(frame) if frame.done % 2 == 0 => Expected::MapKey,
+                    Some(_) => Expected::MapValue
+                };
+                issue(&mut issues, &stack, start, Error::ReservedMarker { offset: start as u64, expected });
+            },
+            Header::Str(len) => {
+                if let Err(e) = crate::utf8::from_utf8(&buf[pos..pos + len]) {
+                    issue(&mut issues, &stack, pos + e.valid_up_to(), Error::InvalidUtf8(e));
+                }
+            },
+            Header::Array(len) if len > 0 => {
+                stack.push(Frame { items: len, done: 0, is_map: false, key: String::new() });
+                continue;
+            },
+            Header::Map(len) if len > 0 => {
+                stack.push(Frame { items: len.saturating_mul(2), done: 0, is_map: true, key: String::new() });
+                continue;
+            },
+            _ => {}
+        }
+        pos += payload;
+        if let Some(frame) = stack.last_mut() {
+            if frame.is_map && frame.done % 2 == 0 {
+                let mut item = &buf[start..pos];
+                frame.key = match decode_from(&mut item) {
+                    Ok(key) => key_segment(&key),
+                    Err(_) => ".<key>".to_string()
+                };
+            }
+        }
+        while let Some(frame) = stack.last_mut() {
+            frame.done += 1;
+            if frame.done < frame.items {
+                break;
+            }
+            stack.pop();
+        }
+    }
+    if !stack.is_empty() {
+        issue(&mut issues, &stack, pos, Error::UnexpectedEof { needed: 1, got: 0 });
+    }
+    issues
+}
+
+/// The containers `validate_with` is inside, each with its items left and
+/// whether it is a map. The first `DEFAULT_MAX_DEPTH` levels are kept
+/// inline, so only input nested deeper than that, under a raised
+/// `max_depth`, allocates.
+struct Levels {
+    inline: [(usize, bool); DEFAULT_MAX_DEPTH],
+    depth: usize,
+    spill: Vec<(usize, bool)>
+}
+
+impl Levels {
+    fn push(&mut self, level: (usize, bool)) {
+        if self.depth < DEFAULT_MAX_DEPTH {
+            self.inline[self.depth] = level;
+        } else {
+            self.spill.push(level);
+        }
+        self.depth += 1;
+    }
+
+    fn pop(&mut self) {
+        if self.depth > DEFAULT_MAX_DEPTH {
+            self.spill.pop();
+        }
+        self.depth -= 1;
+    }
+
+    fn top(&mut self) -> Option<&mut (usize, bool)> {
+        match self.depth {
+            0 => None,
+            d if d <= DEFAULT_MAX_DEPTH => Some(&mut self.inline[d - 1]),
+            _ => self.spill.last_mut()
+        }
+    }
+}
+
+/// Checks that `buf` holds exactly one well-formed value within the
+/// default `DecodeOptions` limits, returning its length.
+pub fn validate(buf: &[u8]) -> std::result::Result<usize, Error> {
+    validate_with(buf, &DecodeOptions::default())
+}
+
+/// Checks that `buf` holds exactly one well-formed value that `decode_with`
+/// would accept under the depth, length and allocation limits of `opts`,
+/// returning its length. Nothing is decoded or allocated, so untrusted
+/// input can be rejected before it is queued or stored. Strings are
+/// checked for valid UTF-8 unless `opts.utf8` tolerates it. Errors are
+/// `Error::Decode` with the offset of the offending item; paths are not
+/// tracked and are always `$`.
+pub fn validate_with(buf: &[u8], opts: &DecodeOptions) -> std::result::Result<usize, Error> {
+    let fail = |offset: usize, error: Error| Error::Decode {
+        offset: offset as u64,
+        path: String::from("$"),
+        error: Box::new(error)
+    };
+    let mut levels = Levels { inline: [(0, false); DEFAULT_MAX_DEPTH], depth: 0, spill: Vec::new() };
+    let mut allocated: usize = 0;
+    let mut pos = 0;
+    loop {
+        let start = pos;
+        let expected = match levels.top() {
+            None => Expected::Value,
+            Some((_, false)) => Expected::ArrayElement,
+            Some((left, true)) if *left % 2 == 0 => Expected::MapKey,
+            Some(_) => Expected::MapValue
+        };
+        if let Some((left, _)) = levels.top() {
+            *left -= 1;
+        }
+        let (header, n) = read_header(&buf[pos..]).map_err(|e| fail(start, e))?;
+        pos += n;
+        let (payload, bytes) = match header {
+            Header::Scalar(len) => (len, 0),
+            Header::Str(len) | Header::Bin(len) | Header::Ext(len) => {
+                if len > opts.max_payload_len {
+                    return Err(fail(start, Error::PayloadTooLong { len, limit: opts.max_payload_len }));
+                }
+                (len, len)
+            },
+            Header::Array(len) | Header::Map(len) => {
+                if len > opts.max_container_len {
+                    return Err(fail(start, Error::ContainerTooLong { len, limit: opts.max_container_len }));
+                }
+                if levels.depth >= opts.max_depth {
+                    return Err(fail(start, Error::DepthLimitExceeded));
+                }
+                let is_map = matches!(header, Header::Map(_));
+                let items = if is_map { len.saturating_mul(2) } else { len };
+                if items > 0 {
+                    levels.push((items, is_map));
+                }
+                (0, items.saturating_mul(std::mem::size_of::<Value>()))
+            },
+            Header::Reserved => return Err(fail(start, Error::ReservedMarker { offset: start as u64, expected }))
+        };
+        allocated = allocated.saturating_add(bytes);
+        if allocated > opts.max_alloc {
+            return Err(fail(start, Error::AllocLimitExceeded { limit: opts.max_alloc }));
+        }
+        if buf.len() - pos < payload {
+            return Err(fail(pos, Error::UnexpectedEof { needed: payload, got: buf.len() - pos }));
+        }
+        if let (Header::Str(len), Utf8Policy::Strict) = (header, opts.utf8) {
+            crate::utf8::from_utf8(&buf[pos..pos + len]).map_err(|e| fail(start, Error::InvalidUtf8(e)))?;
+        }
+        pos += payload;
+        while let Some((0, _)) = levels.top() {
+            levels.pop();
+        }
+        if levels.depth == 0 {
+            break;
+        }
+    }
+    if pos < buf.len() {
+        return Err(fail(pos, Error::TrailingBytes(buf.len() - pos)));
+    }
+    Ok(pos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unpack(issues: Vec<Error>) -> Vec<(u64, String, String)> {
+        issues.into_iter().map(|e| match e {
+            Error::Decode { offset, path, error } => (offset, path, error.to_string()),
+            e => panic!("unexpected issue {}", e)
+        }).collect()
+    }
+
+    #[test]
+    fn clean_input_has_no_issues() {
+        let buf = [0x93, 0x01, 0xa1, b'a', 0x81, 0xa1, b'k', 0xc4, 0x01, 0xff, 0xc0];
+        assert!(validate_all(&buf).is_empty());
+    }
+
+    #[test]
+    fn collects_every_issue() {
+        let buf = [0x94, 0xc1, 0xa2, 0xff, 0xfe, 0x81, 0xa1, b'k', 0xc1, 0xcd, 0x01];
+        let issues = unpack(validate_all(&buf));
+        assert_eq!(issues.len(), 4);
+        assert_eq!((issues[0].0, issues[0].1.as_str()), (1, "$[0]"));
+        assert_eq!((issues[1].0, issues[1].1.as_str()), (3, "$[1]"));
+        assert_eq!((issues[2].0, issues[2].1.as_str()), (8, "$[2].k"));
+        assert_eq!((issues[3].0, issues[3].1.as_str()), (10, "$[3]"));
+        assert!(issues[3].2.starts_with("unexpected end of input"));
+    }
+
+    #[test]
+    fn reports_unfinished_containers() {
+        let issues = unpack(validate_all(&[0xc0, 0x92, 0x01]));
+        assert_eq!(issues.len(), 1);
+        assert_eq!((issues[0].0, issues[0].1.as_str()), (3, "$[1]"));
+    }
+
+    #[test]
+    fn validate_accepts_one_value() {
+        let buf = [0x93, 0x01, 0xa1, b'a', 0x82, 0xa1, b'k', 0xc4, 0x01, 0xff, 0xa1, b'e', 0x90, 0x80];
+        assert_eq!(validate(&buf[..buf.len() - 1]).ok(), Some(buf.len() - 1));
+        assert!(matches!(validate(&[0x91, 0x80]), Ok(2)));
+        assert!(matches!(validate(&[0xc0, 0xc0]).map_err(Error::into_root), Err(Error::TrailingBytes(1))));
+        assert!(matches!(validate(&[]).map_err(Error::into_root), Err(Error::UnexpectedEof { .. })));
+        assert!(matches!(validate(&[0x92, 0x01]), Err(Error::Decode { offset: 2, .. })));
+        assert!(matches!(
+            validate(&[0x81, 0xa1, b'k', 0xc1]).map_err(Error::into_root),
+            Err(Error::ReservedMarker { offset: 3, expected: Expected::MapValue })
+        ));
+        assert!(matches!(validate(&[0xa1, 0xff]).map_err(Error::into_root), Err(Error::InvalidUtf8(_))));
+        let lossy = DecodeOptions { utf8: Utf8Policy::Lossy, ..DecodeOptions::default() };
+        assert!(matches!(validate_with(&[0xa1, 0xff], &lossy), Ok(2)));
+    }
+
+    #[test]
+    fn validate_enforces_limits() {
+        let root = |buf: &[u8], opts: &DecodeOptions| validate_with(buf, opts).map_err(Error::into_root);
+        let opts = DecodeOptions { max_depth: 2, ..DecodeOptions::default() };
+        assert!(root(&[0x91, 0x91, 0xc0], &opts).is_ok());
+        assert!(matches!(root(&[0x91, 0x91, 0x90], &opts), Err(Error::DepthLimitExceeded)));
+        let opts = DecodeOptions { max_container_len: 2, ..DecodeOptions::default() };
+        assert!(matches!(root(&[0x93, 0x01, 0x02, 0x03], &opts), Err(Error::ContainerTooLong { len: 3, limit: 2 })));
+        let opts = DecodeOptions { max_payload_len: 1, ..DecodeOptions::default() };
+        assert!(matches!(root(&[0xc4, 0x02, 0x00, 0x00], &opts), Err(Error::PayloadTooLong { len: 2, limit: 1 })));
+        // A forged length is caught by the allocation limit before the
+        // missing bytes are noticed.
+        let opts = DecodeOptions { max_alloc: 1 << 20, ..DecodeOptions::default() };
+        assert!(matches!(root(&[0xdd, 0xff, 0xff, 0xff, 0xff], &opts), Err(Error::AllocLimitExceeded { .. })));
+    }
+
+    #[test]
+    fn validate_matches_decode() {
+        let mut nested = vec![0x91; 1000];
+        nested.push(0xc0);
+        let deep = DecodeOptions { max_depth: 1000, ..DecodeOptions::default() };
+        assert_eq!(validate_with(&nested, &deep).ok(), Some(nested.len()));
+        let shallow = DecodeOptions { max_depth: 999, ..DecodeOptions::default() };
+        assert!(validate_with(&nested, &shallow).is_err());
+        assert!(crate::decode_with(&mut &nested[..], &shallow).is_err());
+    }
+}
@@ -0,0 +1,357 @@
+use std::borrow::Cow;
+use std::io::Write;
+
+use crate::{
+    encode_bin, encode_ext, encode_fixed_int, encode_float, encode_int, encode_str, encode_value_in,
+    EncodeOptions, IntFormat, MapIter, Open, RawValue, Result, Value, Walk
+};
+
+/// A value whose strings and byte payloads may borrow from elsewhere, for
+/// building documents out of static or borrowed data without allocating
+/// for every string. Maps are kept as entry lists in the order given.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValueRef<'a> {
+    Null,
+    Boolean(bool),
+    Integer(i128),
+    FixedInteger(i128, IntFormat),
+    Float(f64),
+    String(Cow<'a, str>),
+    Bytes(Cow<'a, [u8]>),
+    Array(Vec<ValueRef<'a>>),
+    Map(Vec<(ValueRef<'a>, ValueRef<'a>)>),
+    Ext(i8, Cow<'a, [u8]>),
+    /// An already-encoded value, written out verbatim.
+    Raw(Cow<'a, RawValue>)
+}
+
+/// A document `rebuild` can take apart: a borrowed `Value`, or a
+/// `ValueRef` given up by value.
+trait Unfold: Sized {
+    type Items: ExactSizeIterator<Item = Self>;
+    type Entries: ExactSizeIterator<Item = (Self, Self)>;
+
+    fn unfold(self) -> Unfolded<Self>;
+}
+
+enum Unfolded<S: Unfold> {
+    Array(S::Items),
+    Map(S::Entries),
+    Scalar(S)
+}
+
+impl<'a> Unfold for &'a Value {
+    type Items = std::slice::Iter<'a, Value>;
+    type Entries = MapIter<'a>;
+
+    fn unfold(self) -> Unfolded<&'a Value> {
+        match self {
+            Value::Array(a) => Unfolded::Array(a.iter()),
+            Value::Map(m) => Unfolded::Map(m.iter()),
+            scalar => Unfolded::Scalar(scalar)
+        }
+    }
+}
+
+impl<'a> Unfold for ValueRef<'a> {
+    type Items = std::vec::IntoIter<ValueRef<'a>>;
+    type Entries = std::vec::IntoIter<(ValueRef<'a>, ValueRef<'a>)>;
+
+    fn unfold(self) -> Unfolded<ValueRef<'a>> {
+        match self {
+            ValueRef::Array(a) => Unfolded::Array(a.into_iter()),
+            ValueRef::Map(m) => Unfolded::Map(m.into_iter()),
+            scalar => Unfolded::Scalar(scalar)
+        }
+    }
+}
+
+/// A container `rebuild` is part way through: what is left of the source
+/// and what was rebuilt so far. Maps also hold a rebuilt key until its
+/// value is done, and the source value waiting behind that key.
+enum Folding<S: Unfold, U> {
+    Array(S::Items, Vec<U>),
+    Map(S::Entries, Vec<(U, U)>, Option<U>, Option<S>)
+}
+
+/// Rebuilds `value` bottom up on an explicit stack, so any depth works:
+/// `scalar` converts anything but an array or map, and `array` and `map`
+/// assemble containers from their converted contents.
+fn rebuild<S: Unfold, U>(
+    value: S,
+    mut scalar: impl FnMut(S) -> U,
+    mut array: impl FnMut(Vec<U>) -> U,
+    mut map: impl FnMut(Vec<(U, U)>) -> U
+) -> U {
+    let mut stack: Vec<Folding<S, U>> = Vec::new();
+    let mut current = value;
+    loop {
+        let mut done = match current.unfold() {
+            Unfolded::Array(mut items) => match items.next() {
+                Some(first) => {
+                    let out = Vec::with_capacity(items.len() + 1);
+                    stack.push(Folding::Array(items, out));
+                    current = first;
+                    continue;
+                },
+                None => array(Vec::new())
+            },
+            Unfolded::Map(mut entries) => match entries.next() {
+                Some((k, v)) => {
+                    let out = Vec::with_capacity(entries.len() + 1);
+                    stack.push(Folding::Map(entries, out, None, Some(v)));
+                    current = k;
+                    continue;
+                },
+                None => map(Vec::new())
+            },
+            Unfolded::Scalar(v) => scalar(v)
+        };
+        // Hand the value to its container, closing every container it fills.
+        current = loop {
+            match stack.pop() {
+                None => return done,
+                Some(Folding::Array(mut items, mut out)) => {
+                    out.push(done);
+                    if let Some(v) = items.next() {
+                        stack.push(Folding::Array(items, out));
+                        break v;
+                    }
+                    done = array(out);
+                },
+                Some(Folding::Map(mut entries, mut out, key, pending)) => {
+                    let key = match key {
+                        Some(k) => {
+                            out.push((k, done));
+                            None
+                        },
+                        None => Some(done)
+                    };
+                    if let Some(v) = pending {
+                        stack.push(Folding::Map(entries, out, key, None));
+                        break v;
+                    }
+                    if let Some((k, v)) = entries.next() {
+                        stack.push(Folding::Map(entries, out, key, Some(v)));
+                        break k;
+                    }
+                    done = map(out);
+                }
+            }
+        };
+    }
+}
+
+impl<'a> ValueRef<'a> {
+    /// Converts to an owned `Value`, copying only the borrowed parts.
+    /// Containers are tracked on an explicit stack, so any depth works.
+    pub fn into_owned(self) -> Value {
+        rebuild(
+            self,
+            |v| match v {
+                ValueRef::Null => Value::Null,
+                ValueRef::Boolean(b) => Value::Boolean(b),
+                ValueRef::Integer(i) => Value::Integer(i),
+                ValueRef::FixedInteger(i, format) => Value::FixedInteger(i, format),
+                ValueRef::Float(f) => Value::Float(f),
+                ValueRef::String(s) => Value::String(s.into_owned()),
+                ValueRef::Bytes(b) => Value::Bytes(b.into_owned()),
+                ValueRef::Array(_) | ValueRef::Map(_) => Value::Null,
+                ValueRef::Ext(t, d) => Value::Ext(t, d.into_owned()),
+                ValueRef::Raw(raw) => Value::Raw(raw.into_owned())
+            },
+            Value::Array,
+            |entries| Value::Map(entries.into_iter().collect())
+        )
+    }
+}
+
+/// Borrows every string and payload of `value`, at any depth.
+impl<'a> From<&'a Value> for ValueRef<'a> {
+    fn from(value: &'a Value) -> ValueRef<'a> {
+        rebuild(
+            value,
+            |v| match v {
+                Value::Null => ValueRef::Null,
+                Value::Boolean(b) => ValueRef::Boolean(*b),
+                Value::Integer(i) => ValueRef::Integer(*i),
+                Value::FixedInteger(i, format) => ValueRef::FixedInteger(*i, *format),
+                Value::Float(f) => ValueRef::Float(*f),
+                Value::String(s) => ValueRef::String(Cow::Borrowed(s)),
+                Value::SharedString(s) => ValueRef::String(Cow::Borrowed(s)),
+                Value::InlineString(s) => ValueRef::String(Cow::Borrowed(s)),
+                Value::Bytes(b) => ValueRef::Bytes(Cow::Borrowed(b)),
+                Value::Array(_) | Value::Map(_) => ValueRef::Null,
+                Value::Ext(t, d) => ValueRef::Ext(*t, Cow::Borrowed(d)),
+                Value::Raw(raw) => ValueRef::Raw(Cow::Borrowed(raw))
+            },
+            ValueRef::Array,
+            ValueRef::Map
+        )
+    }
+}
+
+impl<'a> From<&'a str> for ValueRef<'a> {
+    fn from(s: &'a str) -> ValueRef<'a> {
+        ValueRef::String(Cow::Borrowed(s))
+    }
+}
+
+impl<'a> From<String> for ValueRef<'a> {
+    fn from(s: String) -> ValueRef<'a> {
+        ValueRef::String(Cow::Owned(s))
+    }
+}
+
+impl<'a> From<&'a [u8]> for ValueRef<'a> {
+    fn from(b: &'a [u8]) -> ValueRef<'a> {
+        ValueRef::Bytes(Cow::Borrowed(b))
+    }
+}
+
+impl<'a> From<bool> for ValueRef<'a> {
+    fn from(b: bool) -> ValueRef<'a> {
+        ValueRef::Boolean(b)
+    }
+}
+
+impl<'a> From<i64> for ValueRef<'a> {
+    fn from(i: i64) -> ValueRef<'a> {
+        ValueRef::Integer(i as i128)
+    }
+}
+
+impl<'a> From<f64> for ValueRef<'a> {
+    fn from(f: f64) -> ValueRef<'a> {
+        ValueRef::Float(f)
+    }
+}
+
+/// The entries of a `ValueRef` map, in listed order.
+#[derive(Debug)]
+pub(crate) struct RefEntries<'a, 'b>(std::slice::Iter<'a, (ValueRef<'b>, ValueRef<'b>)>);
+
+impl<'a, 'b> Iterator for RefEntries<'a, 'b> {
+    type Item = (&'a ValueRef<'b>, &'a ValueRef<'b>);
+
+    fn next(&mut self) -> Option<(&'a ValueRef<'b>, &'a ValueRef<'b>)> {
+        self.0.next().map(|(k, v)| (k, v))
+    }
+}
+
+impl<'a, 'b: 'a> Walk<'a> for ValueRef<'b> {
+    type Entries = RefEntries<'a, 'b>;
+
+    fn open(&'a self, _sort_keys: bool) -> Option<Open<'a, ValueRef<'b>>> {
+        match self {
+            ValueRef::Array(a) => Some(Open::Array(a)),
+            ValueRef::Map(m) => Some(Open::Map(m.len(), RefEntries(m.iter()))),
+            _ => None
+        }
+    }
+
+    fn encode_scalar<W: Write + ?Sized>(&self, w: &mut W, opts: &EncodeOptions) -> Result<()> {
+        match self {
+            ValueRef::Null => {
+                w.write_all(&[0xc0])?;
+                Ok(())
+            },
+            ValueRef::Boolean(b) => {
+                w.write_all(&[if *b { 0xc3 } else { 0xc2 }])?;
+                Ok(())
+            },
+            ValueRef::Integer(i) => encode_int(w, *i, opts),
+            ValueRef::FixedInteger(i, format) => encode_fixed_int(w, *i, *format),
+            ValueRef::Float(f) => encode_float(w, *f, opts),
+            ValueRef::String(s) => encode_str(w, s, opts),
+            ValueRef::Bytes(b) => encode_bin(w, b, opts),
+            ValueRef::Array(_) | ValueRef::Map(_) => Ok(()),
+            ValueRef::Ext(t, d) => encode_ext(w, *t, d),
+            ValueRef::Raw(raw) => {
+                w.write_all(raw.as_bytes())?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Encodes a `ValueRef` exactly as the equivalent `Value` would be, except
+/// that map entries are written in their listed order.
+/// Like `encode_with`, it walks containers on an explicit stack rather
+/// than recursing.
+pub fn encode_ref<W: Write + ?Sized>(w: &mut W, value: &ValueRef, opts: &EncodeOptions) -> Result<()> {
+    encode_value_in(w, value, opts, &mut Vec::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{enc, ok};
+
+    fn encode(value: &ValueRef) -> Vec<u8> {
+        let mut buf = Vec::new();
+        ok(encode_ref(&mut buf, value, &EncodeOptions::default()));
+        buf
+    }
+
+    #[test]
+    fn borrows_static_data() {
+        let v = ValueRef::Map(vec![
+            ("name".into(), "static".into()),
+            ("blob".into(), ValueRef::from(&b"\x00\x01"[..])),
+            ("n".into(), ValueRef::Array(vec![1i64.into(), 2.5.into(), ValueRef::Null]))
+        ]);
+        if let ValueRef::Map(entries) = &v {
+            assert!(matches!(entries[0].1, ValueRef::String(Cow::Borrowed(_))));
+        }
+        let buf = encode(&v);
+        assert_eq!(buf[..7], [0x83, 0xa4, b'n', b'a', b'm', b'e', 0xa6]);
+        assert_eq!(enc(&v.into_owned()).len(), buf.len());
+    }
+
+    #[test]
+    fn handles_any_depth() {
+        let depth = 200_000;
+        let mut v = ValueRef::Null;
+        for i in 0..depth {
+            v = if i % 2 == 0 { ValueRef::Array(vec![v]) } else { ValueRef::Map(vec![(1i64.into(), v)]) };
+        }
+        let opts = EncodeOptions { max_depth: usize::MAX, ..EncodeOptions::default() };
+        let mut buf = Vec::new();
+        assert!(encode_ref(&mut buf, &v, &opts).is_ok());
+        assert_eq!(buf.len(), depth + depth / 2 + 1);
+        assert!(matches!(encode_ref(&mut Vec::new(), &v, &EncodeOptions::default()), Err(crate::Error::DepthLimitExceeded)));
+
+        let owned = v.into_owned();
+        let mut view = ValueRef::from(&owned);
+        let mut encoded = Vec::new();
+        assert!(encode_ref(&mut encoded, &view, &opts).is_ok());
+        assert_eq!(encoded, buf);
+
+        // Take both apart by hand, as dropping them would recurse.
+        loop {
+            view = match view {
+                ValueRef::Array(mut items) => items.pop().unwrap_or(ValueRef::Null),
+                ValueRef::Map(mut entries) => entries.pop().map(|(_, v)| v).unwrap_or(ValueRef::Null),
+                _ => break
+            };
+        }
+        let mut owned = owned;
+        loop {
+            owned = match owned {
+                Value::Array(mut items) => items.pop().unwrap_or_default(),
+                Value::Map(m) => m.into_iter().next().map(|(_, v)| v).unwrap_or_default(),
+                _ => break
+            };
+        }
+    }
+
+    #[test]
+    fn views_owned_values() {
+        let raw = ok(RawValue::from_value(&msgpack!({"r": [1]})));
+        let owned = msgpack!([1, "two", {"three": [3]}, Value::Raw(raw)]);
+        let view = ValueRef::from(&owned);
+        assert_eq!(enc(&owned), encode(&view));
+        assert_eq!(view.into_owned(), owned);
+    }
+}
@@ -0,0 +1,274 @@
+use std::io::Write;
+
+use crate::{
+    check_depth, encode_with, write_array_header, write_map_header, EncodeOptions, EncodeValue, Error, Result, Value
+};
+
+/// The members a container writer still expects, shared by `ArrayWriter`
+/// and `MapWriter`.
+#[derive(Debug)]
+struct Slots<'a, W: Write + ?Sized> {
+    w: &'a mut W,
+    /// Options for the members, with `max_depth` counting from here.
+    opts: EncodeOptions,
+    declared: usize,
+    written: usize,
+    /// Set when a member fails after being claimed, and while a nested
+    /// container is open, as the output is unusable until it ends.
+    broken: bool,
+    /// The `broken` flag of the enclosing writer, cleared by `end`.
+    parent: Option<&'a mut bool>
+}
+
+impl<'a, W: Write + ?Sized> Slots<'a, W> {
+    fn open(w: &'a mut W, len: usize, slots: usize, is_map: bool, opts: &EncodeOptions, parent: Option<&'a mut bool>) -> Result<Slots<'a, W>> {
+        check_depth(opts, 0)?;
+        if is_map {
+            write_map_header(w, len)?;
+        } else {
+            write_array_header(w, len)?;
+        }
+        let opts = EncodeOptions { max_depth: opts.max_depth - 1, ..opts.clone() };
+        Ok(Slots { w, opts, declared: slots, written: 0, broken: false, parent })
+    }
+
+    /// Claims the next member, failing if all have been written.
+    fn take(&mut self) -> Result<()> {
+        if self.written == self.declared {
+            return Err(Error::LengthMismatch { declared: self.declared, written: self.written + 1 });
+        }
+        self.written += 1;
+        Ok(())
+    }
+
+    fn write(&mut self, value: &Value) -> Result<()> {
+        self.take()?;
+        let result = encode_with(self.w, value, &self.opts);
+        self.broken |= result.is_err();
+        result
+    }
+
+    fn begin_array(&mut self, len: usize) -> Result<ArrayWriter<'_, W>> {
+        self.take()?;
+        self.broken = true;
+        Ok(ArrayWriter { slots: Slots::open(&mut *self.w, len, len, false, &self.opts, Some(&mut self.broken))? })
+    }
+
+    fn begin_map(&mut self, len: usize) -> Result<MapWriter<'_, W>> {
+        self.take()?;
+        self.broken = true;
+        Ok(MapWriter { slots: Slots::open(&mut *self.w, len, len.saturating_mul(2), true, &self.opts, Some(&mut self.broken))? })
+    }
+
+    /// Checks every slot was filled, with counts reported in units of
+    /// `per` slots.
+    fn end(self, per: usize) -> Result<()> {
+        if self.broken {
+            return Err(Error::Unfinished);
+        }
+        if self.written != self.declared {
+            return Err(Error::LengthMismatch { declared: self.declared / per, written: self.written / per });
+        }
+        if let Some(parent) = self.parent {
+            *parent = false;
+        }
+        Ok(())
+    }
+
+    fn write_value<T: EncodeValue + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.take()?;
+        let result = value.encode(self.w);
+        self.broken |= result.is_err();
+        result
+    }
+}
+
+/// Writes an array whose length is known up front one element at a time,
+/// so elements can be produced lazily instead of being collected into a
+/// `Vec<Value>` first. Start one with `begin_array`. Nested containers are
+/// written through the handles returned by `begin_array` and `begin_map`,
+/// and must be ended before the next element.
+///
+/// Writing more elements than declared fails, as does ending with fewer.
+/// Once an element fails part way through, the output is unusable and
+/// `end` fails too, as does ending the parent of a nested writer that was
+/// dropped without `end`.
+#[derive(Debug)]
+#[must_use = "call `end` once every element is written"]
+pub struct ArrayWriter<'a, W: Write + ?Sized> {
+    slots: Slots<'a, W>
+}
+
+impl<W: Write + ?Sized> ArrayWriter<'_, W> {
+    pub fn push(&mut self, value: &Value) -> Result<()> {
+        self.slots.write(value)
+    }
+
+    /// Starts an array as the next element.
+    pub fn begin_array(&mut self, len: usize) -> Result<ArrayWriter<'_, W>> {
+        self.slots.begin_array(len)
+    }
+
+    /// Starts a map as the next element.
+    pub fn begin_map(&mut self, len: usize) -> Result<MapWriter<'_, W>> {
+        self.slots.begin_map(len)
+    }
+
+    /// Elements still to be written.
+    pub fn remaining(&self) -> usize {
+        self.slots.declared - self.slots.written
+    }
+
+    /// Checks that every declared element was written.
+    pub fn end(self) -> Result<()> {
+        self.slots.end(1)
+    }
+
+    /// Like `push`, for any `EncodeValue` type. Typed encoding has no
+    /// options, so those of the writer don't apply.
+    pub fn push_value<T: EncodeValue + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.slots.write_value(value)
+    }
+}
+
+/// Writes a map whose length is known up front one entry at a time; see
+/// `ArrayWriter`. Keys and values alternate: `key` or `begin_*` for the
+/// key, then `value` or `begin_*` for its value, or `entry` for both.
+#[derive(Debug)]
+#[must_use = "call `end` once every entry is written"]
+pub struct MapWriter<'a, W: Write + ?Sized> {
+    slots: Slots<'a, W>
+}
+
+impl<W: Write + ?Sized> MapWriter<'_, W> {
+    pub fn entry(&mut self, key: &Value, value: &Value) -> Result<()> {
+        self.slots.write(key)?;
+        self.slots.write(value)
+    }
+
+    pub fn key(&mut self, key: &Value) -> Result<()> {
+        self.slots.write(key)
+    }
+
+    pub fn value(&mut self, value: &Value) -> Result<()> {
+        self.slots.write(value)
+    }
+
+    /// Starts an array as the next key or value.
+    pub fn begin_array(&mut self, len: usize) -> Result<ArrayWriter<'_, W>> {
+        self.slots.begin_array(len)
+    }
+
+    /// Starts a map as the next key or value.
+    pub fn begin_map(&mut self, len: usize) -> Result<MapWriter<'_, W>> {
+        self.slots.begin_map(len)
+    }
+
+    /// Entries still to be written; a key without its value counts as a
+    /// whole entry.
+    pub fn remaining(&self) -> usize {
+        (self.slots.declared - self.slots.written).div_ceil(2)
+    }
+
+    /// Checks that every declared entry was written.
+    pub fn end(self) -> Result<()> {
+        self.slots.end(2)
+    }
+
+    /// Like `entry`, for any `EncodeValue` types.
+    pub fn entry_value<K: EncodeValue + ?Sized, V: EncodeValue + ?Sized>(&mut self, key: &K, value: &V) -> Result<()> {
+        self.slots.write_value(key)?;
+        self.slots.write_value(value)
+    }
+}
+
+/// Starts writing an array of `len` elements to `w`.
+pub fn begin_array<W: Write + ?Sized>(w: &mut W, len: usize) -> Result<ArrayWriter<'_, W>> {
+    begin_array_with(w, len, &EncodeOptions::default())
+}
+
+pub fn begin_array_with<'a, W: Write + ?Sized>(w: &'a mut W, len: usize, opts: &EncodeOptions) -> Result<ArrayWriter<'a, W>> {
+    Ok(ArrayWriter { slots: Slots::open(w, len, len, false, opts, None)? })
+}
+
+/// Starts writing a map of `len` entries to `w`.
+pub fn begin_map<W: Write + ?Sized>(w: &mut W, len: usize) -> Result<MapWriter<'_, W>> {
+    begin_map_with(w, len, &EncodeOptions::default())
+}
+
+pub fn begin_map_with<'a, W: Write + ?Sized>(w: &'a mut W, len: usize, opts: &EncodeOptions) -> Result<MapWriter<'a, W>> {
+    Ok(MapWriter { slots: Slots::open(w, len, len.saturating_mul(2), true, opts, None)? })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{dec, enc, ok};
+
+    #[test]
+    fn streams_nested_containers() {
+        let mut out = Vec::new();
+        let result = (|| -> Result<()> {
+            let mut rows = begin_array(&mut out, 1000)?;
+            for i in 0..1000 {
+                let mut row = rows.begin_map(2)?;
+                row.entry(&msgpack!("id"), &msgpack!(i))?;
+                row.key(&msgpack!("tags"))?;
+                let mut tags = row.begin_array(2)?;
+                tags.push(&msgpack!("a"))?;
+                tags.push_value("b")?;
+                tags.end()?;
+                row.end()?;
+            }
+            rows.end()
+        })();
+        ok(result);
+        let expected: Vec<Value> = (0..1000).map(|i| msgpack!({"id": i, "tags": ["a", "b"]})).collect();
+        assert_eq!(dec(&out), Value::Array(expected));
+    }
+
+    #[test]
+    fn matches_encoding_a_built_value() {
+        let mut out = Vec::new();
+        let result = (|| -> Result<()> {
+            let mut m = begin_map(&mut out, 1)?;
+            m.entry_value("n", &vec![1u8, 2])?;
+            m.end()
+        })();
+        ok(result);
+        assert_eq!(out, enc(&msgpack!({"n": [1, 2]})));
+    }
+
+    #[test]
+    fn checks_lengths_and_depth() {
+        let mut out = Vec::new();
+        let mut a = ok(begin_array(&mut out, 1));
+        assert_eq!(a.remaining(), 1);
+        assert!(a.push(&msgpack!(1)).is_ok());
+        assert!(matches!(a.push(&msgpack!(2)), Err(Error::LengthMismatch { declared: 1, written: 2 })));
+        assert!(a.end().is_ok());
+
+        let mut out = Vec::new();
+        let m = begin_map(&mut out, 2).ok();
+        assert!(matches!(m.map(MapWriter::end), Some(Err(Error::LengthMismatch { declared: 2, written: 0 }))));
+
+        let opts = EncodeOptions { max_depth: 2, ..EncodeOptions::default() };
+        let mut out = Vec::new();
+        let mut a = ok(begin_array_with(&mut out, 3, &opts));
+        assert!(a.push(&msgpack!([1])).is_ok());
+        assert!(matches!(a.push(&msgpack!([[1]])), Err(Error::DepthLimitExceeded)));
+        let mut inner = ok(a.begin_array(1));
+        assert!(matches!(inner.begin_array(0), Err(Error::DepthLimitExceeded)));
+        assert!(matches!(inner.end(), Err(Error::Unfinished)));
+        // Every element was claimed, but the one that failed was written
+        // only in part.
+        assert_eq!(a.remaining(), 0);
+        assert!(matches!(a.end(), Err(Error::Unfinished)));
+
+        let mut out = Vec::new();
+        let mut m = ok(begin_map(&mut out, 1));
+        assert!(m.key(&msgpack!("k")).is_ok());
+        assert!(m.begin_array(1).is_ok());
+        assert!(matches!(m.end(), Err(Error::Unfinished)));
+    }
+}
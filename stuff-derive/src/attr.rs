@@ -0,0 +1,211 @@
+use syn::{Attribute, LitInt, LitStr};
+
+/// How `rename_all` rewrites field and variant names.
+#[derive(Clone, Copy)]
+pub enum RenameRule {
+    Lower,
+    Upper,
+    Pascal,
+    Camel,
+    Snake,
+    ScreamingSnake,
+    Kebab,
+    ScreamingKebab
+}
+
+impl RenameRule {
+    fn parse(lit: &LitStr) -> syn::Result<RenameRule> {
+        Ok(match lit.value().as_str() {
+            "lowercase" => RenameRule::Lower,
+            "UPPERCASE" => RenameRule::Upper,
+            "PascalCase" => RenameRule::Pascal,
+            "camelCase" => RenameRule::Camel,
+            "snake_case" => RenameRule::Snake,
+            "SCREAMING_SNAKE_CASE" => RenameRule::ScreamingSnake,
+            "kebab-case" => RenameRule::Kebab,
+            "SCREAMING-KEBAB-CASE" => RenameRule::ScreamingKebab,
+            _ => return Err(syn::Error::new_spanned(lit, "unknown rename_all rule"))
+        })
+    }
+
+    /// Renames a snake_case field.
+    pub fn apply_to_field(self, name: &str) -> String {
+        match self {
+            RenameRule::Lower | RenameRule::Snake => name.to_string(),
+            RenameRule::Upper | RenameRule::ScreamingSnake => name.to_ascii_uppercase(),
+            RenameRule::Pascal | RenameRule::Camel => {
+                let mut out = String::new();
+                let mut upper = matches!(self, RenameRule::Pascal);
+                for c in name.chars() {
+                    if c == '_' {
+                        upper = true;
+                    } else if upper {
+                        out.push(c.to_ascii_uppercase());
+                        upper = false;
+                    } else {
+                        out.push(c);
+                    }
+                }
+                out
+            },
+            RenameRule::Kebab => name.replace('_', "-"),
+            RenameRule::ScreamingKebab => name.replace('_', "-").to_ascii_uppercase()
+        }
+    }
+
+    /// Renames a PascalCase variant.
+    pub fn apply_to_variant(self, name: &str) -> String {
+        let snake = || {
+            let mut out = String::new();
+            for (i, c) in name.char_indices() {
+                if c.is_ascii_uppercase() && i > 0 {
+                    out.push('_');
+                }
+                out.push(c.to_ascii_lowercase());
+            }
+            out
+        };
+        match self {
+            RenameRule::Pascal => name.to_string(),
+            RenameRule::Lower => name.to_ascii_lowercase(),
+            RenameRule::Upper => name.to_ascii_uppercase(),
+            RenameRule::Camel => {
+                let mut chars = name.chars();
+                match chars.next() {
+                    Some(first) => first.to_ascii_lowercase().to_string() + chars.as_str(),
+                    None => String::new()
+                }
+            },
+            RenameRule::Snake => snake(),
+            RenameRule::ScreamingSnake => snake().to_ascii_uppercase(),
+            RenameRule::Kebab => snake().replace('_', "-"),
+            RenameRule::ScreamingKebab => snake().replace('_', "-").to_ascii_uppercase()
+        }
+    }
+}
+
+/// How an enum marks which variant a value holds.
+pub enum Tagging {
+    /// `"Variant"` or `{"Variant": content}`.
+    External,
+    /// `{tag: "Variant", content: content}`.
+    Adjacent { tag: String, content: String },
+    /// Just the content; decoding tries each variant in order.
+    Untagged
+}
+
+/// `#[msgpack(..)]` on the struct or enum itself.
+pub struct Container {
+    pub rename_all: Option<RenameRule>,
+    pub tagging: Tagging,
+    /// Every field is decoded as `Default::default()` when missing.
+    pub default: bool
+}
+
+/// `#[msgpack(..)]` on a variant.
+#[derive(Default)]
+pub struct Variant {
+    pub rename: Option<String>
+}
+
+/// `#[msgpack(..)]` on a field.
+#[derive(Default)]
+pub struct Field {
+    pub rename: Option<String>,
+    /// Written under this integer key instead of its name.
+    pub tag: Option<u64>,
+    /// A `Map` collecting entries no other field claims, written back out
+    /// on encode.
+    pub unknown: bool,
+    /// Never written; decoded as `Default::default()`.
+    pub skip: bool,
+    /// Decoded as `Default::default()` when missing.
+    pub default: bool
+}
+
+fn msgpack_attrs(attrs: &[Attribute]) -> impl Iterator<Item = &Attribute> {
+    attrs.iter().filter(|a| a.path().is_ident("msgpack"))
+}
+
+impl Container {
+    pub fn parse(attrs: &[Attribute]) -> syn::Result<Container> {
+        let mut rename_all = None;
+        let mut untagged = false;
+        let mut tag = None;
+        let mut content = None;
+        let mut default = false;
+        for attr in msgpack_attrs(attrs) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename_all") {
+                    rename_all = Some(RenameRule::parse(&meta.value()?.parse()?)?);
+                } else if meta.path.is_ident("untagged") {
+                    untagged = true;
+                } else if meta.path.is_ident("tag") {
+                    tag = Some(meta.value()?.parse::<LitStr>()?.value());
+                } else if meta.path.is_ident("content") {
+                    content = Some(meta.value()?.parse::<LitStr>()?.value());
+                } else if meta.path.is_ident("default") {
+                    default = true;
+                } else {
+                    return Err(meta.error("unknown msgpack container attribute"));
+                }
+                Ok(())
+            })?;
+        }
+        let tagging = match (untagged, tag, content) {
+            (false, None, None) => Tagging::External,
+            (false, Some(tag), Some(content)) => Tagging::Adjacent { tag, content },
+            (true, None, None) => Tagging::Untagged,
+            _ => {
+                let msg = "use either `untagged` or both `tag` and `content`";
+                return Err(syn::Error::new(proc_macro2::Span::call_site(), msg));
+            }
+        };
+        Ok(Container { rename_all, tagging, default })
+    }
+}
+
+impl Variant {
+    pub fn parse(attrs: &[Attribute]) -> syn::Result<Variant> {
+        let mut out = Variant::default();
+        for attr in msgpack_attrs(attrs) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    out.rename = Some(meta.value()?.parse::<LitStr>()?.value());
+                    Ok(())
+                } else {
+                    Err(meta.error("unknown msgpack variant attribute"))
+                }
+            })?;
+        }
+        Ok(out)
+    }
+}
+
+impl Field {
+    pub fn parse(attrs: &[Attribute]) -> syn::Result<Field> {
+        let mut out = Field::default();
+        for attr in msgpack_attrs(attrs) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    out.rename = Some(meta.value()?.parse::<LitStr>()?.value());
+                } else if meta.path.is_ident("tag") {
+                    out.tag = Some(meta.value()?.parse::<LitInt>()?.base10_parse()?);
+                } else if meta.path.is_ident("unknown") {
+                    out.unknown = true;
+                } else if meta.path.is_ident("skip") {
+                    out.skip = true;
+                } else if meta.path.is_ident("default") {
+                    out.default = true;
+                } else {
+                    return Err(meta.error("unknown msgpack field attribute"));
+                }
+                Ok(())
+            })?;
+            if out.rename.is_some() && out.tag.is_some() {
+                return Err(syn::Error::new_spanned(attr, "a field can't have both `rename` and `tag`"));
+            }
+        }
+        Ok(out)
+    }
+}
@@ -0,0 +1,389 @@
+//! `#[derive(EncodeValue, DecodeValue)]` for the `stuff` crate; enable its
+//! `derive` feature rather than depending on this crate directly.
+
+extern crate proc_macro;
+
+mod attr;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Literal, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, parse_quote, Data, DeriveInput, Fields, Generics, Ident, Member};
+
+use attr::{RenameRule, Tagging};
+
+#[proc_macro_derive(EncodeValue, attributes(msgpack))]
+pub fn derive_encode_value(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_encode(&input).unwrap_or_else(syn::Error::into_compile_error).into()
+}
+
+#[proc_macro_derive(DecodeValue, attributes(msgpack))]
+pub fn derive_decode_value(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_decode(&input).unwrap_or_else(syn::Error::into_compile_error).into()
+}
+
+/// Requires every type parameter to implement `bound`.
+fn add_bounds(generics: &Generics, bound: syn::Path) -> Generics {
+    let mut generics = generics.clone();
+    for param in generics.type_params_mut() {
+        param.bounds.push(parse_quote!(#bound));
+    }
+    generics
+}
+
+/// A field with the key it is written under and its attributes.
+struct FieldSpec {
+    member: Member,
+    /// A reference to the key, as a `&str` or `&u64`.
+    key: TokenStream2,
+    /// The name reported when the field is missing.
+    name: String,
+    attrs: attr::Field
+}
+
+fn field_specs(fields: &Fields, rename_all: Option<RenameRule>, default: bool) -> syn::Result<Vec<FieldSpec>> {
+    let specs = fields
+        .iter()
+        .enumerate()
+        .map(|(i, f)| {
+            let mut attrs = attr::Field::parse(&f.attrs)?;
+            attrs.default |= default;
+            if attrs.unknown && f.ident.is_none() {
+                return Err(syn::Error::new_spanned(f, "`unknown` needs a named field"));
+            }
+            let (member, name) = match &f.ident {
+                Some(ident) => (Member::Named(ident.clone()), ident.to_string()),
+                None => (Member::Unnamed(i.into()), i.to_string())
+            };
+            let (key, name) = match (&attrs.rename, attrs.tag, rename_all) {
+                (_, Some(tag), _) => {
+                    let tag = Literal::u64_suffixed(tag);
+                    (quote!(&#tag), name)
+                },
+                (Some(rename), _, _) => (quote!(#rename), rename.clone()),
+                (None, None, Some(rule)) => {
+                    let key = rule.apply_to_field(&name);
+                    (quote!(#key), key)
+                },
+                (None, None, None) => (quote!(#name), name)
+            };
+            Ok(FieldSpec { member, key, name, attrs })
+        })
+        .collect::<syn::Result<Vec<FieldSpec>>>()?;
+    if let Some(extra) = specs.iter().filter(|s| s.attrs.unknown).nth(1) {
+        return Err(syn::Error::new_spanned(&extra.member, "only one field can collect unknown entries"));
+    }
+    Ok(specs)
+}
+
+/// The key a variant is written under.
+fn variant_key(variant: &syn::Variant, rename_all: Option<RenameRule>) -> syn::Result<String> {
+    let attrs = attr::Variant::parse(&variant.attrs)?;
+    let name = variant.ident.to_string();
+    Ok(match (attrs.rename, rename_all) {
+        (Some(rename), _) => rename,
+        (None, Some(rule)) => rule.apply_to_variant(&name),
+        (None, None) => name
+    })
+}
+
+/// A lone unnamed field is written as its content alone.
+fn is_newtype(fields: &Fields, specs: &[FieldSpec]) -> bool {
+    matches!(fields, Fields::Unnamed(_)) && specs.len() == 1 && !specs[0].attrs.skip
+}
+
+/// Builds `to_value` and `encode` bodies for a set of fields, each read
+/// through the matching expression in `access`. Skipped fields are left out.
+fn encode_fields(fields: &Fields, specs: &[FieldSpec], access: &[TokenStream2]) -> (TokenStream2, TokenStream2) {
+    let kept: Vec<(&FieldSpec, &TokenStream2)> = specs.iter().zip(access).filter(|(s, _)| !s.attrs.skip).collect();
+    let unknown: Option<&TokenStream2> = kept.iter().find(|(s, _)| s.attrs.unknown).map(|(_, a)| *a);
+    let kept: Vec<(&FieldSpec, &TokenStream2)> = kept.into_iter().filter(|(s, _)| !s.attrs.unknown).collect();
+    let access: Vec<&TokenStream2> = kept.iter().map(|(_, a)| *a).collect();
+    let len = kept.len();
+    match fields {
+        Fields::Named(_) => {
+            let keys: Vec<&TokenStream2> = kept.iter().map(|(s, _)| &s.key).collect();
+            let (extend, header, write_unknown) = match unknown {
+                // Unknown entries go in first so that known fields win.
+                Some(unknown) => (
+                    quote!(map.extend((#unknown).iter().map(|(k, v)| (k.clone(), v.clone())));),
+                    quote! {
+                        let known = [#( ::stuff::EncodeValue::to_value(#keys) ),*];
+                        let unknown = ::stuff::__private::unknown_entries(#unknown, &known);
+                        ::stuff::__private::write_map_header(w, #len + unknown.len())?;
+                    },
+                    quote! {
+                        for (k, v) in unknown {
+                            ::stuff::EncodeValue::encode(k, w)?;
+                            ::stuff::EncodeValue::encode(v, w)?;
+                        }
+                    }
+                ),
+                None => (quote!(), quote!(::stuff::__private::write_map_header(w, #len)?;), quote!())
+            };
+            (
+                quote! {{
+                    let mut map = ::stuff::Map::default();
+                    #extend
+                    #( map.insert(::stuff::EncodeValue::to_value(#keys), ::stuff::EncodeValue::to_value(#access)); )*
+                    ::stuff::Value::Map(map)
+                }},
+                quote! {{
+                    #header
+                    #(
+                        ::stuff::EncodeValue::encode(#keys, w)?;
+                        ::stuff::EncodeValue::encode(#access, w)?;
+                    )*
+                    #write_unknown
+                    Ok(())
+                }}
+            )
+        },
+        Fields::Unnamed(_) if is_newtype(fields, specs) => {
+            let inner = access[0];
+            (quote!(::stuff::EncodeValue::to_value(#inner)), quote!(::stuff::EncodeValue::encode(#inner, w)))
+        },
+        Fields::Unnamed(_) => (
+            quote!(::stuff::Value::Array(vec![#( ::stuff::EncodeValue::to_value(#access) ),*])),
+            quote! {{
+                ::stuff::__private::write_array_header(w, #len)?;
+                #( ::stuff::EncodeValue::encode(#access, w)?; )*
+                Ok(())
+            }}
+        ),
+        Fields::Unit => (
+            quote!(::stuff::Value::Null),
+            quote! {{
+                ::std::io::Write::write_all(w, &[0xc0])?;
+                Ok(())
+            }}
+        )
+    }
+}
+
+/// Builds an expression decoding `value` into the struct or variant at `path`.
+fn decode_fields(path: TokenStream2, fields: &Fields, specs: &[FieldSpec]) -> TokenStream2 {
+    let default = quote!(::std::default::Default::default());
+    match fields {
+        Fields::Named(_) => {
+            let inits = specs.iter().filter(|s| !s.attrs.unknown).map(|s| {
+                let member = &s.member;
+                let key = &s.key;
+                let name = &s.name;
+                if s.attrs.skip {
+                    quote!(#member: #default)
+                } else if s.attrs.default {
+                    quote!(#member: ::stuff::__private::field_or_default(&mut map, #key)?)
+                } else {
+                    quote!(#member: ::stuff::__private::field(&mut map, #key, #name)?)
+                }
+            });
+            // Whatever is left once every other field has been taken.
+            let unknown = specs.iter().filter(|s| s.attrs.unknown).map(|s| {
+                let member = &s.member;
+                quote!(#member: map)
+            });
+            quote! {{
+                let mut map = ::stuff::__private::into_map(value)?;
+                Ok(#path { #( #inits, )* #( #unknown, )* })
+            }}
+        },
+        Fields::Unnamed(_) if is_newtype(fields, specs) => quote!(Ok(#path(::stuff::DecodeValue::from_value(value)?))),
+        Fields::Unnamed(_) => {
+            let len = specs.iter().filter(|s| !s.attrs.skip).count();
+            let elements = specs.iter().map(|s| {
+                if s.attrs.skip {
+                    default.clone()
+                } else {
+                    quote!(::stuff::__private::element(&mut items)?)
+                }
+            });
+            quote! {{
+                let mut items = ::stuff::__private::into_array(value, #len)?;
+                Ok(#path(#( #elements ),*))
+            }}
+        },
+        Fields::Unit => quote! {{
+            let _ = value;
+            Ok(#path)
+        }}
+    }
+}
+
+/// A pattern binding each field of a variant, and the bound names; skipped
+/// fields are matched with `_`.
+fn bind_fields(path: TokenStream2, fields: &Fields, specs: &[FieldSpec]) -> (TokenStream2, Vec<TokenStream2>) {
+    let bindings: Vec<TokenStream2> = specs
+        .iter()
+        .enumerate()
+        .map(|(i, s)| {
+            if s.attrs.skip {
+                quote!(_)
+            } else {
+                let ident: Ident = format_ident!("__f{}", i);
+                quote!(#ident)
+            }
+        })
+        .collect();
+    let pattern = match fields {
+        Fields::Named(_) => {
+            let members = specs.iter().map(|s| &s.member);
+            quote!(#path { #( #members: #bindings ),* })
+        },
+        Fields::Unnamed(_) => quote!(#path(#( #bindings ),*)),
+        Fields::Unit => quote!(#path)
+    };
+    (pattern, bindings)
+}
+
+fn expand_encode(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let generics = add_bounds(&input.generics, parse_quote!(::stuff::EncodeValue));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let container = attr::Container::parse(&input.attrs)?;
+    let (to_value, encode) = match &input.data {
+        Data::Struct(s) => {
+            let specs = field_specs(&s.fields, container.rename_all, container.default)?;
+            let access: Vec<TokenStream2> = specs.iter().map(|s| {
+                let member = &s.member;
+                quote!(&self.#member)
+            }).collect();
+            encode_fields(&s.fields, &specs, &access)
+        },
+        Data::Enum(e) => {
+            let mut to_value_arms = Vec::new();
+            let mut encode_arms = Vec::new();
+            for variant in &e.variants {
+                let ident = &variant.ident;
+                let tag = variant_key(variant, container.rename_all)?;
+                let specs = field_specs(&variant.fields, None, false)?;
+                let (pattern, access) = bind_fields(quote!(Self::#ident), &variant.fields, &specs);
+                let (content, encode_content) = encode_fields(&variant.fields, &specs, &access);
+                let unit = matches!(variant.fields, Fields::Unit);
+                let (to_value, encode) = match &container.tagging {
+                    Tagging::External if unit => {
+                        (quote!(::stuff::Value::from(#tag)), quote!(::stuff::EncodeValue::encode(#tag, w)))
+                    },
+                    Tagging::External => (quote!(::stuff::__private::single_entry(#tag, #content)), quote! {{
+                        ::stuff::__private::write_map_header(w, 1)?;
+                        ::stuff::EncodeValue::encode(#tag, w)?;
+                        #encode_content
+                    }}),
+                    Tagging::Adjacent { tag: tag_key, .. } if unit => {
+                        (quote!(::stuff::__private::single_entry(#tag_key, ::stuff::Value::from(#tag))), quote! {{
+                            ::stuff::__private::write_map_header(w, 1)?;
+                            ::stuff::EncodeValue::encode(#tag_key, w)?;
+                            ::stuff::EncodeValue::encode(#tag, w)
+                        }})
+                    },
+                    Tagging::Adjacent { tag: tag_key, content: content_key } => (
+                        quote!(::stuff::__private::adjacent_entries(#tag_key, #tag, #content_key, #content)),
+                        quote! {{
+                            ::stuff::__private::write_map_header(w, 2)?;
+                            ::stuff::EncodeValue::encode(#tag_key, w)?;
+                            ::stuff::EncodeValue::encode(#tag, w)?;
+                            ::stuff::EncodeValue::encode(#content_key, w)?;
+                            #encode_content
+                        }}
+                    ),
+                    Tagging::Untagged => (content, encode_content)
+                };
+                to_value_arms.push(quote!(#pattern => #to_value));
+                encode_arms.push(quote!(#pattern => #encode));
+            }
+            (
+                quote!(match self { #( #to_value_arms, )* }),
+                quote!(match self { #( #encode_arms, )* })
+            )
+        },
+        Data::Union(_) => return Err(syn::Error::new_spanned(name, "EncodeValue can't be derived for unions"))
+    };
+    Ok(quote! {
+        impl #impl_generics ::stuff::EncodeValue for #name #ty_generics #where_clause {
+            fn to_value(&self) -> ::stuff::Value {
+                #to_value
+            }
+
+            fn encode<__W: ::std::io::Write + ?Sized>(&self, w: &mut __W) -> ::stuff::Result<()> {
+                #encode
+            }
+        }
+    })
+}
+
+fn expand_decode(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let generics = add_bounds(&input.generics, parse_quote!(::stuff::DecodeValue));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let container = attr::Container::parse(&input.attrs)?;
+    let body = match &input.data {
+        Data::Struct(s) => decode_fields(quote!(Self), &s.fields, &field_specs(&s.fields, container.rename_all, container.default)?),
+        Data::Enum(e) if matches!(container.tagging, Tagging::Untagged) => {
+            let mut attempts = Vec::new();
+            for variant in &e.variants {
+                let ident = &variant.ident;
+                let decode = decode_fields(quote!(Self::#ident), &variant.fields, &field_specs(&variant.fields, None, false)?);
+                attempts.push(if let Fields::Unit = variant.fields {
+                    quote! {
+                        if let ::stuff::Value::Null = value {
+                            return Ok(Self::#ident);
+                        }
+                    }
+                } else {
+                    quote! {
+                        #[allow(clippy::redundant_closure_call)]
+                        let attempt = (|| -> ::stuff::Result<Self> {
+                            let value = value.clone();
+                            #decode
+                        })();
+                        if attempt.is_ok() {
+                            return attempt;
+                        }
+                    }
+                });
+            }
+            let name = name.to_string();
+            quote! {{
+                #( #attempts )*
+                Err(::stuff::__private::no_variant_matched(#name, &value))
+            }}
+        },
+        Data::Enum(e) => {
+            let mut arms = Vec::new();
+            for variant in &e.variants {
+                let ident = &variant.ident;
+                let tag = variant_key(variant, container.rename_all)?;
+                let decode = decode_fields(quote!(Self::#ident), &variant.fields, &field_specs(&variant.fields, None, false)?);
+                arms.push(if let Fields::Unit = variant.fields {
+                    quote!(#tag => Ok(Self::#ident))
+                } else {
+                    quote!(#tag => {
+                        let value = ::stuff::__private::content(content)?;
+                        #decode
+                    })
+                });
+            }
+            let split = match &container.tagging {
+                Tagging::Adjacent { tag, content } => quote!(::stuff::__private::adjacent(value, #tag, #content)?),
+                _ => quote!(::stuff::__private::variant(value)?)
+            };
+            quote! {{
+                let (variant, content) = #split;
+                match variant.as_str() {
+                    #( #arms, )*
+                    _ => Err(::stuff::__private::unknown_variant(variant))
+                }
+            }}
+        },
+        Data::Union(_) => return Err(syn::Error::new_spanned(name, "DecodeValue can't be derived for unions"))
+    };
+    Ok(quote! {
+        impl #impl_generics ::stuff::DecodeValue for #name #ty_generics #where_clause {
+            fn from_value(value: ::stuff::Value) -> ::stuff::Result<Self> {
+                #body
+            }
+        }
+    })
+}